@@ -0,0 +1,388 @@
+//! Operator-only endpoints under `/api/admin`, guarded by `ADMIN_TOKEN`.
+
+use crate::config::DeniedStatus;
+use crate::{
+    client_ip, find_file, ids, listing_order, stored_path, AppState, FileInfo, UPLOAD_DIR,
+};
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Version of the export format. Bump when `MetadataSnapshot` changes
+/// incompatibly; imports of any other version are refused.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Checks the `Authorization: Bearer <ADMIN_TOKEN>` header. The admin API is
+/// hidden entirely (`404`) when no token is configured.
+pub fn require_admin(req: &HttpRequest, data: &AppState) -> Result<(), HttpResponse> {
+    let Some(expected) = data.config.admin_token.as_deref() else {
+        return Err(HttpResponse::NotFound().json(serde_json::json!({"error": "Not found"})));
+    };
+    let provided = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    if constant_time_eq(provided.as_bytes(), expected.as_bytes()) {
+        Ok(())
+    } else {
+        Err(HttpResponse::Unauthorized()
+            .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+            .json(serde_json::json!({"error": "Admin token required"})))
+    }
+}
+
+/// Lets through the client that uploaded `info` (see `client_ip`) or a
+/// caller with the admin token. Anyone else is denied as
+/// `DENIED_FILE_STATUS` says, with `action` naming what only the uploader
+/// may do. Every owner-restricted endpoint goes through here, so none of
+/// them can be used to probe for ids.
+pub fn require_owner(
+    req: &HttpRequest,
+    data: &AppState,
+    info: &FileInfo,
+    action: &str,
+) -> Result<(), HttpResponse> {
+    if info.owner.as_deref() == Some(client_ip(req).as_str()) {
+        return Ok(());
+    }
+    require_admin(req, data).map_err(|res| {
+        match (data.config.denied_file_status, res.status().as_u16()) {
+            (DeniedStatus::NotFound, _) => {
+                HttpResponse::NotFound().json(serde_json::json!({"error": "File not found"}))
+            }
+            (DeniedStatus::Forbidden, 404) => HttpResponse::Forbidden().json(
+                serde_json::json!({"error": format!("Only the uploader may {} this file", action)}),
+            ),
+            (DeniedStatus::Forbidden, _) => res,
+        }
+    })
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[derive(Serialize, Deserialize)]
+struct MetadataSnapshot {
+    #[serde(alias = "schemaVersion")]
+    schema_version: u32,
+    #[serde(alias = "exportedAt")]
+    exported_at: DateTime<Utc>,
+    files: Vec<SnapshotFile>,
+}
+
+/// A record as exported: unlike listings, it includes the owner, which
+/// quotas need after a restore.
+#[derive(Serialize, Deserialize)]
+struct SnapshotFile {
+    #[serde(flatten)]
+    file: FileInfo,
+    #[serde(default)]
+    owner: Option<String>,
+}
+
+impl From<&FileInfo> for SnapshotFile {
+    fn from(file: &FileInfo) -> Self {
+        SnapshotFile {
+            file: file.clone(),
+            owner: file.owner.clone(),
+        }
+    }
+}
+
+impl From<SnapshotFile> for FileInfo {
+    fn from(snapshot: SnapshotFile) -> Self {
+        FileInfo {
+            owner: snapshot.owner,
+            ..snapshot.file
+        }
+    }
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum ImportMode {
+    /// Upsert imported records by file name, keeping everything else.
+    #[default]
+    Merge,
+    /// Discard the current records and use the imported ones.
+    Replace,
+}
+
+#[derive(Deserialize)]
+pub struct ImportQuery {
+    #[serde(default)]
+    mode: ImportMode,
+}
+
+pub async fn export_metadata(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
+    if let Err(res) = require_admin(&req, &data) {
+        return res;
+    }
+    let snapshot = MetadataSnapshot {
+        schema_version: SCHEMA_VERSION,
+        exported_at: Utc::now(),
+        files: data.files.lock().unwrap().iter().map(Into::into).collect(),
+    };
+    HttpResponse::Ok()
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"metadata-{}.json\"",
+                snapshot.exported_at.format("%Y%m%dT%H%M%SZ")
+            ),
+        ))
+        .json(snapshot)
+}
+
+/// Restores metadata from an export. File contents are never touched:
+/// records whose file is not present in the upload directory are skipped
+/// and reported, so blobs must be copied over before importing.
+pub async fn import_metadata(
+    req: HttpRequest,
+    query: web::Query<ImportQuery>,
+    body: web::Bytes,
+    data: web::Data<AppState>,
+) -> HttpResponse {
+    if let Err(res) = require_admin(&req, &data) {
+        return res;
+    }
+
+    let value: serde_json::Value = match serde_json::from_slice(&body) {
+        Ok(v) => v,
+        Err(e) => {
+            return HttpResponse::BadRequest()
+                .json(serde_json::json!({"error": format!("Invalid JSON: {}", e)}));
+        }
+    };
+    match value
+        .get("schema_version")
+        .or_else(|| value.get("schemaVersion"))
+        .and_then(|v| v.as_u64())
+    {
+        Some(v) if v == u64::from(SCHEMA_VERSION) => {}
+        other => {
+            return HttpResponse::UnprocessableEntity().json(serde_json::json!({
+                "error": format!(
+                    "Unsupported schema_version {} (expected {})",
+                    other.map_or("missing".to_string(), |v| v.to_string()),
+                    SCHEMA_VERSION
+                )
+            }));
+        }
+    }
+    let snapshot: MetadataSnapshot = match serde_json::from_value(value) {
+        Ok(s) => s,
+        Err(e) => {
+            return HttpResponse::UnprocessableEntity()
+                .json(serde_json::json!({"error": format!("Invalid snapshot: {}", e)}));
+        }
+    };
+
+    let (present, missing): (Vec<FileInfo>, Vec<FileInfo>) = snapshot
+        .files
+        .into_iter()
+        .map(FileInfo::from)
+        .partition(|f| stored_path(&data.config, &f.name).is_some());
+
+    let imported = present.len();
+    for record in &present {
+        ids::save(&record.name, &record.id);
+    }
+    let mut files = data.files.lock().unwrap();
+    match query.mode {
+        ImportMode::Replace => *files = present,
+        ImportMode::Merge => {
+            for record in present {
+                files.retain(|f| f.name != record.name && f.id != record.id);
+                files.push(record);
+            }
+        }
+    }
+    files.sort_by(listing_order);
+    data.tombstones.reset();
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "imported": imported,
+        "skipped_missing": missing.iter().map(|f| &f.name).collect::<Vec<_>>(),
+        "total": files.len(),
+    }))
+}
+
+/// `GET /api/files/{id}/location`: where a file's bytes live, for debugging
+/// files that fail to read. There is a single backend, the local upload
+/// directory, and the storage key is the stored file name.
+pub async fn file_location(
+    req: HttpRequest,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> HttpResponse {
+    if let Err(res) = require_admin(&req, &data) {
+        return res;
+    }
+    let Some(info) = find_file(&data, &path.into_inner()) else {
+        return HttpResponse::NotFound().json(serde_json::json!({"error": "File not found"}));
+    };
+    let root = std::fs::canonicalize(UPLOAD_DIR).unwrap_or_else(|_| UPLOAD_DIR.into());
+    let file_path = root.join(&info.name);
+    let is_symlink =
+        std::fs::symlink_metadata(&file_path).is_ok_and(|m| m.file_type().is_symlink());
+    HttpResponse::Ok().json(serde_json::json!({
+        "id": info.id,
+        "backend": "local",
+        "root": root,
+        "key": info.name,
+        "path": file_path,
+        "symlink_target": is_symlink.then(|| std::fs::read_link(&file_path).ok()).flatten(),
+        "readable": stored_path(&data.config, &info.name).is_some(),
+    }))
+}
+
+/// `GET /api/admin/usage`: storage used per client, largest first. Files
+/// found on disk at startup have no known owner and are grouped under `null`.
+pub async fn client_usage(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
+    if let Err(res) = require_admin(&req, &data) {
+        return res;
+    }
+    let mut usage: HashMap<Option<String>, (usize, u64)> = HashMap::new();
+    for f in data.files.lock().unwrap().iter() {
+        let entry = usage.entry(f.owner.clone()).or_default();
+        entry.0 += 1;
+        entry.1 += f.size;
+    }
+    let mut clients: Vec<_> = usage.into_iter().collect();
+    clients.sort_by(|(a, (_, a_bytes)), (b, (_, b_bytes))| {
+        b_bytes.cmp(a_bytes).then_with(|| a.cmp(b))
+    });
+    HttpResponse::Ok().json(serde_json::json!({
+        "quota": data.config.client_quota,
+        "clients": clients
+            .into_iter()
+            .map(|(client, (files, bytes))| serde_json::json!({
+                "client": client,
+                "files": files,
+                "bytes": bytes,
+            }))
+            .collect::<Vec<_>>(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{add_file, config, path, record, state, unique};
+    use crate::Config;
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::App;
+
+    #[actix_web::test]
+    async fn snapshots_keep_owners() {
+        let data = state(Config {
+            admin_token: Some("secret".to_string()),
+            ..config()
+        });
+        let app = init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/api/admin/export", web::get().to(export_metadata))
+                .route("/api/admin/import", web::post().to(import_metadata)),
+        )
+        .await;
+        let name = unique("a.txt");
+        let info = add_file(&data, &name, b"abc", Some("1.2.3.4"));
+
+        let req = TestRequest::get()
+            .uri("/api/admin/export")
+            .insert_header((header::AUTHORIZATION, "Bearer secret"))
+            .to_request();
+        let snapshot = read_body(call_service(&app, req).await).await;
+        let value: serde_json::Value = serde_json::from_slice(&snapshot).unwrap();
+        assert_eq!(value["files"][0]["owner"], "1.2.3.4");
+
+        data.files.lock().unwrap().clear();
+        let req = TestRequest::post()
+            .uri("/api/admin/import?mode=replace")
+            .insert_header((header::AUTHORIZATION, "Bearer secret"))
+            .set_payload(snapshot)
+            .to_request();
+        assert_eq!(call_service(&app, req).await.status(), 200);
+        let restored = record(&data, &info.id).unwrap();
+        assert_eq!(restored.owner.as_deref(), Some("1.2.3.4"));
+        assert_eq!(restored.checksum, info.checksum);
+
+        std::fs::remove_file(path(&name)).unwrap();
+        ids::remove(&name);
+    }
+
+    #[actix_web::test]
+    async fn requires_the_admin_token() {
+        let data = state(Config {
+            admin_token: Some("secret".to_string()),
+            ..config()
+        });
+        let app = init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/api/admin/export", web::get().to(export_metadata)),
+        )
+        .await;
+        let req = TestRequest::get()
+            .uri("/api/admin/export")
+            .insert_header((header::AUTHORIZATION, "Bearer wrong"))
+            .to_request();
+        assert_eq!(call_service(&app, req).await.status(), 401);
+
+        let hidden = state(config());
+        assert_eq!(
+            require_admin(&TestRequest::default().to_http_request(), &hidden)
+                .unwrap_err()
+                .status(),
+            404
+        );
+    }
+
+    #[test]
+    fn only_owners_and_admins_pass_the_owner_check() {
+        let info = FileInfo {
+            owner: Some("1.2.3.4".to_string()),
+            ..Default::default()
+        };
+        let from = |peer: &str, token: Option<&str>| {
+            let mut req =
+                TestRequest::default().peer_addr(format!("{}:4000", peer).parse().unwrap());
+            if let Some(token) = token {
+                req = req.insert_header((header::AUTHORIZATION, format!("Bearer {}", token)));
+            }
+            req.to_http_request()
+        };
+        let status = |data: &AppState, req: &HttpRequest| {
+            require_owner(req, data, &info, "change").map_err(|res| res.status().as_u16())
+        };
+
+        let hidden = state(Config {
+            admin_token: Some("secret".to_string()),
+            ..config()
+        });
+        assert_eq!(status(&hidden, &from("1.2.3.4", None)), Ok(()));
+        assert_eq!(status(&hidden, &from("5.6.7.8", Some("secret"))), Ok(()));
+        assert_eq!(status(&hidden, &from("5.6.7.8", None)), Err(404));
+        assert_eq!(status(&hidden, &from("5.6.7.8", Some("wrong"))), Err(404));
+
+        let open = state(Config {
+            admin_token: Some("secret".to_string()),
+            denied_file_status: DeniedStatus::Forbidden,
+            ..config()
+        });
+        assert_eq!(status(&open, &from("5.6.7.8", None)), Err(401));
+        let no_admin = state(Config {
+            denied_file_status: DeniedStatus::Forbidden,
+            ..config()
+        });
+        assert_eq!(status(&no_admin, &from("5.6.7.8", None)), Err(403));
+    }
+}
@@ -0,0 +1,313 @@
+//! `POST /api/files/{id}/append`: streams the request body onto the end of
+//! an existing file, for log shipping and incremental data.
+//!
+//! Only the client that uploaded the file (see `client_ip`) or a caller with
+//! the admin token may append; anyone else gets `DENIED_FILE_STATUS`.
+//! Appends to the same file are serialized; the size, modification time and
+//! checksum are updated once the body is in, and derived data
+//! (precompressed variants, text counts) is dropped. A failed append
+//! truncates the file back to its previous length.
+
+use crate::admin::require_owner;
+use crate::hashing::hash_file;
+use crate::{
+    client_usage, is_storage_full, next_before, precompress, quota_exceeded, stored_path,
+    upload_deadline, write_with_retry, AppState, MAX_FILE_SIZE, UPLOAD_DIR,
+};
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OwnedMutexGuard;
+
+/// One async lock per file id, so concurrent appends to a file queue up
+/// instead of interleaving.
+pub struct AppendLocks {
+    locks: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl AppendLocks {
+    pub fn new() -> Self {
+        AppendLocks {
+            locks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn lock(&self, id: &str) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.locks.lock().unwrap();
+            locks.retain(|_, l| Arc::strong_count(l) > 1);
+            locks.entry(id.to_string()).or_default().clone()
+        };
+        lock.lock_owned().await
+    }
+}
+
+pub async fn append_file(
+    req: HttpRequest,
+    path: web::Path<String>,
+    mut payload: web::Payload,
+    data: web::Data<AppState>,
+) -> HttpResponse {
+    let id = path.into_inner();
+    let deadline = upload_deadline(&data.config);
+    if let Err(res) = data.storage.require_writable() {
+        return res;
+    }
+    if data.config.policy_url.is_some() {
+        return HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "Appends are disabled while an upload policy service is configured"
+        }));
+    }
+
+    let _guard = data.appends.lock(&id).await;
+    let Some(info) = data
+        .files
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|f| f.id == id && f.is_ready())
+        .cloned()
+    else {
+        return HttpResponse::NotFound().json(serde_json::json!({"error": "File not found"}));
+    };
+    if let Err(res) = require_owner(&req, &data, &info, "append to") {
+        return res;
+    }
+    // Re-hashing after the append would certify corrupted bytes as clean.
+    if info.checksum_mismatch.is_some() {
+        return HttpResponse::Conflict().json(serde_json::json!({
+            "error": "This file failed its integrity check and cannot be appended to"
+        }));
+    }
+    let filepath = PathBuf::from(UPLOAD_DIR).join(&info.name);
+    // The target of a symlink may be shared with other files or live
+    // outside the upload directory.
+    if stored_path(&data.config, &info.name).is_none()
+        || std::fs::symlink_metadata(&filepath).is_ok_and(|m| m.file_type().is_symlink())
+    {
+        return HttpResponse::Conflict()
+            .json(serde_json::json!({"error": "This file cannot be appended to"}));
+    }
+
+    let mut file = match OpenOptions::new().append(true).open(&filepath) {
+        Ok(f) => f,
+        Err(e) => {
+            return HttpResponse::InternalServerError()
+                .json(serde_json::json!({"error": format!("Failed to open file: {}", e)}));
+        }
+    };
+    let original = match file.metadata() {
+        Ok(m) => m.len(),
+        Err(e) => {
+            return HttpResponse::InternalServerError()
+                .json(serde_json::json!({"error": format!("Failed to open file: {}", e)}));
+        }
+    };
+    let quota = data.config.client_quota;
+    let owner = info.owner.clone().unwrap_or_default();
+    let used = quota.map_or(0, |_| {
+        client_usage(&data.files.lock().unwrap(), &owner, Some(&info.name))
+    });
+
+    let mut total = original;
+    let failed = loop {
+        let chunk = match next_before(&mut payload, deadline).await {
+            Ok(Some(Ok(chunk))) => chunk,
+            Ok(None) => break None,
+            Ok(Some(Err(e))) => {
+                break Some(
+                    HttpResponse::BadRequest()
+                        .json(serde_json::json!({"error": format!("Upload interrupted: {}", e)})),
+                );
+            }
+            Err(res) => break Some(res),
+        };
+        total += chunk.len() as u64;
+        if total > MAX_FILE_SIZE as u64 {
+            break Some(
+                HttpResponse::PayloadTooLarge()
+                    .json(serde_json::json!({"error": "File too large (max 10 GB)"})),
+            );
+        }
+        if let Some(quota) = quota.filter(|&q| info.owner.is_some() && used + total > q) {
+            break Some(quota_exceeded(quota));
+        }
+        if let Err(e) = write_with_retry(&mut file, &chunk, &data.config).await {
+            break Some(if is_storage_full(&e) {
+                HttpResponse::InsufficientStorage()
+                    .json(serde_json::json!({"error": "Not enough storage space"}))
+            } else {
+                HttpResponse::InternalServerError()
+                    .json(serde_json::json!({"error": format!("Write error: {}", e)}))
+            });
+        }
+    };
+    if let Some(res) = failed {
+        let _ = file.set_len(original);
+        return res;
+    }
+    drop(file);
+
+    let algorithm = info
+        .checksum
+        .as_ref()
+        .map_or(data.config.hash_algorithm, |c| c.algorithm);
+    let checksum = web::block(move || hash_file(&filepath, algorithm))
+        .await
+        .ok()
+        .and_then(Result::ok);
+    if total != original {
+        precompress::remove(&info.name);
+    }
+
+    let mut files = data.files.lock().unwrap();
+    let Some(record) = files.iter_mut().find(|f| f.id == id) else {
+        return HttpResponse::NotFound().json(serde_json::json!({"error": "File not found"}));
+    };
+    record.size = total;
+    record.modified_at = Some(Utc::now());
+    record.checksum = checksum;
+    record.variant_bytes = None;
+    record.text_stats = None;
+    record.last_verified = None;
+    let mut res = HttpResponse::Ok();
+    if let Some(checksum) = &record.checksum {
+        res.insert_header((header::ETAG, format!("\"{}\"", checksum.digest)));
+    }
+    res.json(serde_json::json!({
+        "success": true,
+        "appended": total - original,
+        "file": record,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DeniedStatus;
+    use crate::testing;
+    use actix_web::{test, App};
+
+    async fn append(
+        data: &web::Data<AppState>,
+        id: &str,
+        peer: &str,
+        headers: &[(&str, &str)],
+        body: &'static str,
+    ) -> (u16, serde_json::Value) {
+        let app = test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/api/files/{id}/append", web::post().to(append_file)),
+        )
+        .await;
+        let mut req = test::TestRequest::post()
+            .uri(&format!("/api/files/{}/append", id))
+            .peer_addr(format!("{}:4000", peer).parse().unwrap())
+            .set_payload(body);
+        for &header in headers {
+            req = req.insert_header(header);
+        }
+        let res = test::call_service(&app, req.to_request()).await;
+        let status = res.status().as_u16();
+        let body = test::read_body(res).await;
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    #[actix_web::test]
+    async fn owner_appends_and_record_is_updated() {
+        let data = testing::state(testing::config());
+        let name = testing::unique("log.txt");
+        let info = testing::add_file(&data, &name, b"one\n", Some("1.2.3.4"));
+
+        let (status, body) = append(&data, &info.id, "1.2.3.4", &[], "two\n").await;
+        assert_eq!(status, 200);
+        assert_eq!(body["appended"], 4);
+        assert_eq!(std::fs::read(testing::path(&name)).unwrap(), b"one\ntwo\n");
+        let record = testing::record(&data, &info.id).unwrap();
+        assert_eq!(record.size, 8);
+        assert_ne!(record.checksum, info.checksum);
+        assert!(record.modified_at.is_some());
+        std::fs::remove_file(testing::path(&name)).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn forged_forwarded_for_is_not_the_owner() {
+        let data = testing::state(testing::config());
+        let name = testing::unique("log.txt");
+        let info = testing::add_file(&data, &name, b"one\n", Some("1.2.3.4"));
+
+        let forged = [("X-Forwarded-For", "1.2.3.4")];
+        let (status, _) = append(&data, &info.id, "5.6.7.8", &forged, "evil").await;
+        assert_eq!(status, 404);
+        assert_eq!(std::fs::read(testing::path(&name)).unwrap(), b"one\n");
+        std::fs::remove_file(testing::path(&name)).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn others_are_denied_unless_admin() {
+        let mut config = testing::config();
+        config.admin_token = Some("secret".to_string());
+        config.denied_file_status = DeniedStatus::Forbidden;
+        let data = testing::state(config);
+        let name = testing::unique("log.txt");
+        let info = testing::add_file(&data, &name, b"one\n", Some("1.2.3.4"));
+
+        let (status, _) = append(&data, &info.id, "5.6.7.8", &[], "x").await;
+        assert_eq!(status, 401);
+        let admin = [("Authorization", "Bearer secret")];
+        let (status, _) = append(&data, &info.id, "5.6.7.8", &admin, "x").await;
+        assert_eq!(status, 200);
+        assert_eq!(std::fs::read(testing::path(&name)).unwrap(), b"one\nx");
+        std::fs::remove_file(testing::path(&name)).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn denials_look_like_missing_files_by_default() {
+        let mut config = testing::config();
+        config.admin_token = Some("secret".to_string());
+        let data = testing::state(config);
+        let name = testing::unique("log.txt");
+        let info = testing::add_file(&data, &name, b"one\n", Some("1.2.3.4"));
+        let (status, body) = append(&data, &info.id, "5.6.7.8", &[], "x").await;
+        assert_eq!(status, 404);
+        assert_eq!(body["error"], "File not found");
+
+        let mut config = testing::config();
+        config.denied_file_status = DeniedStatus::Forbidden;
+        let data = testing::state(config);
+        data.files.lock().unwrap().push(info.clone());
+        let (status, _) = append(&data, &info.id, "5.6.7.8", &[], "x").await;
+        assert_eq!(status, 403);
+        assert_eq!(std::fs::read(testing::path(&name)).unwrap(), b"one\n");
+        std::fs::remove_file(testing::path(&name)).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn flagged_files_are_not_appended_to() {
+        let data = testing::state(testing::config());
+        let name = testing::unique("log.txt");
+        let info = testing::add_file(&data, &name, b"one\n", Some("1.2.3.4"));
+        let flagged = Some(Utc::now());
+        data.files.lock().unwrap()[0].checksum_mismatch = flagged;
+
+        let (status, _) = append(&data, &info.id, "1.2.3.4", &[], "two\n").await;
+        assert_eq!(status, 409);
+        assert_eq!(std::fs::read(testing::path(&name)).unwrap(), b"one\n");
+        let record = testing::record(&data, &info.id).unwrap();
+        assert_eq!(record.checksum_mismatch, flagged);
+        assert_eq!(record.checksum, info.checksum);
+        std::fs::remove_file(testing::path(&name)).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn unknown_file_is_not_found() {
+        let data = testing::state(testing::config());
+        let (status, _) = append(&data, "missing", "1.2.3.4", &[], "x").await;
+        assert_eq!(status, 404);
+    }
+}
@@ -0,0 +1,120 @@
+//! Optional camelCase field names in JSON responses.
+//!
+//! Handlers always produce snake_case. When camelCase is selected, either by
+//! `JSON_CASE=camel` or per request with an `Accept` parameter such as
+//! `Accept: application/json; case=camel`, this middleware rewrites the keys
+//! of JSON response bodies. File downloads are never touched, even when the
+//! stored file is JSON.
+
+use crate::AppState;
+use actix_web::body::{self, EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::web;
+use serde_json::{Map, Value};
+use std::str::FromStr;
+
+/// Routes that stream stored files; their bodies are user content.
+const FILE_ROUTES: [&str; 2] = ["/api/download/", "/api/signed/"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JsonCase {
+    Snake,
+    Camel,
+}
+
+impl FromStr for JsonCase {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "snake" | "snake_case" => Ok(JsonCase::Snake),
+            "camel" | "camelcase" => Ok(JsonCase::Camel),
+            other => Err(format!(
+                "unknown JSON case '{}' (expected snake or camel)",
+                other
+            )),
+        }
+    }
+}
+
+/// The `case=` parameter of any media range in `Accept`.
+fn requested(req: &ServiceRequest) -> Option<JsonCase> {
+    let accept = req.headers().get(header::ACCEPT)?.to_str().ok()?;
+    accept
+        .split(',')
+        .flat_map(|range| range.split(';').skip(1))
+        .find_map(|param| {
+            let (key, value) = param.split_once('=')?;
+            (key.trim().eq_ignore_ascii_case("case"))
+                .then(|| value.trim().trim_matches('"').parse().ok())
+                .flatten()
+        })
+}
+
+pub async fn apply<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, actix_web::Error> {
+    let default = req
+        .app_data::<web::Data<AppState>>()
+        .map_or(JsonCase::Snake, |data| data.config.json_case);
+    let case = requested(&req).unwrap_or(default);
+    let download = FILE_ROUTES.iter().any(|p| req.path().starts_with(p));
+
+    let mut res = next.call(req).await?;
+    let is_json = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    if download || !is_json {
+        return Ok(res.map_into_left_body());
+    }
+    res.headers_mut()
+        .append(header::VARY, HeaderValue::from_static("accept"));
+    if case == JsonCase::Snake {
+        return Ok(res.map_into_left_body());
+    }
+
+    let (req, res) = res.into_parts();
+    let (res, body) = res.into_parts();
+    let bytes = body::to_bytes(body)
+        .await
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.into()))?;
+    let bytes = match serde_json::from_slice::<Value>(&bytes) {
+        Ok(value) => serde_json::to_vec(&camelize(value)).unwrap_or_else(|_| bytes.to_vec()),
+        Err(_) => bytes.to_vec(),
+    };
+    let res = res.set_body(bytes).map_into_boxed_body();
+    Ok(ServiceResponse::new(req, res).map_into_right_body())
+}
+
+fn camelize(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(k, v)| (to_camel(&k), camelize(v)))
+                .collect::<Map<_, _>>(),
+        ),
+        Value::Array(items) => Value::Array(items.into_iter().map(camelize).collect()),
+        other => other,
+    }
+}
+
+fn to_camel(key: &str) -> String {
+    let mut out = String::with_capacity(key.len());
+    let mut upper = false;
+    for c in key.chars() {
+        if c == '_' && !out.is_empty() {
+            upper = true;
+        } else if upper {
+            out.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
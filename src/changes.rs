@@ -0,0 +1,191 @@
+//! `GET /api/changes?since=`: what changed since a client last synced, for
+//! incremental mirroring without re-listing everything.
+//!
+//! Uploads, replacements and appends are found from the records themselves;
+//! placeholders show up once their upload completes.
+//! Deletions leave a tombstone (id, name, time) that is kept for
+//! `TOMBSTONE_RETENTION_SECS` and never shown in listings. A cursor older
+//! than what the tombstones still cover (the window, a restart, a metadata
+//! import) gets `410 Gone`, and the client has to resync from `/api/files`.
+
+use crate::AppState;
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Upper bound on stored tombstones, whatever the retention window.
+const MAX_TOMBSTONES: usize = 100_000;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Tombstone {
+    id: String,
+    name: String,
+    deleted_at: DateTime<Utc>,
+}
+
+pub struct Tombstones {
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    /// Oldest first.
+    entries: VecDeque<Tombstone>,
+    /// Deletions from this point on are all in `entries`.
+    complete_since: DateTime<Utc>,
+}
+
+impl Inner {
+    fn purge(&mut self, retention: Duration, now: DateTime<Utc>) {
+        let cutoff = chrono::Duration::from_std(retention)
+            .ok()
+            .and_then(|r| now.checked_sub_signed(r))
+            .unwrap_or(DateTime::<Utc>::MIN_UTC);
+        while let Some(oldest) = self.entries.front() {
+            if oldest.deleted_at >= cutoff && self.entries.len() <= MAX_TOMBSTONES {
+                break;
+            }
+            self.complete_since = self.complete_since.max(oldest.deleted_at);
+            self.entries.pop_front();
+        }
+        self.complete_since = self.complete_since.max(cutoff);
+    }
+}
+
+impl Tombstones {
+    pub fn new() -> Self {
+        Tombstones {
+            inner: Mutex::new(Inner {
+                entries: VecDeque::new(),
+                complete_since: Utc::now(),
+            }),
+        }
+    }
+
+    /// Records the deletion of a file. Call with the files lock held, so a
+    /// concurrent `changes` call sees either the record or its tombstone.
+    pub fn record(&self, retention: Duration, id: String, name: String) {
+        let now = Utc::now();
+        let mut inner = self.inner.lock().unwrap();
+        inner.entries.push_back(Tombstone {
+            id,
+            name,
+            deleted_at: now,
+        });
+        inner.purge(retention, now);
+    }
+
+    /// Forgets all tombstones, for when records were replaced wholesale and
+    /// deletions cannot be told apart.
+    pub fn reset(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.entries.clear();
+        inner.complete_since = Utc::now();
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ChangesQuery {
+    /// The `cursor` of a previous response; omitted for a full snapshot.
+    since: Option<DateTime<Utc>>,
+}
+
+/// `{cursor, files, deleted}`: records uploaded or modified at or after
+/// `since`, and tombstones of files deleted since then. Passing `cursor` back
+/// as `since` picks up where this response left off; an entry may show up in
+/// two consecutive responses, never in none.
+pub async fn changes(query: web::Query<ChangesQuery>, data: web::Data<AppState>) -> HttpResponse {
+    let files = data.files.lock().unwrap();
+    let mut tombstones = data.tombstones.inner.lock().unwrap();
+    let cursor = Utc::now();
+    tombstones.purge(data.config.tombstone_retention, cursor);
+
+    let Some(since) = query.since else {
+        let ready: Vec<_> = files.iter().filter(|f| f.is_ready()).collect();
+        return HttpResponse::Ok().json(serde_json::json!({
+            "cursor": cursor,
+            "files": ready,
+            "deleted": [],
+        }));
+    };
+    if since < tombstones.complete_since {
+        return HttpResponse::Gone().json(serde_json::json!({
+            "error": "Deletions since this cursor are no longer known; resync from /api/files",
+            "oldest_cursor": tombstones.complete_since,
+        }));
+    }
+    let changed: Vec<_> = files
+        .iter()
+        .filter(|f| f.is_ready())
+        .filter(|f| f.uploaded_at.max(f.modified_at.unwrap_or(f.uploaded_at)) >= since)
+        .collect();
+    let deleted: Vec<_> = tombstones
+        .entries
+        .iter()
+        .filter(|t| t.deleted_at >= since)
+        .collect();
+    HttpResponse::Ok().json(serde_json::json!({
+        "cursor": cursor,
+        "files": changed,
+        "deleted": deleted,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{call, config, json, remove, state, unique, upload};
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn expired_tombstones_move_the_window() {
+        let tombstones = Tombstones::new();
+        let hour = Duration::from_secs(3600);
+        tombstones.record(hour, "1".to_string(), "a.txt".to_string());
+        let mut inner = tombstones.inner.lock().unwrap();
+        let started = inner.complete_since;
+        inner.purge(hour, Utc::now());
+        assert_eq!(inner.entries.len(), 1);
+        assert_eq!(inner.complete_since, started);
+
+        let later = Utc::now() + chrono::Duration::hours(2);
+        inner.purge(hour, later);
+        assert!(inner.entries.is_empty());
+        assert_eq!(inner.complete_since, later - chrono::Duration::hours(1));
+    }
+
+    #[actix_web::test]
+    async fn reports_changes_since_a_cursor() {
+        let data = state(config());
+        let get = |since: Option<&str>| {
+            let uri = match since {
+                Some(since) => format!("/api/changes?since={}", since),
+                None => "/api/changes".to_string(),
+            };
+            call(&data, TestRequest::get().uri(&uri))
+        };
+        let (old, new) = (unique("old.txt"), unique("new.txt"));
+        let res = json(call(&data, upload(&old, "old")).await).await;
+        let old_id = res["files"][0]["id"].as_str().unwrap().to_string();
+        let res = json(get(None).await).await;
+        assert_eq!(res["files"][0]["name"], old.as_str());
+        let cursor = res["cursor"].as_str().unwrap().to_string();
+
+        assert_eq!(call(&data, upload(&new, "new")).await.status(), 200);
+        let delete = TestRequest::delete().uri(&format!("/api/files/{}", old_id));
+        assert_eq!(call(&data, delete).await.status(), 200);
+        let res = json(get(Some(&cursor)).await).await;
+        let files = res["files"].as_array().unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0]["name"], new.as_str());
+        assert_eq!(res["deleted"][0]["id"], old_id.as_str());
+        assert_eq!(res["deleted"][0]["name"], old.as_str());
+
+        let res = get(Some("2000-01-01T00:00:00Z")).await;
+        assert_eq!(res.status(), 410);
+        remove(&old);
+        remove(&new);
+    }
+}
@@ -0,0 +1,166 @@
+//! `GET /api/files/compare`: byte identity and, for text, a unified diff.
+
+use crate::textstats::is_text;
+use crate::{find_file, stored_path, AppState, FileInfo};
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+use similar::TextDiff;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+#[derive(Deserialize)]
+pub struct CompareQuery {
+    a: String,
+    b: String,
+    /// Also return a unified diff when both files are text.
+    #[serde(default)]
+    diff: bool,
+}
+
+/// Compares two files byte by byte. Blocking.
+fn same_bytes(a: &Path, b: &Path) -> std::io::Result<bool> {
+    let mut a = BufReader::new(std::fs::File::open(a)?);
+    let mut b = BufReader::new(std::fs::File::open(b)?);
+    let (mut buf_a, mut buf_b) = (vec![0u8; 64 * 1024], vec![0u8; 64 * 1024]);
+    loop {
+        let n = a.read(&mut buf_a)?;
+        if n == 0 {
+            return Ok(b.read(&mut buf_b[..1])? == 0);
+        }
+        if b.read_exact(&mut buf_b[..n]).is_err() || buf_a[..n] != buf_b[..n] {
+            return Ok(false);
+        }
+    }
+}
+
+/// Unified diff of two text files, or why there is none. Blocking.
+fn unified_diff(a: (&FileInfo, &Path), b: (&FileInfo, &Path)) -> Result<String, String> {
+    let read = |path: &Path| {
+        String::from_utf8(std::fs::read(path).map_err(|e| e.to_string())?)
+            .map_err(|_| "not valid UTF-8 text".to_string())
+    };
+    let (text_a, text_b) = (read(a.1)?, read(b.1)?);
+    Ok(TextDiff::from_lines(&text_a, &text_b)
+        .unified_diff()
+        .context_radius(3)
+        .header(&a.0.name, &b.0.name)
+        .to_string())
+}
+
+pub async fn compare_files(
+    query: web::Query<CompareQuery>,
+    data: web::Data<AppState>,
+) -> HttpResponse {
+    let resolve = |id: &str| {
+        let info = find_file(&data, id)?;
+        let path = stored_path(&data.config, &info.name)?;
+        Some((info, path))
+    };
+    let (Some((a, path_a)), Some((b, path_b))) = (resolve(&query.a), resolve(&query.b)) else {
+        return HttpResponse::NotFound().json(serde_json::json!({"error": "File not found"}));
+    };
+
+    // Stored checksums settle it without reading either file.
+    let (identical, method) = match (&a.checksum, &b.checksum) {
+        _ if a.size != b.size => (false, "size"),
+        (Some(ca), Some(cb)) if ca.algorithm == cb.algorithm => {
+            (ca.digest == cb.digest, "checksum")
+        }
+        _ => {
+            let (pa, pb) = (path_a.clone(), path_b.clone());
+            match web::block(move || same_bytes(&pa, &pb)).await {
+                Ok(Ok(same)) => (same, "bytes"),
+                _ => {
+                    return HttpResponse::InternalServerError()
+                        .json(serde_json::json!({"error": "Failed to read files"}));
+                }
+            }
+        }
+    };
+
+    let mut body = serde_json::json!({
+        "a": a.id,
+        "b": b.id,
+        "identical": identical,
+        "method": method,
+        "size_a": a.size,
+        "size_b": b.size,
+        "size_difference": b.size as i64 - a.size as i64,
+    });
+    if query.diff && !identical {
+        let max = data.config.diff_max_bytes;
+        let diff = if !is_text(&a.mime_type) || !is_text(&b.mime_type) {
+            Err("diffs are only available for text files".to_string())
+        } else if a.size > max || b.size > max {
+            Err(format!("files larger than {} bytes are not diffed", max))
+        } else {
+            web::block(move || unified_diff((&a, &path_a), (&b, &path_b)))
+                .await
+                .unwrap_or_else(|_| Err("diff task failed".to_string()))
+        };
+        match diff {
+            Ok(diff) => body["diff"] = diff.into(),
+            Err(reason) => body["diff_skipped"] = reason.into(),
+        }
+    }
+    HttpResponse::Ok().json(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{add_file, call, config, json, path, remove, state, unique};
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn compares_bytes() {
+        let names = [unique("a.bin"), unique("b.bin"), unique("c.bin")];
+        std::fs::write(path(&names[0]), b"same bytes").unwrap();
+        std::fs::write(path(&names[1]), b"same bytes").unwrap();
+        std::fs::write(path(&names[2]), b"same bytes and more").unwrap();
+        let (a, b, c) = (path(&names[0]), path(&names[1]), path(&names[2]));
+        assert!(same_bytes(&a, &b).unwrap());
+        assert!(!same_bytes(&a, &c).unwrap());
+        assert!(!same_bytes(&c, &a).unwrap());
+        names.iter().for_each(|name| remove(name));
+    }
+
+    #[actix_web::test]
+    async fn compares_stored_files() {
+        let data = state(config());
+        let names = [unique("a.txt"), unique("b.txt"), unique("c.txt")];
+        let a = add_file(&data, &names[0], b"one\ntwo\n", None);
+        let b = add_file(&data, &names[1], b"one\ntwo\n", None);
+        let c = add_file(&data, &names[2], b"one\n2\n", None);
+        let compare = |query: String| {
+            call(
+                &data,
+                TestRequest::get().uri(&format!("/api/files/compare?{}", query)),
+            )
+        };
+
+        let res = json(compare(format!("a={}&b={}", a.id, b.id)).await).await;
+        assert_eq!(res["identical"], true);
+        assert_eq!(res["method"], "checksum");
+
+        let res = json(compare(format!("a={}&b={}&diff=true", a.id, c.id)).await).await;
+        assert_eq!(res["identical"], false);
+        assert_eq!(res["method"], "size");
+        assert_eq!(res["size_difference"], -2);
+        let diff = res["diff"].as_str().unwrap();
+        assert!(diff.contains("-two") && diff.contains("+2"));
+
+        data.files
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .for_each(|f| f.checksum = None);
+        let res = json(compare(format!("a={}&b={}", a.id, b.id)).await).await;
+        assert_eq!(res["method"], "bytes");
+        assert_eq!(res["identical"], true);
+
+        let res = compare(format!("a={}&b=missing", a.id)).await;
+        assert_eq!(res.status(), 404);
+        names.iter().for_each(|name| remove(name));
+    }
+}
@@ -0,0 +1,148 @@
+//! Conditional downloads: `If-None-Match`, `If-Modified-Since` and
+//! `If-Range`.
+//!
+//! A client (or cache) revalidating a copy it holds gets `304 Not Modified`
+//! when the copy is current. `If-None-Match` is compared weakly, so entity
+//! tags a proxy marked `W/` still match, and any tag of a precompressed
+//! variant of the same contents counts too. `If-Modified-Since` is only
+//! looked at when there is no `If-None-Match`, and only to the second, as
+//! `Last-Modified` is sent.
+//!
+//! `If-Range` makes a `Range` request conditional: the range is only served
+//! if the validator still describes the file, otherwise the whole file is
+//! sent with `200`, so a resumed download never mixes two versions. An
+//! entity tag must match exactly (weak tags never do), and a date must equal
+//! `Last-Modified`.
+
+use actix_web::http::header::{self, HttpDate};
+use actix_web::HttpRequest;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn opaque(tag: &str) -> &str {
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+/// Whether the client's copy, identified by one of `etags` or by
+/// `modified`, is still current.
+pub fn not_modified(req: &HttpRequest, etags: &[String], modified: Option<SystemTime>) -> bool {
+    let get = |name| req.headers().get(name).and_then(|v| v.to_str().ok());
+    if let Some(tags) = get(header::IF_NONE_MATCH) {
+        return tags
+            .split(',')
+            .map(str::trim)
+            .any(|t| t == "*" || etags.iter().any(|current| opaque(t) == opaque(current)));
+    }
+    let Some(since) = get(header::IF_MODIFIED_SINCE).and_then(|v| v.parse::<HttpDate>().ok())
+    else {
+        return false;
+    };
+    modified.is_some_and(|m| secs(m) <= secs(since.into()))
+}
+
+/// Whether a `Range` header may be honored, given `If-Range`.
+pub fn range_applies(req: &HttpRequest, etag: &str, modified: Option<SystemTime>) -> bool {
+    let Some(value) = req
+        .headers()
+        .get(header::IF_RANGE)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+    else {
+        return true;
+    };
+    if value.starts_with('"') || value.starts_with("W/") {
+        return value == etag;
+    }
+    match (value.parse::<HttpDate>(), modified) {
+        (Ok(date), Some(m)) => secs(SystemTime::from(date)) == secs(m),
+        _ => false,
+    }
+}
+
+fn secs(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use std::time::Duration;
+
+    fn check(name: header::HeaderName, value: &str, modified: SystemTime) -> bool {
+        let req = TestRequest::default()
+            .insert_header((name, value))
+            .to_http_request();
+        not_modified(&req, &["\"abc\"".to_string()], Some(modified))
+    }
+
+    #[test]
+    fn matches_entity_tags() {
+        let now = SystemTime::now();
+        assert!(check(header::IF_NONE_MATCH, "\"abc\"", now));
+        assert!(check(header::IF_NONE_MATCH, "\"x\", W/\"abc\"", now));
+        assert!(check(header::IF_NONE_MATCH, "*", now));
+        assert!(!check(header::IF_NONE_MATCH, "\"abd\"", now));
+        let plain = TestRequest::default().to_http_request();
+        assert!(!not_modified(&plain, &["\"abc\"".to_string()], Some(now)));
+    }
+
+    #[test]
+    fn compares_dates_to_the_second() {
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_millis(1_000_000_500);
+        let at = |secs| HttpDate::from(SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+        assert!(check(
+            header::IF_MODIFIED_SINCE,
+            &at(1_000_000).to_string(),
+            modified
+        ));
+        assert!(check(
+            header::IF_MODIFIED_SINCE,
+            &at(1_000_001).to_string(),
+            modified
+        ));
+        assert!(!check(
+            header::IF_MODIFIED_SINCE,
+            &at(999_999).to_string(),
+            modified
+        ));
+        assert!(!check(header::IF_MODIFIED_SINCE, "yesterday", modified));
+    }
+
+    fn range_check(if_range: Option<&str>, modified: SystemTime) -> bool {
+        let mut req = TestRequest::default();
+        if let Some(value) = if_range {
+            req = req.insert_header((header::IF_RANGE, value));
+        }
+        range_applies(&req.to_http_request(), "\"abc\"", Some(modified))
+    }
+
+    #[test]
+    fn if_range_needs_an_exact_validator() {
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_millis(1_000_000_500);
+        let at = |secs| HttpDate::from(SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+        assert!(range_check(None, modified));
+        assert!(range_check(Some("\"abc\""), modified));
+        assert!(!range_check(Some("\"abd\""), modified));
+        assert!(!range_check(Some("W/\"abc\""), modified));
+        assert!(range_check(Some(&at(1_000_000).to_string()), modified));
+        assert!(!range_check(Some(&at(1_000_001).to_string()), modified));
+        assert!(!range_check(Some("garbage"), modified));
+    }
+
+    #[test]
+    fn entity_tags_take_precedence_over_dates() {
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let req = TestRequest::default()
+            .insert_header((header::IF_NONE_MATCH, "\"old\""))
+            .insert_header((
+                header::IF_MODIFIED_SINCE,
+                HttpDate::from(SystemTime::now()).to_string(),
+            ))
+            .to_http_request();
+        assert!(!not_modified(
+            &req,
+            &["\"abc\"".to_string()],
+            Some(modified)
+        ));
+    }
+}
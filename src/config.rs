@@ -0,0 +1,594 @@
+//! Runtime configuration read from environment variables at startup.
+
+use crate::casing::JsonCase;
+use crate::hashing::HashAlgorithm;
+use crate::logging::LogFormat;
+use crate::mimetypes;
+use crate::proxy::IpNet;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub struct Config {
+    /// `HASH_ALGORITHM`: digest computed for every upload (`sha256` or `blake3`).
+    pub hash_algorithm: HashAlgorithm,
+    /// `UPLOAD_FIELD_NAMES`: multipart field names whose parts are stored as
+    /// files. Parts under any other name are form data, not files.
+    pub upload_field_names: Vec<String>,
+    /// `LOG_FORMAT`: `human` for terminals, `json` for log aggregation.
+    pub log_format: LogFormat,
+    /// `PARTIAL_UPLOAD_MAX_AGE_SECS`: at startup, leftover `.part` files at
+    /// least this old are deleted. `0` removes all of them.
+    pub partial_upload_max_age: Duration,
+    /// `EXTENSIONLESS_UPLOADS`: what to do with uploads whose name has no
+    /// extension.
+    pub extensionless_uploads: ExtensionlessPolicy,
+    /// `UNSAFE_NAMES`: what to do with uploads whose sanitized name is empty,
+    /// hidden or reserved (see `is_unsafe_name`).
+    pub unsafe_names: UnsafeNamePolicy,
+    /// `ADMIN_TOKEN`: bearer token for `/api/admin/*`. Unset disables the
+    /// admin API.
+    pub admin_token: Option<String>,
+    /// `SYMLINK_POLICY`: how symlinks inside the upload directory are treated
+    /// by the startup scan and by downloads.
+    pub symlink_policy: SymlinkPolicy,
+    /// `DOWNLOAD_CONFIRMATION`: require a confirmation token before serving
+    /// downloads (see `confirm.rs`).
+    pub download_confirmation: bool,
+    /// `DOWNLOAD_CONFIRMATION_TTL_SECS`: lifetime of a confirmation token.
+    pub download_confirmation_ttl: Duration,
+    /// `WC_MAX_BYTES`: how much of a text file is read to compute line/word
+    /// counts.
+    pub wc_max_bytes: u64,
+    /// `PUBLIC_BASE_URL`: origin used in absolute links (e.g.
+    /// `https://files.example.com`). Defaults to the request's own host.
+    pub public_base_url: Option<String>,
+    /// `FEED_ENABLED`: serve the RSS feed of recent uploads.
+    pub feed_enabled: bool,
+    /// `PWA`: serve a web app manifest and service worker so the UI can be
+    /// installed and browsed offline (see `pwa.rs`).
+    pub pwa: bool,
+    /// `METRICS`: serve storage health at `/metrics` in the Prometheus
+    /// text format (see `disk_report.rs`).
+    pub metrics: bool,
+    /// `FEED_MAX_ITEMS`: number of uploads listed in the feed.
+    pub feed_max_items: usize,
+    /// `UNIQUE_NAMES`: treat names as stable handles and refuse collisions
+    /// with `409` instead of renaming to `name_<id>.ext`.
+    pub unique_names: bool,
+    /// `IMAGE_DIMENSIONS`: record width/height for image files.
+    pub image_dimensions: bool,
+    /// `WRITE_RETRIES`: retries for a transient write error during upload.
+    pub write_retries: u32,
+    /// `WRITE_RETRY_BACKOFF_MS`: delay before the first retry; doubles each
+    /// attempt.
+    pub write_retry_backoff: Duration,
+    /// `HTTPS_POLICY`: what to do with requests that did not arrive over
+    /// HTTPS (see `https.rs`).
+    pub https_policy: HttpsPolicy,
+    /// `HSTS_MAX_AGE_SECS`: `Strict-Transport-Security` max-age sent on HTTPS
+    /// responses; `0` sends no header.
+    pub hsts_max_age: u64,
+    /// `DEFAULT_PAGE_SIZE`: files per page when a listing omits `limit`.
+    pub default_page_size: usize,
+    /// `MAX_PAGE_SIZE`: upper bound on `limit`; larger values are clamped.
+    pub max_page_size: usize,
+    /// `UPLOAD_MAX_DURATION_SECS`: longest an upload request may take from
+    /// start to finish; `None` (`0`) for no limit.
+    pub upload_max_duration: Option<Duration>,
+    /// `DELETE_GRACE_SECS`: how long a deleted file stays on disk while
+    /// downloads of it are still in flight (see `downloads.rs`).
+    pub delete_grace: Duration,
+    /// `TOMBSTONE_RETENTION_SECS`: how long deletions are remembered for the
+    /// changes feed (see `changes.rs`).
+    pub tombstone_retention: Duration,
+    /// `JSON_CASE`: field naming of JSON responses, `snake` or `camel`
+    /// (see `casing.rs`).
+    pub json_case: JsonCase,
+    /// `SIGNING_KEY`: secret for signed download URLs. A random key is used
+    /// when unset, so signed URLs stop working on restart; with a fixed key
+    /// they survive it, as file ids are persisted (see `ids.rs`).
+    pub signing_key: Option<String>,
+    /// `SIGNED_URL_TTL_SECS`: lifetime of a signed URL when `ttl` is omitted.
+    pub signed_url_ttl: u64,
+    /// `SIGNED_URL_MAX_TTL_SECS`: longest lifetime a client may ask for.
+    pub signed_url_max_ttl: u64,
+    /// `PRECOMPRESS`: store gzip/brotli variants of compressible uploads and
+    /// serve them by `Accept-Encoding` (see `precompress.rs`).
+    pub precompress: bool,
+    /// `DIFF_MAX_BYTES`: largest file the compare endpoint will diff.
+    pub diff_max_bytes: u64,
+    /// `STORAGE_PROBE_INTERVAL_SECS`: how often the upload directory is
+    /// checked for writability; `0` checks only at startup.
+    pub storage_probe_interval: Duration,
+    /// `EXIF_METADATA`: serve photos' EXIF data at `/api/files/{id}/metadata`.
+    pub exif_metadata: bool,
+    /// `CLIENT_QUOTA_BYTES`: storage each client IP may use; `None` (`0`)
+    /// for no limit.
+    pub client_quota: Option<u64>,
+    /// `POLICY_URL`: external service asked to accept or reject each upload
+    /// (see `policy.rs`).
+    pub policy_url: Option<String>,
+    /// `POLICY_TIMEOUT_MS`: how long to wait for the policy service.
+    pub policy_timeout: Duration,
+    /// `POLICY_FAIL_OPEN`: accept uploads when the policy service fails,
+    /// instead of refusing them.
+    pub policy_fail_open: bool,
+    /// `DOWNLOAD_CHUNK_SIZE`: read buffer used when streaming downloads,
+    /// clamped to 4 KiB..8 MiB.
+    pub download_chunk_size: usize,
+    /// `SERVER_HEADER`: value sent as `Server`; unset sends none.
+    pub server_header: Option<String>,
+    /// `ALLOWED_HOSTS`: accepted `Host` values, lowercased; empty accepts any.
+    pub allowed_hosts: Vec<String>,
+    /// `FOLLOW_POLL_MS`: how often a followed file is checked for growth.
+    pub follow_poll: Duration,
+    /// `FOLLOW_MAX_SECS`: how long a follow stream stays open.
+    pub follow_max_duration: Duration,
+    /// `MIME_OVERRIDES` merged over the built-in table, by lowercase
+    /// extension (see `mimetypes.rs`).
+    pub mime_overrides: HashMap<String, String>,
+    /// `SCRUB_FILES_PER_HOUR`: rate at which stored files are re-hashed to
+    /// detect corruption; `0` disables scrubbing.
+    pub scrub_files_per_hour: u32,
+    /// `SCRUB_QUARANTINE`: move files that fail the scrub out of the listing.
+    pub scrub_quarantine: bool,
+    /// `DENIED_FILE_STATUS`: how a request for an existing file that the
+    /// caller may not touch is answered.
+    pub denied_file_status: DeniedStatus,
+    /// `INLINE_MIME_TYPES`: types that `?disposition=inline` may display in
+    /// the browser, lowercased; `type/*` matches a whole family. Everything
+    /// else is always an attachment.
+    pub inline_types: Vec<String>,
+    /// `CASE_INSENSITIVE_NAMES`: whether names differing only by case are
+    /// treated as the same file.
+    pub case_insensitive_names: CaseNames,
+    /// `MAX_VERSIONS`: previous versions kept per replaced file; `0` turns
+    /// versioning off (see `versions.rs`).
+    pub max_versions: usize,
+    /// `PLACEHOLDER_TTL_SECS`: how long a placeholder waits for its upload
+    /// (see `placeholders.rs`).
+    pub placeholder_ttl: Duration,
+    /// `NORMALIZE_NAMES`: bring uploaded file names to Unicode NFC before
+    /// sanitizing them.
+    pub normalize_names: bool,
+    /// `SIZE_MISMATCH`: what to do when an upload sent with `X-Expected-Size`
+    /// turns out to have a different size.
+    pub size_mismatch: SizeMismatchPolicy,
+    /// `DOWNLOAD_CACHE_CONTROL`: `Cache-Control` sent with downloads.
+    pub download_cache_control: String,
+    /// `SURROGATE_CONTROL`: when set, downloads also carry this
+    /// `Surrogate-Control` value and a `Surrogate-Key` of the file id, for
+    /// edge caches that can be purged by key.
+    pub surrogate_control: Option<String>,
+    /// `VERIFY_DOWNLOADS`: check files against their checksum while they
+    /// are served (see `verify.rs`).
+    pub verify_downloads: VerifyDownloads,
+    /// `TRUSTED_PROXIES`: addresses or CIDR ranges of reverse proxies whose
+    /// forwarding headers are believed (see `proxy.rs`).
+    pub trusted_proxies: Vec<IpNet>,
+    /// `PRECOMPRESS_MAX_BYTES`: largest upload that gets precompressed
+    /// variants.
+    pub precompress_max_bytes: u64,
+}
+
+/// Types browsers display without running scripts. Notably not SVG or HTML.
+const DEFAULT_INLINE_TYPES: &[&str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "image/avif",
+    "application/pdf",
+    "text/plain",
+    "audio/*",
+    "video/*",
+];
+
+impl Config {
+    pub fn from_env() -> Result<Self, String> {
+        Ok(Config {
+            hash_algorithm: env_or("HASH_ALGORITHM", HashAlgorithm::Sha256)?,
+            upload_field_names: env_list("UPLOAD_FIELD_NAMES", &["file", "files", "files[]"]),
+            log_format: env_or("LOG_FORMAT", LogFormat::Human)?,
+            partial_upload_max_age: Duration::from_secs(env_or("PARTIAL_UPLOAD_MAX_AGE_SECS", 0)?),
+            extensionless_uploads: env_or("EXTENSIONLESS_UPLOADS", ExtensionlessPolicy::Allow)?,
+            unsafe_names: env_or("UNSAFE_NAMES", UnsafeNamePolicy::Rename)?,
+            admin_token: env_opt("ADMIN_TOKEN"),
+            symlink_policy: env_or("SYMLINK_POLICY", SymlinkPolicy::Ignore)?,
+            download_confirmation: env_flag("DOWNLOAD_CONFIRMATION", false)?,
+            download_confirmation_ttl: Duration::from_secs(env_or(
+                "DOWNLOAD_CONFIRMATION_TTL_SECS",
+                300,
+            )?),
+            wc_max_bytes: env_or("WC_MAX_BYTES", 64 * 1024 * 1024)?,
+            public_base_url: env_opt("PUBLIC_BASE_URL"),
+            feed_enabled: env_flag("FEED_ENABLED", false)?,
+            pwa: env_flag("PWA", false)?,
+            metrics: env_flag("METRICS", false)?,
+            feed_max_items: env_or("FEED_MAX_ITEMS", 50)?,
+            unique_names: env_flag("UNIQUE_NAMES", false)?,
+            image_dimensions: env_flag("IMAGE_DIMENSIONS", true)?,
+            write_retries: env_or("WRITE_RETRIES", 3)?,
+            write_retry_backoff: Duration::from_millis(env_or("WRITE_RETRY_BACKOFF_MS", 50)?),
+            https_policy: env_or("HTTPS_POLICY", HttpsPolicy::Off)?,
+            hsts_max_age: env_or("HSTS_MAX_AGE_SECS", 0)?,
+            default_page_size: env_or("DEFAULT_PAGE_SIZE", 100)?,
+            max_page_size: env_or("MAX_PAGE_SIZE", 1000)?,
+            upload_max_duration: match env_or("UPLOAD_MAX_DURATION_SECS", 0)? {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            delete_grace: Duration::from_secs(env_or("DELETE_GRACE_SECS", 0)?),
+            tombstone_retention: Duration::from_secs(env_or(
+                "TOMBSTONE_RETENTION_SECS",
+                7 * 24 * 3600,
+            )?),
+            json_case: env_or("JSON_CASE", JsonCase::Snake)?,
+            signing_key: env_opt("SIGNING_KEY"),
+            signed_url_ttl: env_or("SIGNED_URL_TTL_SECS", 3600)?,
+            signed_url_max_ttl: env_or("SIGNED_URL_MAX_TTL_SECS", 7 * 24 * 3600)?,
+            precompress: env_flag("PRECOMPRESS", false)?,
+            diff_max_bytes: env_or("DIFF_MAX_BYTES", 1024 * 1024)?,
+            storage_probe_interval: Duration::from_secs(env_or("STORAGE_PROBE_INTERVAL_SECS", 30)?),
+            exif_metadata: env_flag("EXIF_METADATA", false)?,
+            client_quota: Some(env_or("CLIENT_QUOTA_BYTES", 0)?).filter(|&q| q > 0),
+            policy_url: env_opt("POLICY_URL"),
+            policy_timeout: Duration::from_millis(env_or("POLICY_TIMEOUT_MS", 5000)?),
+            policy_fail_open: env_flag("POLICY_FAIL_OPEN", false)?,
+            download_chunk_size: env_or("DOWNLOAD_CHUNK_SIZE", 64 * 1024)?
+                .clamp(4 * 1024, 8 * 1024 * 1024),
+            server_header: match env_opt("SERVER_HEADER") {
+                Some(v) if !v.bytes().all(|b| b == b'\t' || (0x20..0x7f).contains(&b)) => {
+                    return Err("SERVER_HEADER must be printable ASCII".to_string());
+                }
+                v => v,
+            },
+            allowed_hosts: env_list("ALLOWED_HOSTS", &[])
+                .into_iter()
+                .map(|h| h.to_ascii_lowercase())
+                .collect(),
+            follow_poll: Duration::from_millis(env_or("FOLLOW_POLL_MS", 500)?.max(10)),
+            follow_max_duration: Duration::from_secs(env_or("FOLLOW_MAX_SECS", 3600)?),
+            mime_overrides: mimetypes::overrides(env_opt("MIME_OVERRIDES").as_deref())?,
+            scrub_files_per_hour: env_or("SCRUB_FILES_PER_HOUR", 0)?,
+            scrub_quarantine: env_flag("SCRUB_QUARANTINE", false)?,
+            denied_file_status: env_or("DENIED_FILE_STATUS", DeniedStatus::NotFound)?,
+            inline_types: env_list("INLINE_MIME_TYPES", DEFAULT_INLINE_TYPES)
+                .into_iter()
+                .map(|t| t.to_ascii_lowercase())
+                .collect(),
+            case_insensitive_names: env_or("CASE_INSENSITIVE_NAMES", CaseNames::Auto)?,
+            max_versions: env_or("MAX_VERSIONS", 0)?,
+            placeholder_ttl: Duration::from_secs(env_or("PLACEHOLDER_TTL_SECS", 3600)?),
+            normalize_names: env_flag("NORMALIZE_NAMES", true)?,
+            size_mismatch: env_or("SIZE_MISMATCH", SizeMismatchPolicy::Reject)?,
+            download_cache_control: env_opt("DOWNLOAD_CACHE_CONTROL")
+                .unwrap_or_else(|| "no-cache".to_string()),
+            surrogate_control: env_opt("SURROGATE_CONTROL"),
+            verify_downloads: env_or("VERIFY_DOWNLOADS", VerifyDownloads::Off)?,
+            trusted_proxies: env_list("TRUSTED_PROXIES", &[])
+                .iter()
+                .map(|p| {
+                    p.parse()
+                        .map_err(|e| format!("invalid TRUSTED_PROXIES: {}", e))
+                })
+                .collect::<Result<_, String>>()?,
+            precompress_max_bytes: env_or("PRECOMPRESS_MAX_BYTES", 64 * 1024 * 1024)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtensionlessPolicy {
+    /// Store the file under its name as-is.
+    Allow,
+    /// Refuse the upload with `422`.
+    Reject,
+    /// Append an extension derived from the part's declared `Content-Type`;
+    /// falls back to storing as-is when no usable type was declared.
+    Derive,
+}
+
+impl FromStr for ExtensionlessPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "allow" => Ok(ExtensionlessPolicy::Allow),
+            "reject" => Ok(ExtensionlessPolicy::Reject),
+            "derive" => Ok(ExtensionlessPolicy::Derive),
+            other => Err(format!(
+                "unknown policy '{}' (expected allow, reject or derive)",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnsafeNamePolicy {
+    /// Store the file as `file_<id>`, keeping its extension.
+    Rename,
+    /// Refuse the upload with `400`.
+    Reject,
+}
+
+impl FromStr for UnsafeNamePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "rename" => Ok(UnsafeNamePolicy::Rename),
+            "reject" => Ok(UnsafeNamePolicy::Reject),
+            other => Err(format!(
+                "unknown unsafe name policy '{}' (expected rename or reject)",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeniedStatus {
+    /// `404`, exactly as if the file did not exist, so ids cannot be probed.
+    NotFound,
+    /// `403` (or `401` when a token would help), revealing that it exists.
+    Forbidden,
+}
+
+impl FromStr for DeniedStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "404" | "not_found" => Ok(DeniedStatus::NotFound),
+            "403" | "forbidden" => Ok(DeniedStatus::Forbidden),
+            other => Err(format!(
+                "unknown denied file status '{}' (expected 404 or 403)",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaseNames {
+    /// Follow the upload directory's filesystem, probed at startup.
+    Auto,
+    /// Always compare names case-insensitively, e.g. to keep the set of
+    /// names portable to macOS and Windows.
+    Always,
+    Never,
+}
+
+impl FromStr for CaseNames {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(CaseNames::Auto),
+            "always" => Ok(CaseNames::Always),
+            "never" => Ok(CaseNames::Never),
+            other => Err(format!(
+                "unknown case-insensitive names mode '{}' (expected auto, always or never)",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeMismatchPolicy {
+    /// Refuse the upload with `400` and discard what was received.
+    Reject,
+    /// Keep the file with the size actually received and log a warning.
+    Record,
+}
+
+impl FromStr for SizeMismatchPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "reject" => Ok(SizeMismatchPolicy::Reject),
+            "record" => Ok(SizeMismatchPolicy::Record),
+            other => Err(format!(
+                "unknown size mismatch policy '{}' (expected reject or record)",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyDownloads {
+    Off,
+    /// Refuse to serve files that do not match their checksum.
+    Refuse,
+    /// Also move them to the quarantine directory, as `SCRUB_QUARANTINE`
+    /// does.
+    Quarantine,
+}
+
+impl FromStr for VerifyDownloads {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" => Ok(VerifyDownloads::Off),
+            "refuse" => Ok(VerifyDownloads::Refuse),
+            "quarantine" => Ok(VerifyDownloads::Quarantine),
+            other => Err(format!(
+                "unknown download verification mode '{}' (expected off, refuse or quarantine)",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Symlinks are invisible: not listed and not downloadable.
+    Ignore,
+    /// Follow symlinks whose target resolves inside the upload directory.
+    Within,
+    /// Follow every symlink. Unsafe: a link can expose any readable file.
+    Follow,
+}
+
+impl FromStr for SymlinkPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ignore" => Ok(SymlinkPolicy::Ignore),
+            "within" => Ok(SymlinkPolicy::Within),
+            "follow" => Ok(SymlinkPolicy::Follow),
+            other => Err(format!(
+                "unknown symlink policy '{}' (expected ignore, within or follow)",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HttpsPolicy {
+    /// Serve plain HTTP as usual.
+    Off,
+    /// Answer with `308 Permanent Redirect` to the same path on `https://`.
+    Redirect,
+    /// Refuse with `426 Upgrade Required`.
+    Reject,
+}
+
+impl FromStr for HttpsPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" => Ok(HttpsPolicy::Off),
+            "redirect" => Ok(HttpsPolicy::Redirect),
+            "reject" => Ok(HttpsPolicy::Reject),
+            other => Err(format!(
+                "unknown HTTPS policy '{}' (expected off, redirect or reject)",
+                other
+            )),
+        }
+    }
+}
+
+/// Reads and parses `key`, falling back to `default` when it is unset or empty.
+fn env_or<T>(key: &str, default: T) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match std::env::var(key) {
+        Ok(v) if !v.trim().is_empty() => v
+            .trim()
+            .parse()
+            .map_err(|e| format!("invalid {}: {}", key, e)),
+        _ => Ok(default),
+    }
+}
+
+/// Reads a boolean flag: `true`/`false`, `1`/`0`, `yes`/`no` or `on`/`off`.
+fn env_flag(key: &str, default: bool) -> Result<bool, String> {
+    match std::env::var(key) {
+        Ok(v) if !v.trim().is_empty() => match v.trim().to_ascii_lowercase().as_str() {
+            "true" | "1" | "yes" | "on" => Ok(true),
+            "false" | "0" | "no" | "off" => Ok(false),
+            other => Err(format!(
+                "invalid {}: expected a boolean, got '{}'",
+                key, other
+            )),
+        },
+        _ => Ok(default),
+    }
+}
+
+/// Reads an optional string; unset and empty are both `None`.
+fn env_opt(key: &str) -> Option<String> {
+    std::env::var(key)
+        .ok()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// Reads a comma-separated list, falling back to `default` when unset or empty.
+fn env_list(key: &str, default: &[&str]) -> Vec<String> {
+    let items: Vec<String> = std::env::var(key)
+        .unwrap_or_default()
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect();
+    if items.is_empty() {
+        default.iter().map(|s| s.to_string()).collect()
+    } else {
+        items
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_extensionless_policies() {
+        assert_eq!("allow".parse(), Ok(ExtensionlessPolicy::Allow));
+        assert_eq!("Reject".parse(), Ok(ExtensionlessPolicy::Reject));
+        assert_eq!("DERIVE".parse(), Ok(ExtensionlessPolicy::Derive));
+        assert!("guess".parse::<ExtensionlessPolicy>().is_err());
+    }
+
+    #[test]
+    fn parses_symlink_policies() {
+        assert_eq!("ignore".parse(), Ok(SymlinkPolicy::Ignore));
+        assert_eq!("Within".parse(), Ok(SymlinkPolicy::Within));
+        assert_eq!("follow".parse(), Ok(SymlinkPolicy::Follow));
+        assert!("allow".parse::<SymlinkPolicy>().is_err());
+    }
+
+    #[test]
+    fn parses_https_policies() {
+        assert_eq!("off".parse(), Ok(HttpsPolicy::Off));
+        assert_eq!("Redirect".parse(), Ok(HttpsPolicy::Redirect));
+        assert_eq!("REJECT".parse(), Ok(HttpsPolicy::Reject));
+        assert!("on".parse::<HttpsPolicy>().is_err());
+    }
+
+    #[test]
+    fn parses_unsafe_name_policies() {
+        assert_eq!("rename".parse(), Ok(UnsafeNamePolicy::Rename));
+        assert_eq!("Reject".parse(), Ok(UnsafeNamePolicy::Reject));
+        assert!("allow".parse::<UnsafeNamePolicy>().is_err());
+    }
+
+    #[test]
+    fn parses_denied_statuses() {
+        assert_eq!("404".parse(), Ok(DeniedStatus::NotFound));
+        assert_eq!("Not_Found".parse(), Ok(DeniedStatus::NotFound));
+        assert_eq!("403".parse(), Ok(DeniedStatus::Forbidden));
+        assert_eq!("forbidden".parse(), Ok(DeniedStatus::Forbidden));
+        assert!("401".parse::<DeniedStatus>().is_err());
+    }
+
+    #[test]
+    fn parses_case_name_modes() {
+        assert_eq!("auto".parse(), Ok(CaseNames::Auto));
+        assert_eq!("Always".parse(), Ok(CaseNames::Always));
+        assert_eq!("NEVER".parse(), Ok(CaseNames::Never));
+        assert!("sometimes".parse::<CaseNames>().is_err());
+    }
+
+    #[test]
+    fn parses_size_mismatch_policies() {
+        assert_eq!("reject".parse(), Ok(SizeMismatchPolicy::Reject));
+        assert_eq!("Record".parse(), Ok(SizeMismatchPolicy::Record));
+        assert!("ignore".parse::<SizeMismatchPolicy>().is_err());
+    }
+}
@@ -0,0 +1,128 @@
+//! Optional confirmation step in front of downloads to deter naive bots.
+//!
+//! With `DOWNLOAD_CONFIRMATION` enabled, `GET /api/download/{filename}` only
+//! serves the file when it carries a `?confirm=<token>` obtained from
+//! `POST /api/download/{filename}/confirm`. Browsers get a small interstitial
+//! page that fetches the token with JavaScript; API clients get a `403`
+//! pointing at the confirm endpoint. This is a deterrent against crawlers and
+//! scanners that blindly follow links, not access control: anyone can obtain
+//! a token.
+
+use crate::{encode_path_segment, html_escape, stored_path, AppState};
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Issued tokens, each bound to one file name and valid until its deadline.
+/// A token may be reused until it expires so that resumed and ranged
+/// downloads keep working.
+pub struct ConfirmationTokens {
+    tokens: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl ConfirmationTokens {
+    pub fn new() -> Self {
+        ConfirmationTokens {
+            tokens: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn issue(&self, filename: &str, ttl: Duration) -> String {
+        let token = Uuid::new_v4().simple().to_string();
+        let now = Instant::now();
+        let mut tokens = self.tokens.lock().unwrap();
+        tokens.retain(|_, (_, deadline)| *deadline > now);
+        tokens.insert(token.clone(), (filename.to_string(), now + ttl));
+        token
+    }
+
+    pub fn is_valid(&self, token: &str, filename: &str) -> bool {
+        self.tokens
+            .lock()
+            .unwrap()
+            .get(token)
+            .is_some_and(|(name, deadline)| name == filename && *deadline > Instant::now())
+    }
+}
+
+fn download_url(filename: &str, token: &str) -> String {
+    format!(
+        "/api/download/{}?confirm={}",
+        encode_path_segment(filename),
+        token
+    )
+}
+
+/// `POST /api/download/{filename}/confirm`: issues a short-lived token. Also
+/// works when confirmation is disabled, so clients need not know the setting.
+pub async fn issue_token(path: web::Path<String>, data: web::Data<AppState>) -> HttpResponse {
+    let filename = path.into_inner();
+    if stored_path(&data.config, &filename).is_none() {
+        return HttpResponse::NotFound().json(serde_json::json!({"error": "File not found"}));
+    }
+    let ttl = data.config.download_confirmation_ttl;
+    let token = data.confirmations.issue(&filename, ttl);
+    let expires_at = Utc::now() + chrono::Duration::from_std(ttl).unwrap_or_default();
+    HttpResponse::Ok().json(serde_json::json!({
+        "token": token,
+        "expires_at": expires_at,
+        "url": download_url(&filename, &token),
+    }))
+}
+
+/// Response for a download attempted without a valid token.
+pub fn required(req: &HttpRequest, filename: &str) -> HttpResponse {
+    let confirm_url = format!("/api/download/{}/confirm", encode_path_segment(filename));
+    let wants_html = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("text/html"));
+    if !wants_html {
+        return HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "Download confirmation required",
+            "confirm_url": confirm_url,
+        }));
+    }
+
+    let name = html_escape(filename);
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="UTF-8">
+<meta name="viewport" content="width=device-width, initial-scale=1.0">
+<meta name="robots" content="noindex, nofollow">
+<title>Download {name}</title>
+<style>
+body {{ background: #050505; color: #ededed; font-family: system-ui, -apple-system, sans-serif; display: flex; align-items: center; justify-content: center; height: 100vh; margin: 0; }}
+.card {{ background: #111; border: 1px solid #222; border-radius: 16px; padding: 32px; text-align: center; max-width: 420px; }}
+.name {{ font-weight: 600; margin: 12px 0 24px; word-break: break-all; }}
+button {{ background: #3b82f6; color: #fff; border: none; border-radius: 10px; padding: 10px 24px; font-size: 14px; cursor: pointer; }}
+</style>
+</head>
+<body>
+<div class="card">
+<div>Ready to download</div>
+<div class="name">{name}</div>
+<button id="go">Download</button>
+</div>
+<script>
+document.getElementById('go').addEventListener('click', function() {{
+    fetch('{confirm_url}', {{ method: 'POST' }})
+        .then(function(r) {{ return r.json(); }})
+        .then(function(j) {{ window.location.href = j.url; }});
+}});
+</script>
+</body>
+</html>"#
+    );
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .body(html)
+}
@@ -0,0 +1,253 @@
+//! Storage health for long-running deployments: what is lying around in the
+//! upload directory besides live files, and how the live files are spread.
+//!
+//! Everything comes from the records and one directory listing (metadata
+//! only), so a report is cheap even with many large files. Duplicates are
+//! found by comparing stored checksums, never by re-hashing; files without a
+//! checksum are counted as unique.
+
+use crate::admin::require_admin;
+use crate::{AppState, FileInfo, UPLOAD_DIR};
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::path::Path;
+use std::time::SystemTime;
+
+#[derive(Debug, Default, Serialize)]
+pub struct Leftovers {
+    files: u64,
+    bytes: u64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct DiskScan {
+    /// Listed files no longer in the upload directory.
+    missing_files: u64,
+    /// Files in the upload directory with no record.
+    orphaned: Leftovers,
+    /// `.part` files of unfinished uploads.
+    partial: Leftovers,
+    /// Age of the oldest `.part` file.
+    oldest_partial_secs: Option<u64>,
+    /// Precompressed variants whose original is no longer listed.
+    orphaned_variants: Leftovers,
+    /// Files moved aside by the scrubber (`SCRUB_QUARANTINE`).
+    quarantined: Leftovers,
+    /// Previous versions of replaced files (`MAX_VERSIONS`).
+    versions: Leftovers,
+}
+
+fn add(counter: &mut Leftovers, meta: &std::fs::Metadata) {
+    counter.files += 1;
+    counter.bytes += meta.len();
+}
+
+/// Regular files in `dir`, following symlinks.
+fn entries(dir: &Path) -> impl Iterator<Item = (String, std::fs::Metadata)> {
+    std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|e| {
+            let meta = std::fs::metadata(e.path()).ok()?;
+            meta.is_file()
+                .then(|| (e.file_name().to_string_lossy().to_string(), meta))
+        })
+}
+
+/// Lists the upload directory against the set of recorded names. Blocking.
+fn scan(listed: &HashSet<String>) -> DiskScan {
+    let mut report = DiskScan::default();
+    let root = Path::new(UPLOAD_DIR);
+    let mut seen = 0;
+    for (name, meta) in entries(root) {
+        if name.starts_with('.') && name.ends_with(".part") {
+            add(&mut report.partial, &meta);
+            let age = meta
+                .modified()
+                .ok()
+                .and_then(|t| SystemTime::now().duration_since(t).ok())
+                .map(|d| d.as_secs());
+            report.oldest_partial_secs = report.oldest_partial_secs.max(age);
+        } else if listed.contains(&name) {
+            seen += 1;
+        } else if !name.starts_with('.') {
+            add(&mut report.orphaned, &meta);
+        }
+    }
+    report.missing_files = listed.len() as u64 - seen;
+    for (name, meta) in entries(&root.join(".variants")) {
+        let original = name
+            .rsplit_once('.')
+            .map_or(name.as_str(), |(base, _)| base);
+        if !listed.contains(original) {
+            add(&mut report.orphaned_variants, &meta);
+        }
+    }
+    for (_, meta) in entries(&root.join(".quarantine")) {
+        add(&mut report.quarantined, &meta);
+    }
+    for dir in std::fs::read_dir(root.join(".versions"))
+        .into_iter()
+        .flatten()
+        .flatten()
+    {
+        for (_, meta) in entries(&dir.path()) {
+            add(&mut report.versions, &meta);
+        }
+    }
+    report
+}
+
+/// Builds the report shared by both endpoints.
+async fn build(data: &AppState) -> serde_json::Value {
+    let (listed, stats) = {
+        let all = data.files.lock().unwrap();
+        let files: Vec<&FileInfo> = all.iter().filter(|f| f.is_ready()).collect();
+        let listed: HashSet<String> = files.iter().map(|f| f.name.clone()).collect();
+        let total: u64 = files.iter().map(|f| f.size).sum();
+        let mut by_digest: HashMap<_, u64> = HashMap::new();
+        let mut unique = 0;
+        for f in files.iter() {
+            match &f.checksum {
+                Some(c) => {
+                    by_digest.entry((c.algorithm, &c.digest)).or_insert(f.size);
+                }
+                None => unique += f.size,
+            }
+        }
+        unique += by_digest.values().sum::<u64>();
+        let oldest = files.iter().map(|f| f.uploaded_at).min();
+        let stats = serde_json::json!({
+            "files": files.len(),
+            "bytes": total,
+            "average_file_bytes": if files.is_empty() { 0 } else { total / files.len() as u64 },
+            "oldest_file_age_secs": oldest.map(|t| (Utc::now() - t).num_seconds().max(0)),
+            "duplicate_bytes": total - unique,
+            "dedupe_ratio": if unique == 0 { 1.0 } else { total as f64 / unique as f64 },
+            "variant_bytes": files.iter().filter_map(|f| f.variant_bytes).sum::<u64>(),
+        });
+        (listed, stats)
+    };
+    let disk = web::block(move || scan(&listed)).await.unwrap_or_default();
+    let mut report = stats;
+    report["disk"] = serde_json::to_value(disk).unwrap_or_default();
+    report["writable"] = data.storage.problem().is_none().into();
+    report
+}
+
+/// `GET /api/admin/storage-health`.
+pub async fn storage_health(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
+    if let Err(res) = require_admin(&req, &data) {
+        return res;
+    }
+    HttpResponse::Ok().json(build(&data).await)
+}
+
+/// `GET /metrics`: the same figures in the Prometheus text format, when
+/// `METRICS` is on. Also requires the admin token.
+pub async fn metrics(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
+    if !data.config.metrics {
+        return HttpResponse::NotFound().json(serde_json::json!({"error": "Not found"}));
+    }
+    if let Err(res) = require_admin(&req, &data) {
+        return res;
+    }
+    let r = build(&data).await;
+    let gauges = [
+        ("files", "Files listed.", &r["files"]),
+        ("file_bytes", "Bytes of listed files.", &r["bytes"]),
+        (
+            "missing_files",
+            "Listed files no longer on disk.",
+            &r["disk"]["missing_files"],
+        ),
+        (
+            "average_file_bytes",
+            "Average size of listed files.",
+            &r["average_file_bytes"],
+        ),
+        (
+            "oldest_file_age_seconds",
+            "Age of the oldest listed file.",
+            &r["oldest_file_age_secs"],
+        ),
+        (
+            "duplicate_bytes",
+            "Bytes taken by files identical to another listed file.",
+            &r["duplicate_bytes"],
+        ),
+        (
+            "dedupe_ratio",
+            "Listed bytes divided by unique bytes.",
+            &r["dedupe_ratio"],
+        ),
+        (
+            "variant_bytes",
+            "Bytes of precompressed variants.",
+            &r["variant_bytes"],
+        ),
+        (
+            "orphaned_files",
+            "Files on disk with no record.",
+            &r["disk"]["orphaned"]["files"],
+        ),
+        (
+            "orphaned_bytes",
+            "Bytes of files on disk with no record.",
+            &r["disk"]["orphaned"]["bytes"],
+        ),
+        (
+            "partial_uploads",
+            "Unfinished upload files.",
+            &r["disk"]["partial"]["files"],
+        ),
+        (
+            "partial_upload_bytes",
+            "Bytes of unfinished upload files.",
+            &r["disk"]["partial"]["bytes"],
+        ),
+        (
+            "oldest_partial_upload_age_seconds",
+            "Age of the oldest unfinished upload file.",
+            &r["disk"]["oldest_partial_secs"],
+        ),
+        (
+            "orphaned_variant_bytes",
+            "Bytes of variants whose original is gone.",
+            &r["disk"]["orphaned_variants"]["bytes"],
+        ),
+        (
+            "version_bytes",
+            "Bytes of previous versions kept.",
+            &r["disk"]["versions"]["bytes"],
+        ),
+        (
+            "quarantined_files",
+            "Files quarantined by the scrubber.",
+            &r["disk"]["quarantined"]["files"],
+        ),
+        (
+            "storage_writable",
+            "1 if the upload directory is writable.",
+            &serde_json::json!(u8::from(r["writable"] == true)),
+        ),
+    ];
+    let mut body = String::new();
+    for (name, help, value) in gauges {
+        // Absent values (no files yet) are left out rather than reported as 0.
+        if value.is_null() {
+            continue;
+        }
+        let _ = write!(
+            body,
+            "# HELP filedrop_{name} {help}\n# TYPE filedrop_{name} gauge\nfiledrop_{name} {value}\n"
+        );
+    }
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(body)
+}
@@ -0,0 +1,215 @@
+//! Reference counts of in-flight downloads, so that deleting a file can hold
+//! off unlinking it until the transfers reading it have finished.
+
+use crate::logging;
+use futures_util::stream::{Stream, StreamExt};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+#[derive(Default)]
+struct Counts {
+    /// Stored file name -> number of response bodies still streaming it.
+    active: HashMap<String, usize>,
+    /// Files already deleted from the listing whose unlink is still pending.
+    deleting: HashSet<String>,
+}
+
+pub struct ActiveDownloads {
+    counts: Arc<Mutex<Counts>>,
+    /// Woken whenever a download finishes.
+    idle: Arc<Notify>,
+}
+
+/// Counts as one active download of a file until dropped.
+pub struct DownloadGuard {
+    name: String,
+    counts: Arc<Mutex<Counts>>,
+    idle: Arc<Notify>,
+}
+
+impl ActiveDownloads {
+    pub fn new() -> Self {
+        ActiveDownloads {
+            counts: Arc::new(Mutex::new(Counts::default())),
+            idle: Arc::new(Notify::new()),
+        }
+    }
+
+    pub fn start(&self, name: &str) -> DownloadGuard {
+        *self
+            .counts
+            .lock()
+            .unwrap()
+            .active
+            .entry(name.to_string())
+            .or_default() += 1;
+        DownloadGuard {
+            name: name.to_string(),
+            counts: self.counts.clone(),
+            idle: self.idle.clone(),
+        }
+    }
+
+    /// Downloads in progress, over all files.
+    pub fn active_count(&self) -> usize {
+        self.counts.lock().unwrap().active.values().sum()
+    }
+
+    /// Whether `name` was deleted and is only waiting for downloads to drain.
+    pub fn is_deleted(&self, name: &str) -> bool {
+        self.counts.lock().unwrap().deleting.contains(name)
+    }
+
+    /// A new file is about to be stored as `name`: a pending unlink of the
+    /// deleted file of that name is cancelled, so it cannot remove the new
+    /// one. Call before the new file is moved into place.
+    pub fn claim(&self, name: &str) {
+        self.counts.lock().unwrap().deleting.remove(name);
+    }
+
+    /// Unlinks a deleted file right away when nothing is downloading it or
+    /// `grace` is zero. Otherwise the unlink happens in the background once
+    /// the last download finishes, or when `grace` runs out, whichever comes
+    /// first.
+    pub fn remove_when_idle(&self, name: String, path: PathBuf, grace: Duration) {
+        {
+            let mut counts = self.counts.lock().unwrap();
+            if grace.is_zero() || !counts.active.contains_key(&name) {
+                drop(counts);
+                let _ = std::fs::remove_file(path);
+                return;
+            }
+            counts.deleting.insert(name.clone());
+        }
+
+        let counts = self.counts.clone();
+        let idle = self.idle.clone();
+        actix_web::rt::spawn(async move {
+            let deadline = tokio::time::Instant::now() + grace;
+            loop {
+                // Registered before checking, so a download finishing in
+                // between still wakes us.
+                let finished = idle.notified();
+                let active = counts.lock().unwrap().active.get(&name).copied();
+                let Some(active) = active else {
+                    break;
+                };
+                if tokio::time::timeout_at(deadline, finished).await.is_err() {
+                    logging::event(
+                        "warn",
+                        "removing deleted file with downloads still active",
+                        serde_json::json!({"file": name, "active": active}),
+                    );
+                    break;
+                }
+            }
+            // Under the lock, so `claim` either comes first and keeps the new
+            // file, or comes after the old one is gone.
+            let mut counts = counts.lock().unwrap();
+            if counts.deleting.remove(&name) {
+                let _ = std::fs::remove_file(path);
+            }
+        });
+    }
+}
+
+impl DownloadGuard {
+    /// Keeps the download counted as active for as long as `body` is alive,
+    /// i.e. until the response has been sent or the client went away.
+    pub fn attach<S: Stream>(self, body: S) -> impl Stream<Item = S::Item> {
+        body.map(move |item| {
+            let _ = &self;
+            item
+        })
+    }
+}
+
+impl Drop for DownloadGuard {
+    fn drop(&mut self) {
+        let mut counts = self.counts.lock().unwrap();
+        if let Some(n) = counts.active.get_mut(&self.name) {
+            *n -= 1;
+            if *n == 0 {
+                counts.active.remove(&self.name);
+            }
+        }
+        drop(counts);
+        self.idle.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{path, unique};
+    use crate::UPLOAD_DIR;
+
+    fn stored(contents: &str) -> (String, PathBuf) {
+        std::fs::create_dir_all(UPLOAD_DIR).unwrap();
+        let name = unique("file.txt");
+        std::fs::write(path(&name), contents).unwrap();
+        (name.clone(), path(&name))
+    }
+
+    /// Lets the background unlink run.
+    async fn settle() {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    #[test]
+    fn idle_files_are_removed_at_once() {
+        let downloads = ActiveDownloads::new();
+        let (name, file) = stored("old");
+        downloads.remove_when_idle(name.clone(), file.clone(), Duration::from_secs(60));
+        assert!(!file.exists());
+        assert!(!downloads.is_deleted(&name));
+    }
+
+    #[actix_web::test]
+    async fn removal_waits_for_the_last_download() {
+        let downloads = ActiveDownloads::new();
+        let (name, file) = stored("old");
+        let guard = downloads.start(&name);
+        assert_eq!(downloads.active_count(), 1);
+
+        downloads.remove_when_idle(name.clone(), file.clone(), Duration::from_secs(60));
+        settle().await;
+        assert!(file.exists());
+        assert!(downloads.is_deleted(&name));
+
+        drop(guard);
+        settle().await;
+        assert!(!file.exists());
+        assert!(!downloads.is_deleted(&name));
+        assert_eq!(downloads.active_count(), 0);
+    }
+
+    #[actix_web::test]
+    async fn removal_gives_up_waiting_after_the_grace_period() {
+        let downloads = ActiveDownloads::new();
+        let (name, file) = stored("old");
+        let _guard = downloads.start(&name);
+        downloads.remove_when_idle(name, file.clone(), Duration::from_millis(10));
+        settle().await;
+        assert!(!file.exists());
+    }
+
+    #[actix_web::test]
+    async fn a_file_stored_under_the_name_meanwhile_is_kept() {
+        let downloads = ActiveDownloads::new();
+        let (name, file) = stored("old");
+        let guard = downloads.start(&name);
+        downloads.remove_when_idle(name.clone(), file.clone(), Duration::from_secs(60));
+
+        downloads.claim(&name);
+        std::fs::write(&file, "new").unwrap();
+        assert!(!downloads.is_deleted(&name));
+        drop(guard);
+        settle().await;
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "new");
+        std::fs::remove_file(file).unwrap();
+    }
+}
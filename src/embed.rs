@@ -0,0 +1,159 @@
+//! `GET /api/files/{id}/embed`: a ready-to-paste snippet linking to a file,
+//! as an inline image for images and a plain link otherwise.
+
+use crate::{encode_path_segment, find_file, html_escape, public_base_url, AppState};
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum EmbedFormat {
+    #[default]
+    Markdown,
+    Html,
+    Bbcode,
+}
+
+#[derive(Deserialize)]
+pub struct EmbedQuery {
+    #[serde(default)]
+    format: EmbedFormat,
+}
+
+/// Escapes the characters that would end Markdown link text early.
+fn markdown_text(s: &str) -> String {
+    s.chars()
+        .flat_map(|c| match c {
+            '\\' | '[' | ']' | '*' | '_' | '`' | '<' | '>' => vec!['\\', c],
+            c => vec![c],
+        })
+        .collect()
+}
+
+fn snippet(format: EmbedFormat, name: &str, url: &str, image: bool) -> String {
+    match (format, image) {
+        (EmbedFormat::Markdown, true) => format!("![{}]({})", markdown_text(name), url),
+        (EmbedFormat::Markdown, false) => format!("[{}]({})", markdown_text(name), url),
+        (EmbedFormat::Html, true) => {
+            format!(
+                r#"<img src="{}" alt="{}">"#,
+                html_escape(url),
+                html_escape(name)
+            )
+        }
+        (EmbedFormat::Html, false) => {
+            format!(
+                r#"<a href="{}">{}</a>"#,
+                html_escape(url),
+                html_escape(name)
+            )
+        }
+        // BBCode has no escaping; brackets in the name are dropped instead.
+        (EmbedFormat::Bbcode, true) => format!("[img]{}[/img]", url),
+        (EmbedFormat::Bbcode, false) => {
+            format!("[url={}]{}[/url]", url, name.replace(['[', ']'], ""))
+        }
+    }
+}
+
+pub async fn embed_snippet(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<EmbedQuery>,
+    data: web::Data<AppState>,
+) -> HttpResponse {
+    let Some(info) = find_file(&data, &path.into_inner()) else {
+        return HttpResponse::NotFound().json(serde_json::json!({"error": "File not found"}));
+    };
+    // Plain links cannot get past the confirmation step, so there is
+    // nothing that would work when pasted.
+    if data.config.download_confirmation {
+        return HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "Direct links are disabled while download confirmation is on"
+        }));
+    }
+    let url = format!(
+        "{}/api/download/{}",
+        public_base_url(&req, &data.config),
+        encode_path_segment(&info.name)
+    );
+    let image = info.mime_type.starts_with("image/");
+    HttpResponse::Ok().json(serde_json::json!({
+        "id": info.id,
+        "url": url,
+        "kind": if image { "image" } else { "link" },
+        "snippet": snippet(query.format, &info.name, &url, image),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{add_file, call, config, json, remove, state, unique};
+    use crate::Config;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn escapes_names_per_format() {
+        let url = "https://x/a&b";
+        assert_eq!(
+            snippet(EmbedFormat::Markdown, "a_[b].png", url, true),
+            r"![a\_\[b\].png](https://x/a&b)"
+        );
+        assert_eq!(
+            snippet(EmbedFormat::Html, "<b>.txt", url, false),
+            r#"<a href="https://x/a&amp;b">&lt;b&gt;.txt</a>"#
+        );
+        assert_eq!(
+            snippet(EmbedFormat::Bbcode, "[x].txt", url, false),
+            "[url=https://x/a&b]x.txt[/url]"
+        );
+        assert_eq!(
+            snippet(EmbedFormat::Bbcode, "p.png", url, true),
+            "[img]https://x/a&b[/img]"
+        );
+    }
+
+    #[actix_web::test]
+    async fn links_to_the_download() {
+        let data = state(Config {
+            public_base_url: Some("https://files.example".to_string()),
+            ..config()
+        });
+        let name = unique("my photo.png");
+        let info = add_file(&data, &name, b"png", None);
+        let embed = |query: &str| {
+            call(
+                &data,
+                TestRequest::get().uri(&format!("/api/files/{}/embed{}", info.id, query)),
+            )
+        };
+
+        let res = json(embed("").await).await;
+        let url = format!(
+            "https://files.example/api/download/{}",
+            name.replace(' ', "%20")
+        );
+        assert_eq!(res["url"], url.as_str());
+        assert_eq!(res["kind"], "image");
+        assert_eq!(res["snippet"], format!("![{}]({})", name, url));
+
+        let res = json(embed("?format=html").await).await;
+        assert!(res["snippet"].as_str().unwrap().starts_with("<img src="));
+        assert_eq!(embed("?format=rtf").await.status(), 400);
+        remove(&name);
+    }
+
+    #[actix_web::test]
+    async fn refuses_while_downloads_need_confirmation() {
+        let data = state(Config {
+            download_confirmation: true,
+            ..config()
+        });
+        let name = unique("a.txt");
+        let info = add_file(&data, &name, b"a", None);
+        let req = TestRequest::get().uri(&format!("/api/files/{}/embed", info.id));
+        assert_eq!(call(&data, req).await.status(), 403);
+        remove(&name);
+    }
+}
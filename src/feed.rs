@@ -0,0 +1,54 @@
+//! RSS 2.0 feed of the most recent uploads.
+
+use crate::{encode_path_segment, html_escape, public_base_url, AppState};
+use actix_web::{web, HttpRequest, HttpResponse};
+
+/// `GET /api/feed.xml`. Every file is public in this server, so the feed
+/// simply lists the newest `FEED_MAX_ITEMS` uploads.
+pub async fn recent_uploads(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
+    if !data.config.feed_enabled {
+        return HttpResponse::NotFound().json(serde_json::json!({"error": "Feed is disabled"}));
+    }
+    let base = public_base_url(&req, &data.config);
+
+    let mut items = String::new();
+    let files = data.files.lock().unwrap();
+    for f in files
+        .iter()
+        .filter(|f| f.is_ready())
+        .take(data.config.feed_max_items)
+    {
+        let link = html_escape(&format!(
+            "{}/api/download/{}",
+            base,
+            encode_path_segment(&f.name)
+        ));
+        items.push_str(&format!(
+            "    <item>\n      <title>{}</title>\n      <link>{}</link>\n      <guid isPermaLink=\"false\">{}</guid>\n      <pubDate>{}</pubDate>\n      <enclosure url=\"{}\" length=\"{}\" type=\"{}\"/>\n    </item>\n",
+            html_escape(&f.name),
+            link,
+            f.id,
+            f.uploaded_at.to_rfc2822(),
+            link,
+            f.size,
+            html_escape(&f.mime_type),
+        ));
+    }
+    let last_build = files
+        .iter()
+        .find(|f| f.is_ready())
+        .map(|f| f.uploaded_at)
+        .unwrap_or_else(chrono::Utc::now)
+        .to_rfc2822();
+    drop(files);
+
+    let xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<rss version=\"2.0\">\n  <channel>\n    <title>FileDrop uploads</title>\n    <link>{}/</link>\n    <description>Recently uploaded files</description>\n    <lastBuildDate>{}</lastBuildDate>\n{}  </channel>\n</rss>\n",
+        html_escape(&base),
+        last_build,
+        items
+    );
+    HttpResponse::Ok()
+        .content_type("application/rss+xml; charset=utf-8")
+        .body(xml)
+}
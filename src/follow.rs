@@ -0,0 +1,141 @@
+//! `GET /api/files/{id}/follow`: `tail -f` over HTTP for files that are
+//! still being written, such as shared logs.
+//!
+//! The response is a chunked stream of the file's bytes from `?offset=`
+//! onwards. Once it has caught up, the file is polled every
+//! `FOLLOW_POLL_MS` and new bytes are sent as they appear. The stream ends
+//! after `FOLLOW_MAX_SECS` or when the file is deleted through the API; a
+//! client that goes away is noticed the next time there is something to
+//! send.
+//!
+//! A file that shrinks is taken to have been truncated and is followed again
+//! from the start. A file replaced under the same name (log rotation) is
+//! reopened and followed from the start of the new file.
+
+use crate::{find_file, stored_path, AppState};
+use actix_web::http::header;
+use actix_web::{web, HttpResponse};
+use futures_util::stream;
+use serde::Deserialize;
+use std::io::SeekFrom;
+use std::path::PathBuf;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::time::Instant;
+
+#[derive(Deserialize)]
+pub struct FollowQuery {
+    /// Byte offset to start from; past the end means only new bytes.
+    #[serde(default)]
+    offset: u64,
+}
+
+/// Identifies the file behind a path, to notice it being replaced.
+#[cfg(unix)]
+fn identity(meta: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn identity(_meta: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+struct Follower {
+    data: web::Data<AppState>,
+    id: String,
+    path: PathBuf,
+    file: tokio::fs::File,
+    identity: Option<(u64, u64)>,
+    pos: u64,
+    deadline: Instant,
+}
+
+impl Follower {
+    /// Waits for and returns the next bytes, or `None` once following ends.
+    async fn next_chunk(&mut self) -> std::io::Result<Option<web::Bytes>> {
+        let config = &self.data.config;
+        loop {
+            let deleted = !self
+                .data
+                .files
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|f| f.id == self.id);
+            if deleted || Instant::now() >= self.deadline {
+                return Ok(None);
+            }
+            // A rotated-away file that has not been recreated yet is just
+            // waited for.
+            if let Ok(meta) = tokio::fs::metadata(&self.path).await {
+                if identity(&meta) != self.identity {
+                    self.file = tokio::fs::File::open(&self.path).await?;
+                    self.identity = identity(&self.file.metadata().await?);
+                    self.pos = 0;
+                } else if meta.len() < self.pos {
+                    self.file.seek(SeekFrom::Start(0)).await?;
+                    self.pos = 0;
+                }
+                if meta.len() > self.pos {
+                    let want = (meta.len() - self.pos).min(config.download_chunk_size as u64);
+                    let mut buf = vec![0u8; want as usize];
+                    let n = self.file.read(&mut buf).await?;
+                    if n > 0 {
+                        buf.truncate(n);
+                        self.pos += n as u64;
+                        return Ok(Some(web::Bytes::from(buf)));
+                    }
+                }
+            }
+            tokio::time::sleep_until(self.deadline.min(Instant::now() + config.follow_poll)).await;
+        }
+    }
+}
+
+pub async fn follow_file(
+    path: web::Path<String>,
+    query: web::Query<FollowQuery>,
+    data: web::Data<AppState>,
+) -> HttpResponse {
+    let Some(info) = find_file(&data, &path.into_inner()) else {
+        return HttpResponse::NotFound().json(serde_json::json!({"error": "File not found"}));
+    };
+    let Some(filepath) = stored_path(&data.config, &info.name) else {
+        return HttpResponse::NotFound().json(serde_json::json!({"error": "File not found"}));
+    };
+    let opened = async {
+        let mut file = tokio::fs::File::open(&filepath).await?;
+        let meta = file.metadata().await?;
+        let pos = query.offset.min(meta.len());
+        file.seek(SeekFrom::Start(pos)).await?;
+        std::io::Result::Ok((file, identity(&meta), pos))
+    };
+    let (file, identity, pos) = match opened.await {
+        Ok(opened) => opened,
+        Err(e) => {
+            return HttpResponse::InternalServerError()
+                .json(serde_json::json!({"error": format!("Failed to open file: {}", e)}));
+        }
+    };
+
+    let guard = data.downloads.start(&info.name);
+    let follower = Follower {
+        deadline: Instant::now() + data.config.follow_max_duration,
+        data: data.clone(),
+        id: info.id,
+        path: filepath,
+        file,
+        identity,
+        pos,
+    };
+    let body = stream::try_unfold(follower, |mut f| async move {
+        Ok::<_, std::io::Error>(f.next_chunk().await?.map(|chunk| (chunk, f)))
+    });
+    HttpResponse::Ok()
+        .insert_header(("Content-Type", info.mime_type))
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .insert_header(("X-Accel-Buffering", "no"))
+        .insert_header(("X-Follow-Offset", pos.to_string()))
+        .streaming(guard.attach(body))
+}
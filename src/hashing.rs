@@ -0,0 +1,88 @@
+//! Content hashing for uploads.
+
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    Sha256,
+    Blake3,
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "sha256" | "sha-256" => Ok(HashAlgorithm::Sha256),
+            "blake3" => Ok(HashAlgorithm::Blake3),
+            other => Err(format!(
+                "unknown hash algorithm '{}' (expected sha256 or blake3)",
+                other
+            )),
+        }
+    }
+}
+
+/// A digest together with the algorithm that produced it, so it can be
+/// recomputed the same way later.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checksum {
+    pub algorithm: HashAlgorithm,
+    pub digest: String,
+}
+
+/// Incremental hasher fed with upload chunks as they arrive.
+pub enum Hasher {
+    Sha256(sha2::Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    pub fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+            HashAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(h) => h.update(data),
+            Hasher::Blake3(h) => {
+                h.update(data);
+            }
+        }
+    }
+
+    pub fn finalize(self) -> Checksum {
+        match self {
+            Hasher::Sha256(h) => Checksum {
+                algorithm: HashAlgorithm::Sha256,
+                digest: format!("{:x}", h.finalize()),
+            },
+            Hasher::Blake3(h) => Checksum {
+                algorithm: HashAlgorithm::Blake3,
+                digest: h.finalize().to_hex().to_string(),
+            },
+        }
+    }
+}
+
+/// Hashes a whole file with `algorithm`. Blocking.
+pub fn hash_file(path: &Path, algorithm: HashAlgorithm) -> std::io::Result<Checksum> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Hasher::new(algorithm);
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            return Ok(hasher.finalize());
+        }
+        hasher.update(&buf[..n]);
+    }
+}
@@ -0,0 +1,138 @@
+//! Optional `Host` allowlist (`ALLOWED_HOSTS`) against host-header attacks
+//! and DNS rebinding when the server is reachable without a proxy.
+//!
+//! The host checked is the one absolute links are built from: the `Host`
+//! header, or `X-Forwarded-Host` when the request comes through a proxy in
+//! `TRUSTED_PROXIES` (see `proxy.rs`). A client's own `X-Forwarded-Host` is
+//! ignored, since a DNS-rebinding page can set it. An entry matches that
+//! host exactly; an entry without a port matches the host on any port, and
+//! an entry starting with `.` also matches every subdomain.
+
+use crate::{proxy, AppState};
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, HttpResponse};
+
+/// Splits an optional `:port` off a host, leaving IPv6 literals intact.
+fn without_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((name, port))
+            if !port.is_empty()
+                && port.bytes().all(|b| b.is_ascii_digit())
+                && (!name.contains(':') || name.ends_with(']')) =>
+        {
+            name
+        }
+        _ => host,
+    }
+}
+
+fn is_allowed(allowed: &[String], host: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    let name = without_port(&host);
+    allowed.iter().any(|entry| match entry.strip_prefix('.') {
+        Some(domain) => name == domain || name.ends_with(entry.as_str()),
+        None => *entry == host || *entry == name,
+    })
+}
+
+pub async fn check<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, actix_web::Error> {
+    let host = match req.app_data::<web::Data<AppState>>() {
+        Some(data) if !data.config.allowed_hosts.is_empty() => {
+            proxy::host(req.head(), &data.config.trusted_proxies)
+                .filter(|host| is_allowed(&data.config.allowed_hosts, host))
+        }
+        _ => return Ok(next.call(req).await?.map_into_left_body()),
+    };
+    if host.is_some() {
+        return Ok(next.call(req).await?.map_into_left_body());
+    }
+    let res = HttpResponse::BadRequest().json(serde_json::json!({"error": "Invalid Host header"}));
+    Ok(req.into_response(res).map_into_right_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{middleware, App};
+
+    async fn status(allowed: &str, trusted: &[&str], headers: &[(&str, &str)]) -> u16 {
+        let mut config = testing::config();
+        config.allowed_hosts = allowed.split(',').map(str::to_string).collect();
+        config.trusted_proxies = trusted.iter().map(|p| p.parse().unwrap()).collect();
+        let app = init_service(
+            App::new()
+                .app_data(testing::state(config))
+                .wrap(middleware::from_fn(check))
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let mut req = TestRequest::get()
+            .uri("/")
+            .peer_addr("127.0.0.1:4000".parse().unwrap());
+        for &header in headers {
+            req = req.insert_header(header);
+        }
+        call_service(&app, req.to_request()).await.status().as_u16()
+    }
+
+    #[test]
+    fn matches_entries() {
+        let allowed = ["files.example.com".to_string(), ".example.org".to_string()];
+        assert!(is_allowed(&allowed, "files.example.com"));
+        assert!(is_allowed(&allowed, "FILES.example.com:8080"));
+        assert!(is_allowed(&allowed, "example.org"));
+        assert!(is_allowed(&allowed, "a.b.example.org"));
+        assert!(!is_allowed(&allowed, "evil.test"));
+        assert!(!is_allowed(&allowed, "notexample.org"));
+        assert!(!is_allowed(&["[::1]:8080".to_string()], "[::1]"));
+        assert!(is_allowed(&["[::1]".to_string()], "[::1]:8080"));
+    }
+
+    #[actix_web::test]
+    async fn checks_the_host_header() {
+        let allowed = "files.example.com";
+        assert_eq!(
+            status(allowed, &[], &[("Host", "files.example.com")]).await,
+            200
+        );
+        assert_eq!(status(allowed, &[], &[("Host", "evil.test")]).await, 400);
+        assert_eq!(status(allowed, &[], &[]).await, 400);
+    }
+
+    #[actix_web::test]
+    async fn ignores_forwarded_host_from_clients() {
+        let allowed = "files.example.com";
+        let forged = [
+            ("Host", "evil.test"),
+            ("X-Forwarded-Host", "files.example.com"),
+        ];
+        assert_eq!(status(allowed, &[], &forged).await, 400);
+        let forged = [
+            ("Host", "files.example.com"),
+            ("X-Forwarded-Host", "evil.test"),
+        ];
+        assert_eq!(status(allowed, &[], &forged).await, 200);
+    }
+
+    #[actix_web::test]
+    async fn uses_forwarded_host_from_trusted_proxies() {
+        let allowed = "files.example.com";
+        let proxied = [
+            ("Host", "app:8080"),
+            ("X-Forwarded-Host", "files.example.com"),
+        ];
+        assert_eq!(status(allowed, &["127.0.0.1"], &proxied).await, 200);
+        let proxied = [
+            ("Host", "files.example.com"),
+            ("X-Forwarded-Host", "evil.test"),
+        ];
+        assert_eq!(status(allowed, &["127.0.0.1"], &proxied).await, 400);
+    }
+}
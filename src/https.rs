@@ -0,0 +1,163 @@
+//! Optional HTTPS enforcement for deployments behind a TLS-terminating proxy.
+//!
+//! The server itself only speaks plain HTTP, so "is this request secure" is
+//! the scheme reported in `X-Forwarded-Proto` by a proxy in `TRUSTED_PROXIES`.
+//! Without one, every request counts as plain HTTP.
+
+use crate::config::HttpsPolicy;
+use crate::{proxy, AppState};
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::{web, HttpResponse};
+
+pub async fn enforce<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, actix_web::Error> {
+    let Some(data) = req.app_data::<web::Data<AppState>>().cloned() else {
+        return Ok(next.call(req).await?.map_into_left_body());
+    };
+    let trusted = &data.config.trusted_proxies;
+    let secure = proxy::scheme(req.head(), trusted) == "https";
+
+    if !secure {
+        match data.config.https_policy {
+            HttpsPolicy::Off => {}
+            HttpsPolicy::Redirect => {
+                let base = match data.config.public_base_url.as_deref() {
+                    Some(url) if url.starts_with("https://") => {
+                        url.trim_end_matches('/').to_string()
+                    }
+                    _ => format!(
+                        "https://{}",
+                        proxy::host(req.head(), trusted)
+                            .unwrap_or_else(|| req.app_config().host().to_string())
+                    ),
+                };
+                let target = match req.uri().path_and_query() {
+                    Some(pq) => format!("{}{}", base, pq),
+                    None => format!("{}/", base),
+                };
+                let res = HttpResponse::PermanentRedirect()
+                    .insert_header((header::LOCATION, target))
+                    .finish();
+                return Ok(req.into_response(res).map_into_right_body());
+            }
+            HttpsPolicy::Reject => {
+                let res = HttpResponse::UpgradeRequired()
+                    .insert_header((header::UPGRADE, "TLS/1.2, HTTP/1.1"))
+                    .insert_header((header::CONNECTION, "Upgrade"))
+                    .json(serde_json::json!({"error": "HTTPS required"}));
+                return Ok(req.into_response(res).map_into_right_body());
+            }
+        }
+    }
+
+    let mut res = next.call(req).await?;
+    if secure && data.config.hsts_max_age > 0 {
+        let value = format!("max-age={}", data.config.hsts_max_age);
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            res.headers_mut()
+                .insert(header::STRICT_TRANSPORT_SECURITY, value);
+        }
+    }
+    Ok(res.map_into_left_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{config, state};
+    use crate::Config;
+    use actix_web::middleware::from_fn;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::App;
+
+    async fn call(config: Config, req: TestRequest) -> ServiceResponse {
+        let app = init_service(
+            App::new()
+                .app_data(state(config))
+                .wrap(from_fn(enforce))
+                .route("/api/files", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        call_service(&app, req.to_request())
+            .await
+            .map_into_boxed_body()
+    }
+
+    fn proxied(proto: &str) -> TestRequest {
+        TestRequest::get()
+            .uri("/api/files?page=2")
+            .insert_header((header::HOST, "files.example"))
+            .insert_header(("X-Forwarded-Proto", proto))
+            .peer_addr("10.0.0.1:1234".parse().unwrap())
+    }
+
+    fn behind_proxy(https_policy: HttpsPolicy) -> Config {
+        Config {
+            https_policy,
+            hsts_max_age: 600,
+            trusted_proxies: vec!["10.0.0.0/8".parse().unwrap()],
+            ..config()
+        }
+    }
+
+    #[actix_web::test]
+    async fn redirects_plain_http_to_the_same_path() {
+        let res = call(behind_proxy(HttpsPolicy::Redirect), proxied("http")).await;
+        assert_eq!(res.status(), 308);
+        assert_eq!(
+            res.headers().get(header::LOCATION).unwrap(),
+            "https://files.example/api/files?page=2"
+        );
+
+        let config = Config {
+            public_base_url: Some("https://cdn.example/".to_string()),
+            ..behind_proxy(HttpsPolicy::Redirect)
+        };
+        let res = call(config, proxied("http")).await;
+        assert_eq!(
+            res.headers().get(header::LOCATION).unwrap(),
+            "https://cdn.example/api/files?page=2"
+        );
+    }
+
+    #[actix_web::test]
+    async fn rejects_plain_http() {
+        let res = call(behind_proxy(HttpsPolicy::Reject), proxied("http")).await;
+        assert_eq!(res.status(), 426);
+        assert!(res.headers().get(header::UPGRADE).is_some());
+    }
+
+    #[actix_web::test]
+    async fn https_passes_with_hsts() {
+        let res = call(behind_proxy(HttpsPolicy::Reject), proxied("https")).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(
+            res.headers()
+                .get(header::STRICT_TRANSPORT_SECURITY)
+                .unwrap(),
+            "max-age=600"
+        );
+    }
+
+    #[actix_web::test]
+    async fn forwarded_proto_from_untrusted_peers_is_ignored() {
+        let config = Config {
+            trusted_proxies: Vec::new(),
+            ..behind_proxy(HttpsPolicy::Reject)
+        };
+        let res = call(config, proxied("https")).await;
+        assert_eq!(res.status(), 426);
+
+        let res = call(behind_proxy(HttpsPolicy::Off), proxied("http")).await;
+        assert_eq!(res.status(), 200);
+        assert!(res
+            .headers()
+            .get(header::STRICT_TRANSPORT_SECURITY)
+            .is_none());
+    }
+}
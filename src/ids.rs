@@ -0,0 +1,87 @@
+//! File ids that survive restarts.
+//!
+//! Records are kept in memory, but links by id (downloads, signed URLs,
+//! `Surrogate-Key` purges) must keep working after a restart. So each stored
+//! file's id is also written to `uploads/.ids/<name>`, and the startup scan
+//! reads it back instead of making up a new one. Files that have none, such
+//! as files copied into the directory by hand, get a new id on their first
+//! scan, which is then kept.
+
+use crate::{logging, UPLOAD_DIR};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+const ID_DIR: &str = ".ids";
+
+fn id_path(name: &str) -> PathBuf {
+    Path::new(UPLOAD_DIR).join(ID_DIR).join(name)
+}
+
+fn write(name: &str, id: &str) -> io::Result<()> {
+    fs::create_dir_all(Path::new(UPLOAD_DIR).join(ID_DIR))?;
+    let path = id_path(name);
+    let tmp = path.with_file_name(format!(".{}.tmp", name));
+    fs::write(&tmp, id)?;
+    fs::rename(&tmp, &path)
+}
+
+/// Remembers `id` for the stored file `name`. A failure is logged: the file
+/// is still served, it just gets a new id after a restart.
+pub fn save(name: &str, id: &str) {
+    if let Err(e) = write(name, id) {
+        logging::event(
+            "warn",
+            "failed to persist file id",
+            serde_json::json!({"file": name, "error": e.to_string()}),
+        );
+    }
+}
+
+/// The id of the stored file `name`, assigning one if it has none yet.
+pub fn load(name: &str) -> String {
+    let saved = fs::read_to_string(id_path(name)).ok();
+    if let Some(id) = saved
+        .as_deref()
+        .map(str::trim)
+        .filter(|id| Uuid::parse_str(id).is_ok())
+    {
+        return id.to_string();
+    }
+    let id = Uuid::new_v4().to_string();
+    save(name, &id);
+    id
+}
+
+pub fn remove(name: &str) {
+    let _ = fs::remove_file(id_path(name));
+}
+
+/// Moves the id of a renamed file along with it.
+pub fn rename(from: &str, to: &str) {
+    let _ = fs::rename(id_path(from), id_path(to));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::unique;
+
+    #[test]
+    fn ids_are_kept_and_follow_renames() {
+        let (name, renamed) = (unique("a.txt"), unique("b.txt"));
+        let id = load(&name);
+        assert_eq!(load(&name), id);
+
+        rename(&name, &renamed);
+        assert_eq!(load(&renamed), id);
+        assert_ne!(load(&name), id);
+
+        save(&name, &id);
+        assert_eq!(load(&name), id);
+        remove(&name);
+        remove(&renamed);
+        assert!(!id_path(&name).exists() && !id_path(&renamed).exists());
+    }
+}
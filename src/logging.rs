@@ -0,0 +1,152 @@
+//! Line-oriented logging in either human-readable or JSON form.
+//!
+//! Every log line is an event with a message and a flat set of structured
+//! fields. In JSON mode each line is one object (`ts`, `level`, `msg` plus the
+//! fields), ready for ELK/Loki; in human mode the fields follow the message as
+//! `key=value` pairs.
+
+use crate::{proxy, AppState};
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::web;
+use chrono::{SecondsFormat, Utc};
+use serde_json::{Map, Value};
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::Instant;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Human,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "human" | "text" | "pretty" => Ok(LogFormat::Human),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!(
+                "unknown log format '{}' (expected human or json)",
+                other
+            )),
+        }
+    }
+}
+
+static FORMAT: OnceLock<LogFormat> = OnceLock::new();
+
+pub fn init(format: LogFormat) {
+    let _ = FORMAT.set(format);
+}
+
+pub fn format() -> LogFormat {
+    FORMAT.get().copied().unwrap_or(LogFormat::Human)
+}
+
+/// Writes one log line. `fields` should be a JSON object; anything else is
+/// logged under a `data` key.
+pub fn event(level: &str, msg: &str, fields: Value) {
+    let ts = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+    let fields = match fields {
+        Value::Object(map) => map,
+        Value::Null => Map::new(),
+        other => Map::from_iter([("data".to_string(), other)]),
+    };
+
+    match format() {
+        LogFormat::Json => {
+            let mut line = Map::new();
+            line.insert("ts".into(), ts.into());
+            line.insert("level".into(), level.into());
+            line.insert("msg".into(), msg.into());
+            line.extend(fields);
+            println!("{}", Value::Object(line));
+        }
+        LogFormat::Human => {
+            let mut line = format!("{} {:<5} {}", ts, level.to_uppercase(), msg);
+            for (key, value) in fields {
+                match value {
+                    Value::Null => continue,
+                    Value::String(s) => line.push_str(&format!(" {}={}", key, s)),
+                    other => line.push_str(&format!(" {}={}", key, other)),
+                }
+            }
+            println!("{}", line);
+        }
+    }
+}
+
+/// Access-log middleware: one `request` event per response, tagged with a
+/// request id that is also returned to the client as `X-Request-Id`.
+pub async fn access_log(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let started = Instant::now();
+    let request_id = req
+        .headers()
+        .get("x-request-id")
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty() && v.len() <= 128)
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    let method = req.method().to_string();
+    let path = req.path().to_string();
+    let trusted = req
+        .app_data::<web::Data<AppState>>()
+        .map_or(&[][..], |data| &data.config.trusted_proxies);
+    let client_ip = proxy::client_addr(req.head(), trusted)
+        .map_or_else(|| "-".to_string(), |ip| ip.to_string());
+    let bytes_in = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+
+    let mut res = next.call(req).await?;
+
+    let status = res.status();
+    let bytes = match res.response().body().size() {
+        BodySize::Sized(n) => Some(n),
+        _ => None,
+    };
+    let error = (status.is_client_error() || status.is_server_error()).then(|| {
+        status
+            .canonical_reason()
+            .unwrap_or("error")
+            .to_ascii_lowercase()
+            .replace(' ', "_")
+    });
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        res.headers_mut()
+            .insert(header::HeaderName::from_static("x-request-id"), value);
+    }
+
+    event(
+        if status.is_server_error() {
+            "error"
+        } else {
+            "info"
+        },
+        "request",
+        serde_json::json!({
+            "request_id": request_id,
+            "method": method,
+            "path": path,
+            "status": status.as_u16(),
+            "latency_ms": (started.elapsed().as_secs_f64() * 1_000_000.0).round() / 1000.0,
+            "client_ip": client_ip,
+            "bytes": bytes,
+            "bytes_in": bytes_in,
+            "error": error,
+        }),
+    );
+
+    Ok(res)
+}
@@ -1,150 +1,1150 @@
 use actix_cors::Cors;
 use actix_multipart::Multipart;
-use actix_web::{web, App, HttpResponse, HttpServer};
+use actix_web::http::header::{
+    self, Charset, ContentDisposition, DispositionParam, DispositionType, ExtendedValue,
+};
+use actix_web::{middleware, web, App, HttpRequest, HttpResponse, HttpServer};
 use chrono::{DateTime, Utc};
-use futures_util::StreamExt;
+use futures_util::stream::{self, Stream, StreamExt};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
 use serde::{Deserialize, Serialize};
 use std::fs;
-use std::io::Write;
-use std::path::PathBuf;
+use std::io::{SeekFrom, Write};
+use std::path::{Path, PathBuf};
 use std::sync::Mutex;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use unicode_normalization::UnicodeNormalization;
 use uuid::Uuid;
 
+mod admin;
+mod append;
+mod casing;
+mod changes;
+mod compare;
+mod conditional;
+mod config;
+mod confirm;
+mod disk_report;
+mod downloads;
+mod embed;
+mod feed;
+mod follow;
+mod hashing;
+mod hosts;
+mod https;
+mod ids;
+mod logging;
+mod metadata;
+mod mimetypes;
+mod placeholders;
+mod policy;
+mod precompress;
+mod proxy;
+mod pwa;
+mod range;
+mod scrub;
+mod server_header;
+mod signing;
+mod status;
+mod storage;
+mod tags;
+#[cfg(test)]
+mod testing;
+mod textstats;
+mod update;
+mod verify;
+mod versions;
+
+use append::AppendLocks;
+use changes::Tombstones;
+use config::{
+    CaseNames, Config, ExtensionlessPolicy, SizeMismatchPolicy, SymlinkPolicy, UnsafeNamePolicy,
+    VerifyDownloads,
+};
+use confirm::ConfirmationTokens;
+use downloads::ActiveDownloads;
+use hashing::{Checksum, Hasher};
+use logging::LogFormat;
+use policy::{PolicyDecision, PolicyRequest};
+use range::RangeRequest;
+use scrub::ScrubReport;
+use signing::Signer;
+use status::ActiveUploads;
+use storage::StorageHealth;
+use textstats::TextStats;
+use versions::Version;
+
+#[cfg(not(test))]
 const UPLOAD_DIR: &str = "./uploads";
+/// Tests share one directory and keep apart by using unique file names (see
+/// `testing.rs`).
+#[cfg(test)]
+const UPLOAD_DIR: &str = "./target/test-uploads";
 const MAX_FILE_SIZE: usize = 10 * 1024 * 1024 * 1024; // 10 GB
+/// Longest `tags` or `description` form field accepted with an upload.
+const MAX_FORM_FIELD: usize = 8 * 1024;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 struct FileInfo {
     id: String,
     name: String,
     size: u64,
+    // Aliases let camelCase clients (see `casing.rs`) send records back.
+    #[serde(alias = "mimeType")]
     mime_type: String,
+    #[serde(alias = "uploadedAt")]
     uploaded_at: DateTime<Utc>,
+    /// Digest computed while the upload streamed in. Files discovered on disk
+    /// at startup have none until something hashes them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    checksum: Option<Checksum>,
+    /// Line/word/byte counts for text files, filled in on first request.
+    #[serde(default, skip_serializing_if = "Option::is_none", alias = "textStats")]
+    text_stats: Option<TextStats>,
+    /// Pixel dimensions of images, read from the file header.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    height: Option<u32>,
+    /// Extra disk space taken by precompressed variants (`PRECOMPRESS`).
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "variantBytes"
+    )]
+    variant_bytes: Option<u64>,
+    /// Client that uploaded the file (see `client_ip`), for per-client
+    /// quotas. Never serialized, so listings do not reveal addresses.
+    #[serde(default, skip_serializing)]
+    owner: Option<String>,
+    /// Verdict of the external policy service (`POLICY_URL`), if one is set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    policy: Option<PolicyDecision>,
+    /// Free-form labels, kept sorted (see `tags.rs`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    /// Free text sent with the upload in a `description` form field, or set
+    /// later with `PATCH`/`PUT /api/files/{id}`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    /// When the scrubber last found the file matching its checksum.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "lastVerified"
+    )]
+    last_verified: Option<DateTime<Utc>>,
+    /// When the scrubber found the file no longer matching its checksum.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "checksumMismatch"
+    )]
+    checksum_mismatch: Option<DateTime<Utc>>,
+    /// When the contents or tags last changed after the upload (appends,
+    /// tagging), for the changes feed.
+    #[serde(default, skip_serializing_if = "Option::is_none", alias = "modifiedAt")]
+    modified_at: Option<DateTime<Utc>>,
+    /// Previous contents kept on replacement (`MAX_VERSIONS`), oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    versions: Vec<Version>,
+    /// `pending` for a placeholder whose upload has not completed (see
+    /// `placeholders.rs`).
+    #[serde(default)]
+    status: FileStatus,
+    /// Whole-file downloads since the server started. Range requests are
+    /// not counted.
+    #[serde(default)]
+    downloads: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum FileStatus {
+    #[default]
+    Ready,
+    Pending,
+}
+
+impl FileInfo {
+    /// Whether the record stands for a stored file, rather than a
+    /// placeholder with nothing on disk yet.
+    fn is_ready(&self) -> bool {
+        self.status == FileStatus::Ready
+    }
 }
 
 struct AppState {
+    config: Config,
     files: Mutex<Vec<FileInfo>>,
+    confirmations: ConfirmationTokens,
+    downloads: ActiveDownloads,
+    uploads: ActiveUploads,
+    signer: Signer,
+    storage: StorageHealth,
+    scrub: ScrubReport,
+    appends: AppendLocks,
+    tombstones: Tombstones,
+    /// Whether file names are compared case-insensitively
+    /// (`CASE_INSENSITIVE_NAMES`, resolved at startup).
+    fold_case: bool,
 }
 
 impl AppState {
-    fn new() -> Self {
+    fn new(config: Config) -> Self {
         let mut files = Vec::new();
         // Load existing files from disk
         if let Ok(entries) = fs::read_dir(UPLOAD_DIR) {
             for entry in entries.flatten() {
-                let path = entry.path();
-                if path.is_file() {
-                    let filename = path.file_name().unwrap().to_string_lossy().to_string();
+                let filename = entry.file_name().to_string_lossy().to_string();
+                if let Some(path) = stored_path(&config, &filename) {
                     if filename.starts_with('.') {
                         continue;
                     }
                     let metadata = fs::metadata(&path).unwrap();
-                    let mime = mime_guess::from_path(&path)
-                        .first_or_octet_stream()
-                        .to_string();
+                    let mime = mimetypes::guess(&config, &path);
+                    let (width, height) = image_dimensions(&config, &path, &mime);
+                    let variant_bytes = precompress::disk_usage(&filename);
+                    let versions = versions::scan(&filename);
                     files.push(FileInfo {
-                        id: Uuid::new_v4().to_string(),
+                        id: ids::load(&filename),
                         name: filename,
                         size: metadata.len(),
                         mime_type: mime,
                         uploaded_at: metadata
                             .modified()
-                            .map(|t| DateTime::<Utc>::from(t))
+                            .map(DateTime::<Utc>::from)
                             .unwrap_or_else(|_| Utc::now()),
+                        width,
+                        height,
+                        variant_bytes,
+                        versions,
+                        ..Default::default()
                     });
                 }
             }
         }
-        files.sort_by(|a, b| b.uploaded_at.cmp(&a.uploaded_at));
+        Self::with_files(config, files)
+    }
+
+    fn with_files(config: Config, mut files: Vec<FileInfo>) -> Self {
+        files.sort_by(listing_order);
+        let signer = Signer::new(config.signing_key.as_deref());
+        let fold_case = match config.case_insensitive_names {
+            CaseNames::Auto => storage::is_case_insensitive(),
+            CaseNames::Always => true,
+            CaseNames::Never => false,
+        };
         AppState {
+            config,
             files: Mutex::new(files),
+            confirmations: ConfirmationTokens::new(),
+            downloads: ActiveDownloads::new(),
+            uploads: ActiveUploads::new(),
+            signer,
+            storage: StorageHealth::new(),
+            scrub: ScrubReport::new(),
+            appends: AppendLocks::new(),
+            tombstones: Tombstones::new(),
+            fold_case,
         }
     }
 }
 
 async fn upload_file(
+    req: HttpRequest,
     mut payload: Multipart,
     data: web::Data<AppState>,
 ) -> HttpResponse {
     let mut uploaded: Vec<FileInfo> = Vec::new();
+    let mut form_tags = Vec::new();
+    let mut description = None;
+    let header_name = header_filename(&req);
+    let deadline = upload_deadline(&data.config);
+    let client = client_ip(&req);
+    let precondition = match upload_precondition(&req) {
+        Ok(p) => p,
+        Err(res) => return res,
+    };
+    let expected_size = match expected_size(&req) {
+        Ok(size) => size,
+        Err(res) => return res,
+    };
 
-    while let Some(Ok(mut field)) = payload.next().await {
-        let content_disposition = field.content_disposition().cloned();
-        let filename = content_disposition
-            .as_ref()
-            .and_then(|cd| cd.get_filename().map(|f| sanitize_filename(f)))
-            .unwrap_or_else(|| format!("upload_{}", Uuid::new_v4()));
-
-        let file_id = Uuid::new_v4().to_string();
-        let filepath = PathBuf::from(UPLOAD_DIR).join(&filename);
-
-        // Handle duplicate names
-        let final_path = if filepath.exists() {
-            let stem = filepath.file_stem().unwrap().to_string_lossy().to_string();
-            let ext = filepath
-                .extension()
-                .map(|e| format!(".{}", e.to_string_lossy()))
-                .unwrap_or_default();
-            let new_name = format!("{}_{}{}", stem, &file_id[..8], ext);
-            PathBuf::from(UPLOAD_DIR).join(&new_name)
-        } else {
-            filepath
+    loop {
+        let mut field = match next_before(&mut payload, deadline).await {
+            Ok(Some(Ok(field))) => field,
+            Ok(_) => break,
+            Err(res) => return res,
+        };
+        let field_name = field.name().unwrap_or_default().to_string();
+        if !data.config.upload_field_names.contains(&field_name) {
+            // Form data travelling alongside the files; never stored on disk.
+            let keep = matches!(field_name.as_str(), "tags" | "description");
+            let mut value = Vec::new();
+            loop {
+                match next_before(&mut field, deadline).await {
+                    Ok(Some(Ok(chunk))) if keep => {
+                        if value.len() + chunk.len() > MAX_FORM_FIELD {
+                            return HttpResponse::BadRequest().json(serde_json::json!({
+                                "error": format!("Form field '{}' is too long", field_name)
+                            }));
+                        }
+                        value.extend_from_slice(&chunk);
+                    }
+                    Ok(Some(Ok(_))) => {}
+                    Ok(_) => break,
+                    Err(res) => return res,
+                }
+            }
+            let value = String::from_utf8_lossy(&value);
+            if field_name == "description" {
+                description = Some(value.trim().to_string()).filter(|d| !d.is_empty());
+            } else if keep {
+                for tag in value.split(',').filter(|t| !t.trim().is_empty()) {
+                    match tags::normalize(tag) {
+                        Ok(tag) => form_tags.push(tag),
+                        Err(res) => return res,
+                    }
+                }
+            }
+            continue;
+        }
+
+        // The part's own filename wins; X-Filename only names parts sent
+        // without one.
+        let requested = field
+            .content_disposition()
+            .and_then(|cd| cd.get_filename())
+            .filter(|name| !name.is_empty())
+            .map(str::to_string)
+            .or_else(|| header_name.clone());
+        let mime_type = field.content_type().map(|m| m.essence_str().to_string());
+        if matches!(precondition, Precondition::Placeholder(_)) && !uploaded.is_empty() {
+            return HttpResponse::BadRequest()
+                .json(serde_json::json!({"error": "A placeholder takes exactly one file"}));
+        }
+        if expected_size.is_some() && !uploaded.is_empty() {
+            return HttpResponse::BadRequest()
+                .json(serde_json::json!({"error": "X-Expected-Size applies to a single file"}));
+        }
+
+        let declared = Declared {
+            name: requested.as_deref(),
+            mime_type: mime_type.as_deref(),
+            size: expected_size,
         };
+        match store_upload(
+            &data,
+            &declared,
+            &mut field,
+            deadline,
+            &precondition,
+            &client,
+        )
+        .await
+        {
+            Ok(info) => uploaded.push(info),
+            Err(res) => return res,
+        }
+    }
 
-        let final_name = final_path
-            .file_name()
-            .unwrap()
-            .to_string_lossy()
-            .to_string();
+    // Form fields may come after the files they describe, so they are
+    // applied once every part has been read.
+    if !form_tags.is_empty() || description.is_some() {
+        let mut files = data.files.lock().unwrap();
+        for info in &mut uploaded {
+            info.tags = tags::apply(&info.tags, &form_tags, &[]);
+            if description.is_some() {
+                info.description = description.clone();
+            }
+            if let Some(f) = files.iter_mut().find(|f| f.id == info.id) {
+                f.tags = info.tags.clone();
+                f.description = info.description.clone();
+            }
+        }
+    }
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "files": uploaded
+    }))
+}
+
+/// `PUT /api/upload`: the request body is the file. Its name comes from the
+/// `X-Filename` header.
+async fn upload_raw(
+    req: HttpRequest,
+    mut payload: web::Payload,
+    data: web::Data<AppState>,
+) -> HttpResponse {
+    let requested = header_filename(&req);
+    let mime_type = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.split(';').next().unwrap_or_default().trim().to_string());
+
+    let precondition = match upload_precondition(&req) {
+        Ok(p) => p,
+        Err(res) => return res,
+    };
+    let expected_size = match expected_size(&req) {
+        Ok(size) => size,
+        Err(res) => return res,
+    };
+
+    let declared = Declared {
+        name: requested.as_deref(),
+        mime_type: mime_type.as_deref(),
+        size: expected_size,
+    };
+    match store_upload(
+        &data,
+        &declared,
+        &mut payload,
+        upload_deadline(&data.config),
+        &precondition,
+        &client_ip(&req),
+    )
+    .await
+    {
+        Ok(info) => {
+            let mut res = HttpResponse::Ok();
+            if let Some(checksum) = &info.checksum {
+                res.insert_header((header::ETAG, format!("\"{}\"", checksum.digest)));
+            }
+            res.json(serde_json::json!({
+                "success": true,
+                "files": [info]
+            }))
+        }
+        Err(res) => res,
+    }
+}
+
+/// Reads the `X-Filename` header, percent-decoding it so non-ASCII names
+/// can be sent.
+fn header_filename(req: &HttpRequest) -> Option<String> {
+    let raw = req.headers().get("x-filename")?.to_str().ok()?.trim();
+    let name = percent_decode_str(raw).decode_utf8().ok()?.to_string();
+    (!name.is_empty()).then_some(name)
+}
+
+/// Upload preconditions, from `If-None-Match` / `If-Match`.
+enum Precondition {
+    None,
+    /// `If-None-Match: *`: create the name only if it is free, instead of
+    /// renaming around an existing file.
+    CreateOnly,
+    /// `If-Match`: replace the existing file of that name in place, only if
+    /// its current ETag is one of these (or the list holds `*`).
+    Replace(Vec<String>),
+    /// `X-Placeholder-Id`: fill this pending placeholder, taking its id and
+    /// name.
+    Placeholder(String),
+}
+
+fn upload_precondition(req: &HttpRequest) -> Result<Precondition, HttpResponse> {
+    let get = |name| {
+        req.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+    };
+    if let Some(id) = get(header::HeaderName::from_static("x-placeholder-id")) {
+        if get(header::IF_MATCH).is_some() || get(header::IF_NONE_MATCH).is_some() {
+            return Err(HttpResponse::BadRequest().json(
+                serde_json::json!({"error": "X-Placeholder-Id cannot be combined with If-Match or If-None-Match"}),
+            ));
+        }
+        return Ok(Precondition::Placeholder(id.to_string()));
+    }
+    match (get(header::IF_MATCH), get(header::IF_NONE_MATCH)) {
+        (None, None) => Ok(Precondition::None),
+        (None, Some("*")) => Ok(Precondition::CreateOnly),
+        (Some(tags), None) => Ok(Precondition::Replace(
+            tags.split(',')
+                .map(|t| t.trim().to_string())
+                .filter(|t| !t.is_empty())
+                .collect(),
+        )),
+        (Some(_), Some(_)) => Err(HttpResponse::BadRequest()
+            .json(serde_json::json!({"error": "Send either If-Match or If-None-Match, not both"}))),
+        (None, Some(_)) => Err(HttpResponse::BadRequest()
+            .json(serde_json::json!({"error": "Uploads only support If-None-Match: *"}))),
+    }
+}
+
+/// Reads `X-Expected-Size`, the size the client says it is about to send.
+/// Multipart bodies carry no per-file size, so this is the only hint for
+/// them; raw uploads can use it to check the file rather than the transfer.
+fn expected_size(req: &HttpRequest) -> Result<Option<u64>, HttpResponse> {
+    let Some(value) = req.headers().get("x-expected-size") else {
+        return Ok(None);
+    };
+    match value.to_str().ok().and_then(|v| v.trim().parse().ok()) {
+        Some(size) => Ok(Some(size)),
+        None => Err(HttpResponse::BadRequest()
+            .json(serde_json::json!({"error": "Invalid X-Expected-Size header"}))),
+    }
+}
+
+fn size_mismatch(expected: u64, received: &str) -> HttpResponse {
+    HttpResponse::BadRequest().json(serde_json::json!({
+        "error": format!("Upload size mismatch: expected {} bytes, received {}", expected, received)
+    }))
+}
+
+fn precondition_failed(name: &str, exists: bool) -> HttpResponse {
+    let error = if exists {
+        format!("File '{}' does not match the precondition", name)
+    } else {
+        format!("File '{}' does not exist", name)
+    };
+    HttpResponse::PreconditionFailed().json(serde_json::json!({ "error": error }))
+}
+
+/// Strong validator for a stored file: its upload checksum when known,
+/// otherwise its size and modification time.
+fn etag(checksum: Option<&Checksum>, meta: &fs::Metadata) -> String {
+    match checksum {
+        Some(c) => format!("\"{}\"", c.digest),
+        None => {
+            let mtime = meta
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_nanos());
+            format!("\"{:x}-{:x}\"", meta.len(), mtime)
+        }
+    }
+}
+
+/// Checks an `If-Match` list against the file currently stored as `name`.
+/// Never matches a file that does not exist.
+fn if_match(files: &[FileInfo], name: &str, tags: &[String]) -> bool {
+    let Ok(meta) = fs::metadata(PathBuf::from(UPLOAD_DIR).join(name)) else {
+        return false;
+    };
+    let checksum = files
+        .iter()
+        .find(|f| f.name == name && f.is_ready())
+        .and_then(|f| f.checksum.as_ref());
+    let current = etag(checksum, &meta);
+    tags.iter().any(|t| t == "*" || *t == current)
+}
+
+/// The stored name that `name` differs from only by case, when names are
+/// compared case-insensitively (`CASE_INSENSITIVE_NAMES`).
+fn case_collision(data: &AppState, files: &[FileInfo], name: &str) -> Option<String> {
+    if !data.fold_case {
+        return None;
+    }
+    let folded = name.to_lowercase();
+    files
+        .iter()
+        .find(|f| f.is_ready() && f.name != name && f.name.to_lowercase() == folded)
+        .map(|f| f.name.clone())
+}
+
+/// Whether `name` is in use by anything but the record `id`: a file on disk
+/// (a deleted file may linger during `DELETE_GRACE_SECS`), or another record,
+/// placeholders included, of the same name or, when names are compared
+/// case-insensitively, one differing only by case.
+fn name_taken(data: &AppState, files: &[FileInfo], name: &str, id: &str) -> bool {
+    let folded = name.to_lowercase();
+    Path::new(UPLOAD_DIR).join(name).exists()
+        || files.iter().any(|f| {
+            f.id != id && (f.name == name || data.fold_case && f.name.to_lowercase() == folded)
+        })
+}
+
+/// `name` if it is free for the record `id`, otherwise `name` suffixed with
+/// the start of the id and then the whole id, before the extension. `None`
+/// when all of them are taken.
+fn free_name(data: &AppState, files: &[FileInfo], name: &str, id: &str) -> Option<String> {
+    let path = Path::new(name);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    let suffixed = |suffix: &str| format!("{}_{}{}", stem, suffix, ext);
+    [name.to_string(), suffixed(&id[..8]), suffixed(id)]
+        .into_iter()
+        .find(|candidate| !name_taken(data, files, candidate, id))
+}
+
+/// `409` for an upload or placeholder whose name is in use under
+/// `UNIQUE_NAMES`.
+fn name_conflict(name: &str) -> HttpResponse {
+    HttpResponse::Conflict().json(serde_json::json!({
+        "error": format!("A file named '{}' already exists", name)
+    }))
+}
+
+/// Bytes stored by `client`, not counting the file named `except`.
+fn client_usage(files: &[FileInfo], client: &str, except: Option<&str>) -> u64 {
+    files
+        .iter()
+        .filter(|f| f.is_ready() && f.owner.as_deref() == Some(client))
+        .filter(|f| Some(f.name.as_str()) != except)
+        .map(|f| f.size)
+        .sum()
+}
+
+fn quota_exceeded(quota: u64) -> HttpResponse {
+    HttpResponse::InsufficientStorage().json(serde_json::json!({
+        "error": format!("Upload would exceed your storage quota of {} bytes", quota)
+    }))
+}
+
+/// When the current upload request must be finished by, per
+/// `UPLOAD_MAX_DURATION_SECS`.
+fn upload_deadline(config: &Config) -> Option<tokio::time::Instant> {
+    config
+        .upload_max_duration
+        .map(|max| tokio::time::Instant::now() + max)
+}
+
+/// Awaits the next item of an upload stream, giving up with `408` once the
+/// request has run past `deadline`.
+async fn next_before<S: Stream + Unpin>(
+    stream: &mut S,
+    deadline: Option<tokio::time::Instant>,
+) -> Result<Option<S::Item>, HttpResponse> {
+    let Some(deadline) = deadline else {
+        return Ok(stream.next().await);
+    };
+    tokio::time::timeout_at(deadline, stream.next())
+        .await
+        .map_err(|_| {
+            HttpResponse::RequestTimeout()
+                .json(serde_json::json!({"error": "Upload took too long"}))
+        })
+}
+
+/// What the client said about a file before sending it.
+struct Declared<'a> {
+    /// Requested name, sanitized by `store_upload`.
+    name: Option<&'a str>,
+    mime_type: Option<&'a str>,
+    /// From `X-Expected-Size`, checked against `SIZE_MISMATCH`.
+    size: Option<u64>,
+}
+
+/// Picks the final name for an upload, streams `body` into place and records
+/// the file, using what the client `declared` about it. `precondition` is
+/// checked once before the body is read and again, under the state lock,
+/// right before the file is moved into place. `client` owns the file for per-client quotas.
+async fn store_upload<S, E>(
+    data: &web::Data<AppState>,
+    declared: &Declared<'_>,
+    body: &mut S,
+    deadline: Option<tokio::time::Instant>,
+    precondition: &Precondition,
+    client: &str,
+) -> Result<FileInfo, HttpResponse>
+where
+    S: Stream<Item = Result<web::Bytes, E>> + Unpin,
+    E: std::fmt::Display,
+{
+    data.storage.require_writable()?;
+    let _active = data.uploads.start();
+
+    // A placeholder's name was sanitized when it was created.
+    let (file_id, placeholder_name) = match precondition {
+        Precondition::Placeholder(id) => {
+            let name = data
+                .files
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|f| f.id == *id && !f.is_ready())
+                .map(|f| f.name.clone())
+                .ok_or_else(placeholders::gone)?;
+            (id.clone(), Some(name))
+        }
+        _ => (Uuid::new_v4().to_string(), None),
+    };
+    let filename = placeholder_name
+        .or_else(|| {
+            declared
+                .name
+                .map(|name| sanitize_filename(&data.config, name))
+        })
+        .unwrap_or_else(|| format!("upload_{}", Uuid::new_v4()));
+    let mut filename = checked_name(&data.config, filename, &file_id, declared.mime_type)?;
+
+    // A replacement of `report.pdf` targets a stored `Report.pdf` under its
+    // stored spelling, so the record and the file on disk stay one.
+    let collision = case_collision(data, &data.files.lock().unwrap(), &filename);
+    if let (Some(existing), Precondition::Replace(_)) = (&collision, &precondition) {
+        filename = existing.clone();
+    }
+    let exists = name_taken(data, &data.files.lock().unwrap(), &filename, &file_id);
+
+    let replace = match precondition {
+        Precondition::None | Precondition::Placeholder(_) => false,
+        Precondition::CreateOnly if exists => {
+            return Err(precondition_failed(&filename, true));
+        }
+        Precondition::CreateOnly => false,
+        Precondition::Replace(tags) => {
+            if !if_match(&data.files.lock().unwrap(), &filename, tags) {
+                return Err(precondition_failed(&filename, exists));
+            }
+            true
+        }
+    };
+
+    // Handle duplicate names. This is only a first pick: another upload of
+    // the same name may finish while this one streams, so it is checked
+    // again under the lock.
+    let mut final_name = if exists && !replace {
+        if data.config.unique_names {
+            return Err(name_conflict(&filename));
+        }
+        free_name(data, &data.files.lock().unwrap(), &filename, &file_id)
+            .ok_or_else(|| name_conflict(&filename))?
+    } else {
+        filename.clone()
+    };
+
+    // A file being replaced stops counting against its owner's quota.
+    let replacing = replace.then(|| final_name.clone());
+    let quota = data.config.client_quota;
+    let used = quota.map_or(0, |_| {
+        client_usage(&data.files.lock().unwrap(), client, replacing.as_deref())
+    });
+
+    // Write to a hidden temp file and rename it into place once complete,
+    // so a crash never leaves a truncated file under the real name.
+    let part_path = part_path(&file_id);
+    let mut file = match fs::File::create(&part_path) {
+        Ok(f) => f,
+        Err(e) if storage::is_unwritable(&e) => {
+            data.storage.set(Some(e.to_string()));
+            return Err(storage::unavailable(&e.to_string()));
+        }
+        Err(e) => {
+            return Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({"error": format!("Failed to create file: {}", e)})));
+        }
+    };
 
-        let mut file = match fs::File::create(&final_path) {
-            Ok(f) => f,
+    let reject_size = declared
+        .size
+        .filter(|_| data.config.size_mismatch == SizeMismatchPolicy::Reject);
+    let mut total_size: u64 = 0;
+    let mut hasher = Hasher::new(data.config.hash_algorithm);
+    loop {
+        let chunk = match next_before(body, deadline).await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(res) => {
+                let _ = fs::remove_file(&part_path);
+                return Err(res);
+            }
+        };
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
             Err(e) => {
-                return HttpResponse::InternalServerError()
-                    .json(serde_json::json!({"error": format!("Failed to create file: {}", e)}));
+                let _ = fs::remove_file(&part_path);
+                return Err(HttpResponse::BadRequest()
+                    .json(serde_json::json!({"error": format!("Upload interrupted: {}", e)})));
             }
         };
+        total_size += chunk.len() as u64;
+        if total_size > MAX_FILE_SIZE as u64 {
+            let _ = fs::remove_file(&part_path);
+            return Err(HttpResponse::PayloadTooLarge()
+                .json(serde_json::json!({"error": "File too large (max 10 GB)"})));
+        }
+        if let Some(quota) = quota.filter(|&q| used + total_size > q) {
+            let _ = fs::remove_file(&part_path);
+            return Err(quota_exceeded(quota));
+        }
+        if let Some(expected) = reject_size.filter(|&e| total_size > e) {
+            let _ = fs::remove_file(&part_path);
+            return Err(size_mismatch(expected, &format!("more than {}", expected)));
+        }
+        if let Err(e) = write_with_retry(&mut file, &chunk, &data.config).await {
+            let _ = fs::remove_file(&part_path);
+            let res = if is_storage_full(&e) {
+                HttpResponse::InsufficientStorage()
+                    .json(serde_json::json!({"error": "Not enough storage space"}))
+            } else {
+                HttpResponse::InternalServerError()
+                    .json(serde_json::json!({"error": format!("Write error: {}", e)}))
+            };
+            return Err(res);
+        }
+        hasher.update(&chunk);
+    }
+    drop(file);
+    if let Some(expected) = declared.size.filter(|&e| e != total_size) {
+        if reject_size.is_some() {
+            let _ = fs::remove_file(&part_path);
+            return Err(size_mismatch(expected, &total_size.to_string()));
+        }
+        logging::event(
+            "warn",
+            "upload size differs from X-Expected-Size",
+            serde_json::json!({"file": final_name, "expected": expected, "received": total_size}),
+        );
+    }
 
-        let mut total_size: u64 = 0;
-        while let Some(Ok(chunk)) = field.next().await {
-            total_size += chunk.len() as u64;
-            if total_size > MAX_FILE_SIZE as u64 {
-                let _ = fs::remove_file(&final_path);
-                return HttpResponse::PayloadTooLarge()
-                    .json(serde_json::json!({"error": "File too large (max 10 GB)"}));
-            }
-            if let Err(e) = file.write_all(&chunk) {
-                let _ = fs::remove_file(&final_path);
-                return HttpResponse::InternalServerError()
-                    .json(serde_json::json!({"error": format!("Write error: {}", e)}));
+    let mime = mimetypes::guess(&data.config, &final_name);
+    let (width, height) = image_dimensions(&data.config, &part_path, &mime);
+    let checksum = hasher.finalize();
+
+    let request = PolicyRequest {
+        name: &final_name,
+        size: total_size,
+        mime_type: &mime,
+        checksum: &checksum,
+        client,
+    };
+    let policy = match policy::check(&data.config, &request).await {
+        Ok(decision) => decision,
+        Err(res) => {
+            let _ = fs::remove_file(&part_path);
+            return Err(res);
+        }
+    };
+
+    let info = {
+        let mut files = data.files.lock().unwrap();
+        // Whether the name picked before streaming was taken meanwhile:
+        // without this, two uploads of one name would both pass and the
+        // second rename would overwrite the first file.
+        let taken = !replace && name_taken(data, &files, &final_name, &file_id);
+        let still_holds = match precondition {
+            Precondition::None => true,
+            Precondition::CreateOnly => !taken,
+            Precondition::Replace(tags) => if_match(&files, &final_name, tags),
+            Precondition::Placeholder(id) => {
+                if !files.iter().any(|f| f.id == *id && !f.is_ready()) {
+                    let _ = fs::remove_file(&part_path);
+                    return Err(placeholders::gone());
+                }
+                true
             }
+        };
+        if !still_holds {
+            let _ = fs::remove_file(&part_path);
+            return Err(precondition_failed(
+                &final_name,
+                taken || Path::new(UPLOAD_DIR).join(&final_name).exists(),
+            ));
+        }
+        if taken {
+            let free = (!data.config.unique_names)
+                .then(|| free_name(data, &files, &filename, &file_id))
+                .flatten();
+            let Some(free) = free else {
+                let _ = fs::remove_file(&part_path);
+                return Err(name_conflict(&final_name));
+            };
+            final_name = free;
+        }
+        let final_path = PathBuf::from(UPLOAD_DIR).join(&final_name);
+        // Other uploads from the same client may have finished meanwhile.
+        if let Some(quota) =
+            quota.filter(|&q| client_usage(&files, client, replacing.as_deref()) + total_size > q)
+        {
+            let _ = fs::remove_file(&part_path);
+            return Err(quota_exceeded(quota));
         }
 
-        let mime = mime_guess::from_path(&final_path)
-            .first_or_octet_stream()
-            .to_string();
+        // A replaced file keeps its id, so links by id stay valid, its tags,
+        // description and its history, to which its current contents are added.
+        let previous = replace
+            .then(|| files.iter().position(|f| f.name == final_name))
+            .flatten();
+        let mut versions = match previous.map(|pos| versions::archive(&data.config, &files[pos])) {
+            None => Vec::new(),
+            Some(Ok(versions)) => versions,
+            Some(Err(e)) => {
+                let _ = fs::remove_file(&part_path);
+                return Err(HttpResponse::InternalServerError().json(
+                    serde_json::json!({"error": format!("Failed to keep previous version: {}", e)}),
+                ));
+            }
+        };
+        data.downloads.claim(&final_name);
+        if let Err(e) = fs::rename(&part_path, &final_path) {
+            versions::unarchive(&data.config, &final_name, &versions);
+            let _ = fs::remove_file(&part_path);
+            return Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({"error": format!("Failed to store file: {}", e)})));
+        }
+        versions::prune(&data.config, &final_name, &mut versions);
+        files.retain(|f| f.id != file_id || f.is_ready());
 
+        let (id, tags, description) = previous
+            .map(|pos| files.remove(pos))
+            .map_or((file_id, Vec::new(), None), |f| {
+                (f.id, f.tags, f.description)
+            });
         let info = FileInfo {
-            id: file_id,
+            id,
+            tags,
+            description,
+            versions,
             name: final_name,
             size: total_size,
             mime_type: mime,
             uploaded_at: Utc::now(),
+            checksum: Some(checksum),
+            width,
+            height,
+            owner: Some(client.to_string()),
+            policy,
+            ..Default::default()
         };
 
-        uploaded.push(info.clone());
-        data.files.lock().unwrap().insert(0, info);
+        ids::save(&info.name, &info.id);
+
+        // Usually the front, unless a scanned file has an mtime in the future.
+        let pos = files.partition_point(|f| listing_order(f, &info).is_lt());
+        files.insert(pos, info.clone());
+        info
+    };
+    if replace {
+        precompress::remove(&info.name);
+    }
+
+    if data.config.precompress
+        && precompress::is_compressible(&info.mime_type)
+        && info.size <= data.config.precompress_max_bytes
+    {
+        spawn_precompress(data.clone(), &info);
+    }
+    Ok(info)
+}
+
+/// Creates the variants of a new upload in the background; the response does
+/// not wait for them.
+fn spawn_precompress(data: web::Data<AppState>, info: &FileInfo) {
+    let (id, name, checksum) = (info.id.clone(), info.name.clone(), info.checksum.clone());
+    actix_web::rt::spawn(async move {
+        let task_name = name.clone();
+        match web::block(move || precompress::create(&task_name)).await {
+            Ok(Ok(bytes)) if bytes > 0 => {
+                let mut files = data.files.lock().unwrap();
+                // Unless the file was replaced or renamed meanwhile.
+                if let Some(f) = files
+                    .iter_mut()
+                    .find(|f| f.id == id && f.name == name && f.checksum == checksum)
+                {
+                    f.variant_bytes = Some(bytes);
+                }
+            }
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => logging::event(
+                "warn",
+                "failed to precompress upload",
+                serde_json::json!({"file": name, "error": e.to_string()}),
+            ),
+            Err(_) => logging::event(
+                "warn",
+                "precompression task failed",
+                serde_json::json!({"file": name}),
+            ),
+        }
+    });
+}
+
+/// Applies `UNSAFE_NAMES` and `EXTENSIONLESS_UPLOADS` to the sanitized name
+/// of the file `id`, whose type is `mime` if known.
+fn checked_name(
+    config: &Config,
+    mut name: String,
+    id: &str,
+    mime: Option<&str>,
+) -> Result<String, HttpResponse> {
+    if is_unsafe_name(&name) {
+        match config.unsafe_names {
+            UnsafeNamePolicy::Rename => name = safe_name(&name, id),
+            UnsafeNamePolicy::Reject => {
+                return Err(HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": format!("File name '{}' is not allowed", name)
+                })));
+            }
+        }
+    }
+
+    if Path::new(&name).extension().is_none() {
+        match config.extensionless_uploads {
+            ExtensionlessPolicy::Allow => {}
+            ExtensionlessPolicy::Reject => {
+                return Err(HttpResponse::UnprocessableEntity().json(serde_json::json!({
+                    "error": format!("File '{}' has no extension", name)
+                })));
+            }
+            ExtensionlessPolicy::Derive => {
+                if let Some(ext) = mime.and_then(extension_for_mime) {
+                    name = format!("{}.{}", name, ext);
+                }
+            }
+        }
+    }
+    Ok(name)
+}
+
+/// Order of the file listing: newest first, then by name and id, so files
+/// sharing an upload time (as scanned files with coarse mtimes often do) are
+/// listed the same way on every run.
+fn listing_order(a: &FileInfo, b: &FileInfo) -> std::cmp::Ordering {
+    b.uploaded_at
+        .cmp(&a.uploaded_at)
+        .then_with(|| a.name.cmp(&b.name))
+        .then_with(|| a.id.cmp(&b.id))
+}
+
+/// Returns a snapshot of the stored file with the given id. Placeholders
+/// are not found.
+fn find_file(data: &AppState, id: &str) -> Option<FileInfo> {
+    data.files
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|f| f.id == id && f.is_ready())
+        .cloned()
+}
+
+#[derive(Deserialize)]
+struct ListQuery {
+    limit: Option<usize>,
+    #[serde(default)]
+    offset: usize,
+}
+
+impl ListQuery {
+    /// `limit` defaults to `DEFAULT_PAGE_SIZE` and is clamped to
+    /// `MAX_PAGE_SIZE`.
+    fn page<'a, I: Iterator<Item = &'a FileInfo>>(
+        &self,
+        config: &Config,
+        files: I,
+    ) -> Vec<&'a FileInfo> {
+        let limit = self
+            .limit
+            .unwrap_or(config.default_page_size)
+            .min(config.max_page_size);
+        files.skip(self.offset).take(limit).collect()
     }
+}
 
+/// `GET /api/files?limit=&offset=`: one page of the newest-first listing.
+/// The unpaginated total is returned in `X-Total-Count`.
+async fn list_files(query: web::Query<ListQuery>, data: web::Data<AppState>) -> HttpResponse {
+    let files = data.files.lock().unwrap();
+    let page = query.page(&data.config, files.iter());
+    HttpResponse::Ok()
+        .insert_header(("X-Total-Count", files.len().to_string()))
+        .json(page)
+}
+
+/// `GET /api/quota`: the calling client's usage against `CLIENT_QUOTA_BYTES`.
+async fn quota_status(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
+    let client = client_ip(&req);
+    let used = client_usage(&data.files.lock().unwrap(), &client, None);
+    let quota = data.config.client_quota;
     HttpResponse::Ok().json(serde_json::json!({
-        "success": true,
-        "files": uploaded
+        "client": client,
+        "used": used,
+        "quota": quota,
+        "remaining": quota.map(|q| q.saturating_sub(used)),
     }))
 }
 
-async fn list_files(data: web::Data<AppState>) -> HttpResponse {
+/// Display categories in the order the grouped listing returns them.
+const CATEGORIES: [&str; 6] = ["image", "video", "audio", "document", "archive", "other"];
+
+/// Buckets a MIME type into one of [`CATEGORIES`].
+fn category(mime: &str) -> &'static str {
+    let (top, sub) = mime.split_once('/').unwrap_or((mime, ""));
+    match top {
+        "image" => "image",
+        "video" => "video",
+        "audio" => "audio",
+        "text" => "document",
+        "application" => match sub {
+            "zip" | "gzip" | "x-tar" | "x-gtar" | "x-bzip2" | "x-xz" | "x-7z-compressed"
+            | "x-rar-compressed" | "vnd.rar" | "zstd" => "archive",
+            "pdf" | "rtf" | "msword" | "json" | "xml" | "epub+zip" => "document",
+            s if s.starts_with("vnd.openxmlformats-officedocument")
+                || s.starts_with("vnd.oasis.opendocument")
+                || s.starts_with("vnd.ms-") =>
+            {
+                "document"
+            }
+            _ => "other",
+        },
+        _ => "other",
+    }
+}
+
+#[derive(Deserialize)]
+struct LargestQuery {
+    limit: Option<usize>,
+    /// Only files uploaded at least this many seconds ago.
+    older_than: Option<u64>,
+}
+
+/// `GET /api/files/largest`: the biggest files first, to find what to
+/// clean up when space runs low.
+async fn largest_files(query: web::Query<LargestQuery>, data: web::Data<AppState>) -> HttpResponse {
+    let limit = query
+        .limit
+        .unwrap_or(data.config.default_page_size)
+        .min(data.config.max_page_size);
+    let cutoff = query
+        .older_than
+        .map(|secs| Utc::now() - chrono::Duration::seconds(secs.min(i64::MAX as u64) as i64));
     let files = data.files.lock().unwrap();
-    HttpResponse::Ok().json(&*files)
+    let mut matching: Vec<&FileInfo> = files
+        .iter()
+        .filter(|f| f.is_ready() && cutoff.is_none_or(|c| f.uploaded_at <= c))
+        .collect();
+    let total = matching.len();
+    matching.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| listing_order(a, b)));
+    matching.truncate(limit);
+    HttpResponse::Ok()
+        .insert_header(("X-Total-Count", total.to_string()))
+        .json(matching)
+}
+
+/// `GET /api/files/grouped?limit=&offset=`: the listing bucketed by
+/// category. Each bucket is paged like the flat listing, while its `count`
+/// and `size` cover all of it. Placeholders have no bytes yet and are left
+/// out.
+async fn list_files_grouped(
+    query: web::Query<ListQuery>,
+    data: web::Data<AppState>,
+) -> HttpResponse {
+    let files = data.files.lock().unwrap();
+    let groups: Vec<serde_json::Value> = CATEGORIES
+        .iter()
+        .map(|cat| {
+            let members = || {
+                files
+                    .iter()
+                    .filter(|f| f.is_ready() && category(&f.mime_type) == *cat)
+            };
+            serde_json::json!({
+                "category": cat,
+                "count": members().count(),
+                "size": members().map(|f| f.size).sum::<u64>(),
+                "files": query.page(&data.config, members()),
+            })
+        })
+        .collect();
+    HttpResponse::Ok().json(serde_json::json!({ "groups": groups }))
 }
 
 async fn delete_file(
@@ -156,47 +1156,611 @@ async fn delete_file(
 
     if let Some(pos) = files.iter().position(|f| f.id == file_id) {
         let file_info = files.remove(pos);
+        // A placeholder has nothing on disk, and its name may be shared with
+        // a stored file whose bytes, variants, history and id are not its own.
+        // It is never in the changes feed either, so it leaves no tombstone.
+        if !file_info.is_ready() {
+            return HttpResponse::Ok().json(serde_json::json!({"success": true}));
+        }
+        data.tombstones.record(
+            data.config.tombstone_retention,
+            file_info.id.clone(),
+            file_info.name.clone(),
+        );
         let filepath = PathBuf::from(UPLOAD_DIR).join(&file_info.name);
-        let _ = fs::remove_file(filepath);
+        precompress::remove(&file_info.name);
+        versions::remove_all(&file_info.name);
+        ids::remove(&file_info.name);
+        data.downloads
+            .remove_when_idle(file_info.name, filepath, data.config.delete_grace);
         HttpResponse::Ok().json(serde_json::json!({"success": true}))
     } else {
         HttpResponse::NotFound().json(serde_json::json!({"error": "File not found"}))
     }
 }
 
-async fn download_file(path: web::Path<String>) -> HttpResponse {
+#[derive(Deserialize)]
+struct DownloadQuery {
+    confirm: Option<String>,
+    /// Name to save the download as, instead of the stored name.
+    filename: Option<String>,
+    /// `inline` to display the file in the browser, if its type is in
+    /// `INLINE_MIME_TYPES`.
+    #[serde(default)]
+    disposition: Disposition,
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Disposition {
+    #[default]
+    Attachment,
+    Inline,
+}
+
+/// Whether `INLINE_MIME_TYPES` lets a type be displayed inline.
+fn inline_allowed(config: &Config, mime: &str) -> bool {
+    let mime = mime.to_ascii_lowercase();
+    let essence = mime.split(';').next().unwrap_or_default().trim();
+    config
+        .inline_types
+        .iter()
+        .any(|allowed| match allowed.strip_suffix("/*") {
+            Some(family) => essence.split_once('/').is_some_and(|(t, _)| t == family),
+            None => allowed == essence,
+        })
+}
+
+async fn download_file(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<DownloadQuery>,
+    data: web::Data<AppState>,
+) -> HttpResponse {
     let filename = path.into_inner();
-    let filepath = PathBuf::from(UPLOAD_DIR).join(&filename);
+    if data.config.download_confirmation
+        && !query
+            .confirm
+            .as_deref()
+            .is_some_and(|t| data.confirmations.is_valid(t, &filename))
+    {
+        if stored_path(&data.config, &filename).is_none() {
+            return HttpResponse::NotFound().json(serde_json::json!({"error": "File not found"}));
+        }
+        return confirm::required(&req, &filename);
+    }
+
+    let save_as = match query.filename.as_deref() {
+        None => filename.clone(),
+        Some(name) if name.is_empty() || name.contains(['/', '\\']) => {
+            return HttpResponse::BadRequest().json(
+                serde_json::json!({"error": "filename must be a plain name without path separators"}),
+            );
+        }
+        Some(name) => sanitize_filename(&data.config, name),
+    };
+    serve_file(&req, &data, &filename, &save_as, query.disposition).await
+}
+
+/// Caching headers for a download of the file with record `id`:
+/// `Last-Modified`, `DOWNLOAD_CACHE_CONTROL` and, with `SURROGATE_CONTROL`,
+/// the surrogate headers an edge cache is purged by.
+fn cache_headers(res: &mut HttpResponse, config: &Config, id: Option<&str>, meta: &fs::Metadata) {
+    let headers = res.headers_mut();
+    if let Some(modified) = meta.modified().ok().map(header::HttpDate::from) {
+        if let Ok(value) = header::HeaderValue::from_str(&modified.to_string()) {
+            headers.insert(header::LAST_MODIFIED, value);
+        }
+    }
+    if let Ok(value) = header::HeaderValue::from_str(&config.download_cache_control) {
+        headers.insert(header::CACHE_CONTROL, value);
+    }
+    let surrogate = config.surrogate_control.as_deref();
+    if let Some(value) = surrogate.and_then(|v| header::HeaderValue::from_str(v).ok()) {
+        headers.insert(header::HeaderName::from_static("surrogate-control"), value);
+        if let Some(key) = id.and_then(|id| header::HeaderValue::from_str(id).ok()) {
+            headers.insert(header::HeaderName::from_static("surrogate-key"), key);
+        }
+    }
+}
 
-    if !filepath.exists() {
+/// Streams a stored file named `save_as`, honoring `Range`. `Inline` is only
+/// honored for types in `INLINE_MIME_TYPES`. Access checks are up to the
+/// caller.
+async fn serve_file(
+    req: &HttpRequest,
+    data: &web::Data<AppState>,
+    filename: &str,
+    save_as: &str,
+    disposition: Disposition,
+) -> HttpResponse {
+    let guard = data.downloads.start(filename);
+    let Some((filepath, meta)) = stored_path(&data.config, filename)
+        .filter(|_| !data.downloads.is_deleted(filename))
+        .and_then(|p| fs::metadata(&p).ok().map(|m| (p, m)))
+    else {
         return HttpResponse::NotFound().json(serde_json::json!({"error": "File not found"}));
+    };
+
+    let range_header = req
+        .headers()
+        .get(header::RANGE)
+        .map(|v| v.to_str().unwrap_or_default());
+
+    let size = meta.len();
+    let (id, checksum, corrupt) = data
+        .files
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|f| f.name == filename && f.is_ready())
+        .map(|f| {
+            (
+                Some(f.id.clone()),
+                f.checksum.clone(),
+                f.checksum_mismatch.is_some(),
+            )
+        })
+        .unwrap_or_default();
+    let verify = data.config.verify_downloads != VerifyDownloads::Off;
+    if verify && corrupt {
+        return verify::integrity_failed();
     }
+    let etag = etag(checksum.as_ref(), &meta);
+    let vary = data.config.precompress && precompress::has_variants(filename, &meta);
+    let range_header =
+        range_header.filter(|_| conditional::range_applies(req, &etag, meta.modified().ok()));
 
-    let mime = mime_guess::from_path(&filepath)
-        .first_or_octet_stream()
-        .to_string();
+    let mut etags = vec![etag.clone()];
+    if vary {
+        etags.extend(precompress::variant_etags(&etag));
+    }
+    if conditional::not_modified(req, &etags, meta.modified().ok()) {
+        let mut res = HttpResponse::NotModified()
+            .insert_header((header::ETAG, etag))
+            .finish();
+        cache_headers(&mut res, &data.config, id.as_deref(), &meta);
+        if vary {
+            res.headers_mut().insert(
+                header::VARY,
+                header::HeaderValue::from_static("Accept-Encoding"),
+            );
+        }
+        return res;
+    }
+    if range_header.is_none() {
+        let mut files = data.files.lock().unwrap();
+        if let Some(f) = files
+            .iter_mut()
+            .find(|f| f.name == filename && f.is_ready())
+        {
+            f.downloads += 1;
+        }
+    }
+
+    let mime = mimetypes::guess(&data.config, &filepath);
+    let inline = disposition == Disposition::Inline && inline_allowed(&data.config, mime.as_str());
+    let disposition = content_disposition(
+        if inline {
+            DispositionType::Inline
+        } else {
+            DispositionType::Attachment
+        },
+        save_as,
+    );
+    let chunk_size = data.config.download_chunk_size;
 
-    match fs::read(&filepath) {
-        Ok(data) => HttpResponse::Ok()
-            .insert_header(("Content-Type", mime.as_str()))
-            .insert_header((
-                "Content-Disposition",
-                format!("attachment; filename=\"{}\"", filename),
-            ))
-            .body(data),
-        Err(_) => HttpResponse::InternalServerError()
-            .json(serde_json::json!({"error": "Failed to read file"})),
+    let range = range::parse(range_header, size);
+    let expected = id.clone().zip(checksum).filter(|_| verify);
+    // Small files are checked whole even for a range of them. Ranges of
+    // larger files cannot be checked without reading the whole file, so they
+    // are only refused once a full download or the scrub has flagged it.
+    if let Some((id, expected)) = &expected {
+        if size <= verify::CHECK_FIRST_MAX && !matches!(range, RangeRequest::Unsatisfiable) {
+            if let Err(res) = verify::check(data, id, filepath.clone(), expected).await {
+                return res;
+            }
+        }
     }
+
+    // Precompressed variants are only served whole; range requests get the
+    // original bytes, and the variant response does not offer ranges. A
+    // variant cannot be checked as it streams, so with verification on only
+    // files checked above get one.
+    let checked = expected.is_none() || size <= verify::CHECK_FIRST_MAX;
+    if vary && checked && range_header.is_none() {
+        if let Some((encoding, path, variant)) = precompress::negotiate(req, filename, &meta) {
+            let len = variant.len();
+            let mut res = HttpResponse::Ok()
+                .insert_header(("Content-Type", mime.as_str()))
+                .insert_header(disposition)
+                .insert_header((header::CONTENT_ENCODING, encoding.token()))
+                .insert_header((header::VARY, "Accept-Encoding"))
+                .insert_header((header::ACCEPT_RANGES, "none"))
+                .insert_header((header::ETAG, precompress::variant_etag(&etag, encoding)))
+                .no_chunking(len)
+                .streaming(guard.attach(file_stream(path, 0, len, chunk_size)));
+            cache_headers(&mut res, &data.config, id.as_deref(), &meta);
+            return res;
+        }
+    }
+
+    let mut res = match range {
+        RangeRequest::Full => {
+            let body = file_stream(filepath, 0, size, chunk_size);
+            let body = match expected.filter(|_| size > verify::CHECK_FIRST_MAX) {
+                Some((id, expected)) => {
+                    verify::stream(body, data.clone(), id, expected, size).boxed_local()
+                }
+                None => body.boxed_local(),
+            };
+            HttpResponse::Ok()
+                .insert_header(("Content-Type", mime.as_str()))
+                .insert_header(disposition)
+                .insert_header((header::ACCEPT_RANGES, "bytes"))
+                .insert_header((header::ETAG, etag))
+                .no_chunking(size)
+                .streaming(guard.attach(body))
+        }
+        RangeRequest::Partial(ranges) if ranges.len() == 1 => {
+            let r = ranges[0];
+            HttpResponse::PartialContent()
+                .insert_header(("Content-Type", mime.as_str()))
+                .insert_header(disposition)
+                .insert_header((header::ACCEPT_RANGES, "bytes"))
+                .insert_header((header::ETAG, etag))
+                .insert_header((
+                    header::CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", r.start, r.end, size),
+                ))
+                .no_chunking(r.len())
+                .streaming(guard.attach(file_stream(filepath, r.start, r.len(), chunk_size)))
+        }
+        RangeRequest::Partial(ranges) => {
+            let boundary = Uuid::new_v4().simple().to_string();
+            let mut parts = Vec::with_capacity(ranges.len() * 2 + 1);
+            let mut total: u64 = 0;
+            for (i, r) in ranges.iter().enumerate() {
+                let head = format!(
+                    "{}--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                    if i == 0 { "" } else { "\r\n" },
+                    boundary,
+                    mime,
+                    r.start,
+                    r.end,
+                    size
+                );
+                total += head.len() as u64 + r.len();
+                parts.push(stream::once(async move { Ok(web::Bytes::from(head)) }).boxed_local());
+                parts.push(
+                    file_stream(filepath.clone(), r.start, r.len(), chunk_size).boxed_local(),
+                );
+            }
+            let tail = format!("\r\n--{}--\r\n", boundary);
+            total += tail.len() as u64;
+            parts.push(stream::once(async move { Ok(web::Bytes::from(tail)) }).boxed_local());
+
+            HttpResponse::PartialContent()
+                .insert_header((
+                    "Content-Type",
+                    format!("multipart/byteranges; boundary={}", boundary),
+                ))
+                .insert_header(disposition)
+                .insert_header((header::ACCEPT_RANGES, "bytes"))
+                .insert_header((header::ETAG, etag))
+                .no_chunking(total)
+                .streaming(guard.attach(stream::iter(parts).flatten()))
+        }
+        RangeRequest::Unsatisfiable => HttpResponse::RangeNotSatisfiable()
+            .insert_header((header::CONTENT_RANGE, format!("bytes */{}", size)))
+            .json(serde_json::json!({"error": "Requested range not satisfiable"})),
+    };
+    if res.status().is_success() {
+        cache_headers(&mut res, &data.config, id.as_deref(), &meta);
+    }
+    if vary {
+        res.headers_mut().insert(
+            header::VARY,
+            header::HeaderValue::from_static("Accept-Encoding"),
+        );
+    }
+    // Keep the browser from second-guessing the allowlisted type.
+    if inline {
+        res.headers_mut().insert(
+            header::X_CONTENT_TYPE_OPTIONS,
+            header::HeaderValue::from_static("nosniff"),
+        );
+    }
+    res
+}
+
+/// Builds a `Content-Disposition` header. Non-ASCII names get an RFC 5987
+/// `filename*` parameter next to a plain ASCII fallback for old clients.
+fn content_disposition(disposition: DispositionType, name: &str) -> ContentDisposition {
+    let mut parameters = Vec::with_capacity(2);
+    if name.is_ascii() {
+        parameters.push(DispositionParam::Filename(name.to_string()));
+    } else {
+        let fallback: String = name
+            .chars()
+            .map(|c| if c.is_ascii() { c } else { '_' })
+            .collect();
+        parameters.push(DispositionParam::Filename(fallback));
+        parameters.push(DispositionParam::FilenameExt(ExtendedValue {
+            charset: Charset::Ext("UTF-8".to_string()),
+            language_tag: None,
+            value: name.as_bytes().to_vec(),
+        }));
+    }
+    ContentDisposition {
+        disposition,
+        parameters,
+    }
+}
+
+/// Streams `len` bytes of the file at `path` starting at `start`, without
+/// buffering the whole file in memory. Each chunk is at most `chunk_size`
+/// bytes (`DOWNLOAD_CHUNK_SIZE`).
+fn file_stream(
+    path: PathBuf,
+    start: u64,
+    len: u64,
+    chunk_size: usize,
+) -> impl Stream<Item = std::io::Result<web::Bytes>> {
+    stream::try_unfold(
+        (None::<tokio::fs::File>, path, start, len),
+        move |(file, path, pos, remaining)| async move {
+            if remaining == 0 {
+                return Ok(None);
+            }
+            let mut file = match file {
+                Some(f) => f,
+                None => {
+                    let mut f = tokio::fs::File::open(&path).await?;
+                    f.seek(SeekFrom::Start(pos)).await?;
+                    f
+                }
+            };
+            let mut buf = vec![0u8; remaining.min(chunk_size as u64) as usize];
+            let n = file.read(&mut buf).await?;
+            if n == 0 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "file shrank while being downloaded",
+                ));
+            }
+            buf.truncate(n);
+            Ok(Some((
+                web::Bytes::from(buf),
+                (Some(file), path, pos + n as u64, remaining - n as u64),
+            )))
+        },
+    )
 }
 
-async fn index() -> HttpResponse {
+/// Writes all of `buf`, retrying transient errors up to `WRITE_RETRIES`
+/// times with exponential backoff. Progress is tracked per `write` call, so a
+/// retry never duplicates bytes that already reached the file.
+async fn write_with_retry<W: Write>(
+    file: &mut W,
+    mut buf: &[u8],
+    config: &Config,
+) -> std::io::Result<()> {
+    let mut attempt = 0;
+    while !buf.is_empty() {
+        match file.write(buf) {
+            Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                buf = &buf[n..];
+                attempt = 0;
+            }
+            Err(e) if is_transient(&e) && attempt < config.write_retries => {
+                tokio::time::sleep(config.write_retry_backoff * 2u32.pow(attempt)).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+fn is_transient(e: &std::io::Error) -> bool {
+    use std::io::ErrorKind::*;
+    matches!(e.kind(), Interrupted | WouldBlock | TimedOut | ResourceBusy)
+}
+
+/// Out of disk space or over a filesystem quota: retrying cannot help.
+fn is_storage_full(e: &std::io::Error) -> bool {
+    use std::io::ErrorKind::*;
+    matches!(e.kind(), StorageFull | QuotaExceeded)
+}
+
+/// Reads width and height from an image's header without decoding it.
+/// Formats `imagesize` does not understand simply yield no dimensions.
+fn image_dimensions(config: &Config, path: &Path, mime: &str) -> (Option<u32>, Option<u32>) {
+    if !config.image_dimensions || !mime.starts_with("image/") {
+        return (None, None);
+    }
+    match imagesize::size(path) {
+        Ok(dim) => (
+            u32::try_from(dim.width).ok(),
+            u32::try_from(dim.height).ok(),
+        ),
+        Err(_) => (None, None),
+    }
+}
+
+/// Picks the conventional extension for a MIME type. `mime_guess` lists
+/// extensions alphabetically (`image/jpeg` starts with `jfif`), so prefer the
+/// subtype itself or a known favourite before falling back to the first entry.
+fn extension_for_mime(mime: &str) -> Option<&'static str> {
+    const PREFERRED: &[(&str, &str)] = &[
+        ("image/jpeg", "jpg"),
+        ("text/plain", "txt"),
+        ("text/markdown", "md"),
+        ("audio/mpeg", "mp3"),
+        ("video/quicktime", "mov"),
+    ];
+    if mime == "application/octet-stream" {
+        return None;
+    }
+    if let Some((_, ext)) = PREFERRED.iter().find(|(m, _)| *m == mime) {
+        return Some(ext);
+    }
+    let exts = mime_guess::get_mime_extensions_str(mime)?;
+    let subtype = mime.split('/').nth(1).unwrap_or_default();
+    exts.iter()
+        .find(|e| **e == subtype)
+        .or_else(|| exts.first())
+        .copied()
+}
+
+/// Characters left unescaped when a file name is placed in a URL path segment.
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'.')
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'~');
+
+fn encode_path_segment(s: &str) -> String {
+    utf8_percent_encode(s, PATH_SEGMENT).to_string()
+}
+
+/// Escapes text for HTML and XML bodies and attribute values.
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// The client's IP: the peer address, or what a trusted proxy reported (see
+/// `proxy.rs`).
+fn client_ip(req: &HttpRequest) -> String {
+    let trusted = req
+        .app_data::<web::Data<AppState>>()
+        .map_or(&[][..], |data| &data.config.trusted_proxies);
+    proxy::client_addr(req.head(), trusted)
+        .map_or_else(|| "unknown".to_string(), |ip| ip.to_string())
+}
+
+/// Origin used in absolute links: `PUBLIC_BASE_URL` when configured,
+/// otherwise the scheme and host the request arrived on (checked against
+/// `ALLOWED_HOSTS` by `hosts::check`).
+fn public_base_url(req: &HttpRequest, config: &Config) -> String {
+    match &config.public_base_url {
+        Some(url) => url.trim_end_matches('/').to_string(),
+        None => {
+            let trusted = &config.trusted_proxies;
+            let host = proxy::host(req.head(), trusted)
+                .unwrap_or_else(|| req.app_config().host().to_string());
+            format!("{}://{}", proxy::scheme(req.head(), trusted), host)
+        }
+    }
+}
+
+/// Resolves a stored file name to its path in the upload directory, applying
+/// the symlink policy. Returns `None` for anything that is not a regular file
+/// (after following an allowed symlink).
+fn stored_path(config: &Config, name: &str) -> Option<PathBuf> {
+    if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+        return None;
+    }
+    let path = PathBuf::from(UPLOAD_DIR).join(name);
+    let link = fs::symlink_metadata(&path).ok()?;
+    if link.file_type().is_symlink() {
+        match config.symlink_policy {
+            SymlinkPolicy::Ignore => return None,
+            SymlinkPolicy::Follow => {}
+            SymlinkPolicy::Within => {
+                let root = fs::canonicalize(UPLOAD_DIR).ok()?;
+                if !fs::canonicalize(&path).ok()?.starts_with(root) {
+                    return None;
+                }
+            }
+        }
+        return path.is_file().then_some(path);
+    }
+    link.is_file().then_some(path)
+}
+
+fn part_path(file_id: &str) -> PathBuf {
+    PathBuf::from(UPLOAD_DIR).join(format!(".{}.part", file_id))
+}
+
+/// Removes `.part` files left behind by uploads that were interrupted by a
+/// crash or ungraceful shutdown. Completed uploads are always renamed away
+/// from their `.part` name, so only abandoned temp files match.
+fn cleanup_partial_uploads(max_age: std::time::Duration) {
+    let Ok(entries) = fs::read_dir(UPLOAD_DIR) else {
+        return;
+    };
+    let mut removed = 0;
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !(name.starts_with('.') && name.ends_with(".part")) {
+            continue;
+        }
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        let age = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.elapsed().ok())
+            .unwrap_or_default();
+        if !metadata.is_file() || age < max_age {
+            continue;
+        }
+        match fs::remove_file(entry.path()) {
+            Ok(()) => {
+                removed += 1;
+                logging::event(
+                    "info",
+                    "removed partial upload",
+                    serde_json::json!({"file": name, "bytes": metadata.len(), "age_secs": age.as_secs()}),
+                );
+            }
+            Err(e) => logging::event(
+                "warn",
+                "failed to remove partial upload",
+                serde_json::json!({"file": name, "error": e.to_string()}),
+            ),
+        }
+    }
+    if removed > 0 {
+        logging::event(
+            "info",
+            "partial upload cleanup finished",
+            serde_json::json!({"removed": removed}),
+        );
+    }
+}
+
+async fn index(data: web::Data<AppState>) -> HttpResponse {
     let html = include_str!("../static/index.html");
+    let html = if data.config.pwa {
+        html.replacen("</head>", &format!("{}</head>", pwa::HEAD_TAGS), 1)
+    } else {
+        html.to_string()
+    };
     HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")
         .body(html)
 }
 
-fn sanitize_filename(name: &str) -> String {
+/// Replaces anything but letters, digits and `.-_ ` with `_`. With
+/// `NORMALIZE_NAMES`, the name is first brought to Unicode NFC, so a name
+/// typed on macOS (which sends NFD, with accents as separate combining
+/// marks) comes out the same as elsewhere instead of with its accents
+/// replaced.
+fn sanitize_filename(config: &Config, name: &str) -> String {
+    let name: String = if config.normalize_names {
+        name.nfc().collect()
+    } else {
+        name.to_string()
+    };
     name.chars()
         .map(|c| {
             if c.is_alphanumeric() || c == '.' || c == '-' || c == '_' || c == ' ' {
@@ -208,31 +1772,154 @@ fn sanitize_filename(name: &str) -> String {
         .collect()
 }
 
+/// Device names Windows reserves regardless of extension (`CON.txt` too).
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Whether a sanitized name cannot be stored as-is: empty or nothing but
+/// punctuation (`""`, `..`, `___`), hidden (and so skipped by the startup
+/// scan, and liable to clash with `.part` files), or a reserved device name.
+fn is_unsafe_name(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or_default().trim();
+    !name.chars().any(char::is_alphanumeric)
+        || name.starts_with('.')
+        || RESERVED_NAMES.iter().any(|r| stem.eq_ignore_ascii_case(r))
+}
+
+/// Replacement for an unsafe name: `file_<id>`, plus the original extension
+/// when it has one.
+fn safe_name(name: &str, file_id: &str) -> String {
+    match name
+        .rsplit_once('.')
+        .map(|(_, ext)| ext)
+        .filter(|ext| !ext.is_empty() && ext.chars().all(char::is_alphanumeric))
+    {
+        Some(ext) => format!("file_{}.{}", &file_id[..8], ext),
+        None => format!("file_{}", &file_id[..8]),
+    }
+}
+
+/// Every endpoint; `main` adds the middleware around them.
+fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/", web::get().to(index))
+        .route("/manifest.json", web::get().to(pwa::manifest))
+        .route("/sw.js", web::get().to(pwa::service_worker))
+        .route("/icon.svg", web::get().to(pwa::icon))
+        .route("/healthz", web::get().to(storage::healthz))
+        .route("/readyz", web::get().to(storage::readyz))
+        .route("/api/status", web::get().to(status::status))
+        .route("/api/upload", web::post().to(upload_file))
+        .route("/api/upload", web::put().to(upload_raw))
+        .route("/api/files", web::get().to(list_files))
+        .route("/api/files/grouped", web::get().to(list_files_grouped))
+        .route("/api/files/largest", web::get().to(largest_files))
+        .route("/api/files/compare", web::get().to(compare::compare_files))
+        .route("/api/files/tag", web::post().to(tags::bulk_tag))
+        .route(
+            "/api/files/placeholder",
+            web::post().to(placeholders::create_placeholder),
+        )
+        .route("/api/changes", web::get().to(changes::changes))
+        .route("/api/files/{id}", web::delete().to(delete_file))
+        .route("/api/files/{id}", web::patch().to(update::patch_file))
+        .route("/api/files/{id}", web::put().to(update::put_file))
+        .route(
+            "/api/files/{id}/location",
+            web::get().to(admin::file_location),
+        )
+        .route(
+            "/api/files/{id}/signed-url",
+            web::get().to(signing::signed_url),
+        )
+        .route(
+            "/api/files/{id}/metadata",
+            web::get().to(metadata::get_metadata),
+        )
+        .route(
+            "/api/files/{id}/wc",
+            web::get().to(textstats::get_text_stats),
+        )
+        .route("/api/files/{id}/follow", web::get().to(follow::follow_file))
+        .route(
+            "/api/files/{id}/append",
+            web::post().to(append::append_file),
+        )
+        .route("/api/files/{id}/embed", web::get().to(embed::embed_snippet))
+        .route(
+            "/api/files/{id}/versions",
+            web::get().to(versions::list_versions),
+        )
+        .route(
+            "/api/files/{id}/versions/{version}/restore",
+            web::post().to(versions::restore_version),
+        )
+        .route("/api/download/{filename}", web::get().to(download_file))
+        .route(
+            "/api/download/{filename}/confirm",
+            web::post().to(confirm::issue_token),
+        )
+        .route(
+            "/api/signed/{id}/{expires}/{signature}",
+            web::get().to(signing::signed_download),
+        )
+        .route("/api/feed.xml", web::get().to(feed::recent_uploads))
+        .route("/api/admin/export", web::get().to(admin::export_metadata))
+        .route("/api/admin/import", web::post().to(admin::import_metadata))
+        .route("/api/admin/usage", web::get().to(admin::client_usage))
+        .route("/api/admin/scrub", web::get().to(scrub::scrub_report))
+        .route(
+            "/api/admin/storage-health",
+            web::get().to(disk_report::storage_health),
+        )
+        .route("/metrics", web::get().to(disk_report::metrics))
+        .route("/api/quota", web::get().to(quota_status));
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     fs::create_dir_all(UPLOAD_DIR)?;
 
-    let data = web::Data::new(AppState::new());
+    let config =
+        Config::from_env().map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    logging::init(config.log_format);
+    cleanup_partial_uploads(config.partial_upload_max_age);
+    let probe_interval = config.storage_probe_interval;
+    let data = web::Data::new(AppState::new(config));
+    storage::start_probing(data.clone(), probe_interval);
+    scrub::start(data.clone());
+    placeholders::start_expiry(data.clone());
 
     let bind_addr = std::env::var("BIND_ADDR").unwrap_or_else(|_| "0.0.0.0:8080".to_string());
 
-    println!();
-    println!("  ⚡ File Sharing Server");
-    println!("  Running on http://{}", bind_addr);
-    println!();
+    match logging::format() {
+        LogFormat::Human => {
+            println!();
+            println!("  ⚡ File Sharing Server");
+            println!("  Running on http://{}", bind_addr);
+            println!();
+        }
+        LogFormat::Json => logging::event(
+            "info",
+            "server started",
+            serde_json::json!({"bind_addr": bind_addr}),
+        ),
+    }
 
     HttpServer::new(move || {
         let cors = Cors::permissive();
 
         App::new()
             .wrap(cors)
+            .wrap(middleware::from_fn(casing::apply))
+            .wrap(middleware::from_fn(https::enforce))
+            .wrap(middleware::from_fn(hosts::check))
+            .wrap(middleware::from_fn(logging::access_log))
+            .wrap(middleware::from_fn(server_header::apply))
             .app_data(data.clone())
             .app_data(web::PayloadConfig::new(MAX_FILE_SIZE))
-            .route("/", web::get().to(index))
-            .route("/api/upload", web::post().to(upload_file))
-            .route("/api/files", web::get().to(list_files))
-            .route("/api/files/{id}", web::delete().to(delete_file))
-            .route("/api/download/{filename}", web::get().to(download_file))
+            .configure(routes)
     })
     .bind(&bind_addr)?
     .workers(num_cpus())
@@ -245,3 +1932,1013 @@ fn num_cpus() -> usize {
         .map(|n| n.get())
         .unwrap_or(4)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{
+        add_file, call, config, json, path, record, remove, state, unique, upload,
+    };
+    use actix_web::test::{read_body, TestRequest};
+
+    fn download(name: &str) -> TestRequest {
+        TestRequest::get()
+            .uri(&format!("/api/download/{}", name))
+            .insert_header((header::ACCEPT_ENCODING, "gzip"))
+    }
+
+    #[actix_web::test]
+    async fn verified_files_get_their_variant() {
+        let data = state(Config {
+            precompress: true,
+            verify_downloads: VerifyDownloads::Refuse,
+            ..config()
+        });
+        let name = unique("notes.txt");
+        let info = add_file(&data, &name, "lorem ipsum ".repeat(1000).as_bytes(), None);
+        precompress::create(&name).unwrap();
+
+        let res = call(&data, download(&name)).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers().get(header::CONTENT_ENCODING).unwrap(), "gzip");
+        assert!(record(&data, &info.id).unwrap().last_verified.is_some());
+
+        precompress::remove(&name);
+        fs::remove_file(path(&name)).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn corrupted_files_are_refused_before_their_variant() {
+        let data = state(Config {
+            precompress: true,
+            verify_downloads: VerifyDownloads::Refuse,
+            ..config()
+        });
+        let name = unique("notes.txt");
+        let info = add_file(&data, &name, "lorem ipsum ".repeat(1000).as_bytes(), None);
+        fs::write(path(&name), "dolor sit amet ".repeat(1000)).unwrap();
+        precompress::create(&name).unwrap();
+
+        let res = call(&data, download(&name)).await;
+        assert_eq!(res.status(), 500);
+        assert!(res.headers().get(header::CONTENT_ENCODING).is_none());
+        assert!(record(&data, &info.id).unwrap().checksum_mismatch.is_some());
+        let res = call(&data, download(&name)).await;
+        assert_eq!(res.status(), 500);
+
+        precompress::remove(&name);
+        fs::remove_file(path(&name)).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn corrupted_large_files_end_in_an_error() {
+        let data = state(Config {
+            verify_downloads: VerifyDownloads::Refuse,
+            ..config()
+        });
+        let name = unique("large.bin");
+        let mut contents = vec![7u8; verify::CHECK_FIRST_MAX as usize + 1024 * 1024];
+        let info = add_file(&data, &name, &contents, None);
+        contents[3 * 1024 * 1024] ^= 1;
+        fs::write(path(&name), &contents).unwrap();
+
+        let res = call(&data, download(&name)).await;
+        assert_eq!(res.status(), 200);
+        assert!(actix_web::body::to_bytes(res.into_body()).await.is_err());
+        assert!(record(&data, &info.id).unwrap().checksum_mismatch.is_some());
+        assert_eq!(call(&data, download(&name)).await.status(), 500);
+        remove(&name);
+    }
+
+    #[actix_web::test]
+    async fn ranges_of_small_files_are_checked_first() {
+        let data = state(Config {
+            verify_downloads: VerifyDownloads::Refuse,
+            ..config()
+        });
+        let name = unique("small.txt");
+        let info = add_file(&data, &name, b"0123456789", None);
+        let range = || download(&name).insert_header((header::RANGE, "bytes=0-3"));
+        assert_eq!(call(&data, range()).await.status(), 206);
+
+        fs::write(path(&name), b"0123456780").unwrap();
+        assert_eq!(call(&data, range()).await.status(), 500);
+        assert!(record(&data, &info.id).unwrap().checksum_mismatch.is_some());
+        remove(&name);
+    }
+
+    #[actix_web::test]
+    async fn revalidation_gets_not_modified() {
+        let data = state(config());
+        let name = unique("notes.txt");
+        let info = add_file(&data, &name, b"hello", None);
+        let etag = format!("\"{}\"", info.checksum.as_ref().unwrap().digest);
+
+        let req = TestRequest::get()
+            .uri(&format!("/api/download/{}", name))
+            .insert_header((header::IF_NONE_MATCH, etag.as_str()));
+        let res = call(&data, req).await;
+        assert_eq!(res.status(), 304);
+        assert_eq!(res.headers().get(header::ETAG).unwrap(), etag.as_str());
+        assert!(res.headers().contains_key(header::LAST_MODIFIED));
+        assert!(read_body(res).await.is_empty());
+        assert_eq!(record(&data, &info.id).unwrap().downloads, 0);
+
+        let req = TestRequest::get()
+            .uri(&format!("/api/download/{}", name))
+            .insert_header((header::IF_NONE_MATCH, "\"stale\""));
+        let res = call(&data, req).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(record(&data, &info.id).unwrap().downloads, 1);
+
+        let since = header::HttpDate::from(
+            std::time::SystemTime::now() + std::time::Duration::from_secs(5),
+        );
+        let req = TestRequest::get()
+            .uri(&format!("/api/download/{}", name))
+            .insert_header((header::IF_MODIFIED_SINCE, since.to_string()));
+        assert_eq!(call(&data, req).await.status(), 304);
+
+        fs::remove_file(path(&name)).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn stale_if_range_gets_the_whole_file() {
+        let data = state(config());
+        let name = unique("notes.txt");
+        let info = add_file(&data, &name, b"hello world", None);
+        let etag = format!("\"{}\"", info.checksum.as_ref().unwrap().digest);
+        let resume = |if_range: &str| {
+            TestRequest::get()
+                .uri(&format!("/api/download/{}", name))
+                .insert_header((header::RANGE, "bytes=6-"))
+                .insert_header((header::IF_RANGE, if_range))
+        };
+
+        let res = call(&data, resume(&etag)).await;
+        assert_eq!(res.status(), 206);
+        assert_eq!(read_body(res).await, "world");
+        let res = call(&data, resume("\"stale\"")).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(read_body(res).await, "hello world");
+
+        fs::remove_file(path(&name)).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn grouped_listing_pages_buckets_and_skips_placeholders() {
+        let data = state(config());
+        let names: Vec<String> = (0..3).map(|i| unique(&format!("{}.txt", i))).collect();
+        for name in &names {
+            add_file(&data, name, b"abc", None);
+        }
+        data.files.lock().unwrap().push(FileInfo {
+            id: "pending".to_string(),
+            name: unique("later.txt"),
+            mime_type: "text/plain".to_string(),
+            size: 100,
+            status: FileStatus::Pending,
+            ..Default::default()
+        });
+
+        let req = TestRequest::get().uri("/api/files/grouped?limit=2&offset=1");
+        let body: serde_json::Value =
+            serde_json::from_slice(&read_body(call(&data, req).await).await).unwrap();
+        let document = &body["groups"][3];
+        assert_eq!(document["category"], "document");
+        assert_eq!(document["count"], 3);
+        assert_eq!(document["size"], 9);
+        let page: Vec<&str> = document["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| f["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(page.len(), 2);
+        assert!(page.iter().all(|n| names.iter().any(|name| name == n)));
+        assert_eq!(body["groups"][0]["count"], 0);
+
+        for name in &names {
+            fs::remove_file(path(name)).unwrap();
+        }
+    }
+
+    fn multipart(parts: &[(&str, Option<&str>, &str)]) -> (String, String) {
+        let boundary = "XyZbOuNdArY";
+        let mut body = String::new();
+        for (field, filename, value) in parts {
+            body.push_str(&format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"",
+                boundary, field
+            ));
+            if let Some(filename) = filename {
+                body.push_str(&format!(
+                    "; filename=\"{}\"\r\nContent-Type: text/plain",
+                    filename
+                ));
+            }
+            body.push_str(&format!("\r\n\r\n{}\r\n", value));
+        }
+        body.push_str(&format!("--{}--\r\n", boundary));
+        (format!("multipart/form-data; boundary={}", boundary), body)
+    }
+
+    #[actix_web::test]
+    async fn form_fields_describe_the_uploaded_files() {
+        let data = state(config());
+        let (first, second) = (unique("a.txt"), unique("b.txt"));
+        let (content_type, body) = multipart(&[
+            ("tags", None, "work, draft"),
+            ("file", Some(&first), "first"),
+            ("csrf_token", None, "abc123"),
+            ("files[]", Some(&second), "second"),
+            ("description", None, " Meeting notes "),
+        ]);
+        let req = TestRequest::post()
+            .uri("/api/upload")
+            .peer_addr("1.2.3.4:4000".parse().unwrap())
+            .insert_header((header::CONTENT_TYPE, content_type))
+            .set_payload(body);
+        let res: serde_json::Value =
+            serde_json::from_slice(&read_body(call(&data, req).await).await).unwrap();
+
+        let stored = res["files"].as_array().unwrap();
+        assert_eq!(stored.len(), 2);
+        for file in stored {
+            assert_eq!(file["tags"], serde_json::json!(["draft", "work"]));
+            assert_eq!(file["description"], "Meeting notes");
+            let info = record(&data, file["id"].as_str().unwrap()).unwrap();
+            assert_eq!(info.tags, ["draft", "work"]);
+            assert_eq!(info.description.as_deref(), Some("Meeting notes"));
+        }
+        assert_eq!(fs::read_to_string(path(&first)).unwrap(), "first");
+        assert!(!path("csrf_token").exists());
+
+        for name in [&first, &second] {
+            fs::remove_file(path(name)).unwrap();
+            ids::remove(name);
+        }
+    }
+
+    #[actix_web::test]
+    async fn invalid_form_fields_are_rejected() {
+        let data = state(config());
+        let long = "x".repeat(MAX_FORM_FIELD + 1);
+        for value in [long.as_str(), &"t".repeat(100)] {
+            let (content_type, body) = multipart(&[("tags", None, value)]);
+            let req = TestRequest::post()
+                .uri("/api/upload")
+                .insert_header((header::CONTENT_TYPE, content_type))
+                .set_payload(body);
+            assert_eq!(call(&data, req).await.status(), 400);
+        }
+    }
+
+    #[actix_web::test]
+    async fn variants_are_unchecked_without_verification() {
+        let data = state(Config {
+            precompress: true,
+            ..config()
+        });
+        let name = unique("notes.txt");
+        add_file(&data, &name, "lorem ipsum ".repeat(1000).as_bytes(), None);
+        precompress::create(&name).unwrap();
+
+        let res = call(&data, download(&name)).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers().get(header::CONTENT_ENCODING).unwrap(), "gzip");
+        assert!(!read_body(res).await.is_empty());
+
+        precompress::remove(&name);
+        fs::remove_file(path(&name)).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn extensionless_uploads_follow_the_policy() {
+        let name = unique("README");
+        let data = state(config());
+        let res = json(call(&data, upload(&name, "hi")).await).await;
+        assert_eq!(res["files"][0]["name"], name);
+        remove(&name);
+
+        let data = state(Config {
+            extensionless_uploads: ExtensionlessPolicy::Reject,
+            ..config()
+        });
+        assert_eq!(call(&data, upload(&name, "hi")).await.status(), 422);
+        assert!(!path(&name).exists());
+
+        let data = state(Config {
+            extensionless_uploads: ExtensionlessPolicy::Derive,
+            ..config()
+        });
+        let req = upload(&name, "{}").insert_header((header::CONTENT_TYPE, "application/json"));
+        let res = json(call(&data, req).await).await;
+        let derived = format!("{}.json", name);
+        assert_eq!(res["files"][0]["name"], derived);
+        remove(&derived);
+        // Nothing to derive from: stored as named.
+        let req =
+            upload(&name, "?").insert_header((header::CONTENT_TYPE, "application/octet-stream"));
+        assert_eq!(json(call(&data, req).await).await["files"][0]["name"], name);
+        remove(&name);
+    }
+
+    #[test]
+    fn extensions_for_types() {
+        assert_eq!(extension_for_mime("image/jpeg"), Some("jpg"));
+        assert_eq!(extension_for_mime("text/plain"), Some("txt"));
+        assert_eq!(extension_for_mime("image/png"), Some("png"));
+        assert_eq!(extension_for_mime("application/pdf"), Some("pdf"));
+        assert_eq!(extension_for_mime("application/octet-stream"), None);
+        assert_eq!(extension_for_mime("made/up"), None);
+    }
+
+    #[cfg(unix)]
+    #[actix_web::test]
+    async fn symlinks_follow_the_policy() {
+        use std::os::unix::fs::symlink;
+        let data = state(config());
+        let target = unique("target.txt");
+        add_file(&data, &target, b"inside", None);
+        let outside = std::env::temp_dir().join(unique("outside.txt"));
+        fs::write(&outside, "outside").unwrap();
+        let (inner, outer) = (unique("inner.txt"), unique("outer.txt"));
+        symlink(fs::canonicalize(path(&target)).unwrap(), path(&inner)).unwrap();
+        symlink(&outside, path(&outer)).unwrap();
+
+        let resolves = |policy, name: &str| {
+            let config = Config {
+                symlink_policy: policy,
+                ..config()
+            };
+            stored_path(&config, name).is_some()
+        };
+        assert!(!resolves(SymlinkPolicy::Ignore, &inner));
+        assert!(resolves(SymlinkPolicy::Within, &inner));
+        assert!(!resolves(SymlinkPolicy::Within, &outer));
+        assert!(resolves(SymlinkPolicy::Follow, &outer));
+        assert!(resolves(SymlinkPolicy::Ignore, &target));
+        assert!(!resolves(SymlinkPolicy::Follow, "../Cargo.toml"));
+
+        let req = TestRequest::get().uri(&format!("/api/download/{}", outer));
+        assert_eq!(call(&data, req).await.status(), 404);
+        let data = state(Config {
+            symlink_policy: SymlinkPolicy::Within,
+            ..config()
+        });
+        let req = TestRequest::get().uri(&format!("/api/download/{}", inner));
+        assert_eq!(read_body(call(&data, req).await).await, "inside");
+
+        for name in [&target, &inner, &outer] {
+            fs::remove_file(path(name)).unwrap();
+        }
+        fs::remove_file(outside).unwrap();
+    }
+
+    /// The name the upload response says the file was stored as.
+    async fn stored_as(data: &web::Data<AppState>, req: TestRequest) -> String {
+        let res = json(call(data, req).await).await;
+        res["files"][0]["name"].as_str().unwrap().to_string()
+    }
+
+    #[actix_web::test]
+    async fn duplicate_names_get_a_suffix() {
+        let data = state(config());
+        let name = unique("report.txt");
+        assert_eq!(stored_as(&data, upload(&name, "one")).await, name);
+        let second = stored_as(&data, upload(&name, "two")).await;
+        let stem = name.trim_end_matches(".txt");
+        assert!(second.starts_with(&format!("{}_", stem)) && second.ends_with(".txt"));
+        assert_eq!(fs::read_to_string(path(&name)).unwrap(), "one");
+        assert_eq!(fs::read_to_string(path(&second)).unwrap(), "two");
+        remove(&name);
+        remove(&second);
+    }
+
+    #[actix_web::test]
+    async fn unique_names_refuses_duplicates() {
+        let data = state(Config {
+            unique_names: true,
+            ..config()
+        });
+        let name = unique("report.txt");
+        assert_eq!(call(&data, upload(&name, "one")).await.status(), 200);
+        let res = call(&data, upload(&name, "two")).await;
+        assert_eq!(res.status(), 409);
+        assert_eq!(fs::read_to_string(path(&name)).unwrap(), "one");
+        assert_eq!(data.files.lock().unwrap().len(), 1);
+        remove(&name);
+    }
+
+    fn disposition(res: &actix_web::dev::ServiceResponse) -> String {
+        let value = res.headers().get(header::CONTENT_DISPOSITION).unwrap();
+        value.to_str().unwrap().to_string()
+    }
+
+    #[actix_web::test]
+    async fn downloads_can_be_saved_under_another_name() {
+        let data = state(config());
+        let name = unique("report.txt");
+        add_file(&data, &name, b"hi", None);
+        let get = |query: &str| TestRequest::get().uri(&format!("/api/download/{}{}", name, query));
+
+        let res = call(&data, get("")).await;
+        assert_eq!(
+            disposition(&res),
+            format!("attachment; filename=\"{}\"", name)
+        );
+        let res = call(&data, get("?filename=Q3%20summary.txt")).await;
+        assert_eq!(disposition(&res), "attachment; filename=\"Q3 summary.txt\"");
+        let res = call(&data, get("?filename=r%C3%A9sum%C3%A9.txt")).await;
+        assert_eq!(
+            disposition(&res),
+            "attachment; filename=\"r_sum_.txt\"; filename*=UTF-8''r%C3%A9sum%C3%A9.txt"
+        );
+        for bad in ["?filename=", "?filename=a%2Fb.txt", "?filename=a%5Cb.txt"] {
+            assert_eq!(call(&data, get(bad)).await.status(), 400, "{}", bad);
+        }
+        assert!(path(&name).exists());
+        remove(&name);
+    }
+
+    /// The start of a PNG file, up to and including its dimensions.
+    fn png_header(width: u32, height: u32) -> Vec<u8> {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend_from_slice(&width.to_be_bytes());
+        png.extend_from_slice(&height.to_be_bytes());
+        png.extend_from_slice(&[8, 6, 0, 0, 0, 0, 0, 0, 0]);
+        png
+    }
+
+    /// The start of a baseline JPEG, up to its `SOF0` frame header.
+    fn jpeg_header(width: u16, height: u16) -> Vec<u8> {
+        let mut jpeg = b"\xff\xd8\xff\xe0\0\x10JFIF\0\x01\x01\0\0\x01\0\x01\0\0".to_vec();
+        jpeg.extend_from_slice(b"\xff\xc0\0\x11\x08");
+        jpeg.extend_from_slice(&height.to_be_bytes());
+        jpeg.extend_from_slice(&width.to_be_bytes());
+        jpeg.extend_from_slice(&[3, 1, 0x22, 0, 2, 0x11, 1, 3, 0x11, 1]);
+        jpeg
+    }
+
+    /// A GIF's header and logical screen descriptor.
+    fn gif_header(width: u16, height: u16) -> Vec<u8> {
+        let mut gif = b"GIF89a".to_vec();
+        gif.extend_from_slice(&width.to_le_bytes());
+        gif.extend_from_slice(&height.to_le_bytes());
+        gif.extend_from_slice(&[0, 0, 0]);
+        gif
+    }
+
+    /// An extended WebP's RIFF header and `VP8X` chunk.
+    fn webp_header(width: u32, height: u32) -> Vec<u8> {
+        let mut webp = b"RIFF\x16\0\0\0WEBPVP8X\x0a\0\0\0\0\0\0\0".to_vec();
+        webp.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+        webp.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+        webp
+    }
+
+    #[test]
+    fn reads_dimensions_of_each_image_format() {
+        for (ext, mime, header) in [
+            ("png", "image/png", png_header(640, 480)),
+            ("jpg", "image/jpeg", jpeg_header(640, 480)),
+            ("gif", "image/gif", gif_header(640, 480)),
+            ("webp", "image/webp", webp_header(640, 480)),
+        ] {
+            let name = unique(&format!("image.{}", ext));
+            fs::write(path(&name), header).unwrap();
+            assert_eq!(
+                image_dimensions(&config(), &path(&name), mime),
+                (Some(640), Some(480)),
+                "{}",
+                mime
+            );
+            remove(&name);
+        }
+    }
+
+    #[actix_web::test]
+    async fn uploads_of_images_record_their_dimensions() {
+        let data = state(config());
+        let name = unique("pixel.png");
+        let req = upload(&name, "").set_payload(png_header(640, 480));
+        let res = json(call(&data, req).await).await;
+        assert_eq!(res["files"][0]["width"], 640);
+        assert_eq!(res["files"][0]["height"], 480);
+        remove(&name);
+
+        let broken = unique("broken.png");
+        let res = json(call(&data, upload(&broken, "not a png")).await).await;
+        assert!(res["files"][0].get("width").is_none());
+        remove(&broken);
+
+        let off = Config {
+            image_dimensions: false,
+            ..config()
+        };
+        fs::write(path(&name), png_header(2, 3)).unwrap();
+        assert_eq!(
+            image_dimensions(&off, &path(&name), "image/png"),
+            (None, None)
+        );
+        assert_eq!(
+            image_dimensions(&config(), &path(&name), "image/png"),
+            (Some(2), Some(3))
+        );
+        assert_eq!(
+            image_dimensions(&config(), &path(&name), "text/plain"),
+            (None, None)
+        );
+        remove(&name);
+    }
+
+    #[actix_web::test]
+    async fn writes_complete_and_classify_errors() {
+        let name = unique("retry.bin");
+        let mut file = fs::File::create(path(&name)).unwrap();
+        write_with_retry(&mut file, b"all of it", &config())
+            .await
+            .unwrap();
+        assert_eq!(fs::read(path(&name)).unwrap(), b"all of it");
+        remove(&name);
+
+        use std::io::{Error, ErrorKind};
+        assert!(is_transient(&Error::from(ErrorKind::Interrupted)));
+        assert!(!is_transient(&Error::from(ErrorKind::StorageFull)));
+        assert!(is_storage_full(&Error::from(ErrorKind::StorageFull)));
+        assert!(is_storage_full(&Error::from(ErrorKind::QuotaExceeded)));
+        assert!(!is_storage_full(&Error::from(ErrorKind::PermissionDenied)));
+    }
+
+    /// A writer that fails with each of `errors` in turn, then accepts at
+    /// most three bytes per call.
+    struct Flaky {
+        errors: Vec<std::io::ErrorKind>,
+        written: Vec<u8>,
+    }
+
+    impl Write for Flaky {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if !self.errors.is_empty() {
+                return Err(self.errors.remove(0).into());
+            }
+            let n = buf.len().min(3);
+            self.written.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[actix_web::test]
+    async fn transient_errors_are_retried_without_losing_bytes() {
+        use std::io::ErrorKind::{Interrupted, WouldBlock};
+        let config = Config {
+            write_retry_backoff: std::time::Duration::from_millis(1),
+            ..config()
+        };
+        for errors in [
+            vec![Interrupted],
+            vec![WouldBlock],
+            vec![Interrupted, WouldBlock],
+        ] {
+            let mut writer = Flaky {
+                errors,
+                written: Vec::new(),
+            };
+            write_with_retry(&mut writer, b"the whole buffer", &config)
+                .await
+                .unwrap();
+            assert!(writer.errors.is_empty());
+            assert_eq!(writer.written, b"the whole buffer");
+        }
+
+        let mut writer = Flaky {
+            errors: vec![Interrupted; 3],
+            written: Vec::new(),
+        };
+        let config = Config {
+            write_retries: 2,
+            ..config
+        };
+        let e = write_with_retry(&mut writer, b"data", &config)
+            .await
+            .unwrap_err();
+        assert_eq!(e.kind(), Interrupted);
+        assert!(writer.written.is_empty());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[actix_web::test]
+    async fn a_full_disk_is_not_retried() {
+        let mut full = fs::OpenOptions::new()
+            .write(true)
+            .open("/dev/full")
+            .unwrap();
+        let config = Config {
+            write_retries: 5,
+            write_retry_backoff: std::time::Duration::from_secs(60),
+            ..config()
+        };
+        let e = write_with_retry(&mut full, b"data", &config)
+            .await
+            .unwrap_err();
+        assert!(is_storage_full(&e));
+    }
+
+    #[actix_web::test]
+    async fn lists_a_page_with_the_total() {
+        let data = state(Config {
+            default_page_size: 2,
+            max_page_size: 3,
+            ..config()
+        });
+        let names: Vec<String> = (0..5).map(|i| unique(&format!("{}.txt", i))).collect();
+        for name in &names {
+            add_file(&data, name, b"x", None);
+        }
+        let list = |uri: &str| call(&data, TestRequest::get().uri(uri));
+        let listed = |res: serde_json::Value| -> Vec<String> {
+            let files = res.as_array().unwrap().iter();
+            files
+                .map(|f| f["name"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        let res = list("/api/files").await;
+        assert_eq!(res.headers().get("X-Total-Count").unwrap(), "5");
+        assert_eq!(
+            listed(json(res).await),
+            [names[4].clone(), names[3].clone()]
+        );
+
+        let res = list("/api/files?limit=10").await;
+        assert_eq!(listed(json(res).await).len(), 3);
+
+        let res = list("/api/files?offset=4&limit=3").await;
+        assert_eq!(res.headers().get("X-Total-Count").unwrap(), "5");
+        assert_eq!(listed(json(res).await), [names[0].clone()]);
+
+        let res = list("/api/files?offset=9").await;
+        assert!(listed(json(res).await).is_empty());
+        names.iter().for_each(|name| remove(name));
+    }
+
+    #[actix_web::test]
+    async fn uploads_stop_at_their_deadline() {
+        use futures_util::stream;
+        use std::time::Duration;
+
+        let mut items = stream::iter([1, 2]);
+        assert_eq!(next_before(&mut items, None).await.unwrap(), Some(1));
+        let later = Some(tokio::time::Instant::now() + Duration::from_secs(60));
+        assert_eq!(next_before(&mut items, later).await.unwrap(), Some(2));
+        assert_eq!(next_before(&mut items, later).await.unwrap(), None);
+
+        let soon = Some(tokio::time::Instant::now() + Duration::from_millis(10));
+        let res = next_before(&mut stream::pending::<()>(), soon)
+            .await
+            .unwrap_err();
+        assert_eq!(res.status(), 408);
+
+        assert!(upload_deadline(&config()).is_none());
+        let limited = Config {
+            upload_max_duration: Some(Duration::from_secs(5)),
+            ..config()
+        };
+        let deadline = upload_deadline(&limited).unwrap();
+        assert!(deadline > tokio::time::Instant::now() + Duration::from_secs(4));
+    }
+
+    #[actix_web::test]
+    async fn stalled_uploads_time_out_without_leftovers() {
+        use actix_web::dev::Payload;
+        use actix_web::test::{call_service, init_service};
+        use futures_util::{stream, StreamExt};
+
+        let data = state(Config {
+            upload_max_duration: Some(std::time::Duration::from_millis(200)),
+            ..config()
+        });
+        let name = unique("stalled.txt");
+        let marker = Uuid::new_v4().to_string();
+        // The first chunk arrives, then the client goes quiet.
+        let body = stream::iter([Ok(web::Bytes::from(marker.clone()))])
+            .chain(stream::pending())
+            .boxed_local();
+        let (req, _) = upload(&name, "")
+            .to_request()
+            .replace_payload(Payload::Stream { payload: body });
+        let app = init_service(App::new().app_data(data.clone()).configure(routes)).await;
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), 408);
+
+        assert!(data.files.lock().unwrap().iter().all(|f| f.name != name));
+        assert!(!path(&name).exists());
+        let leftover = fs::read_dir(UPLOAD_DIR).unwrap().flatten().any(|entry| {
+            let file = entry.file_name().to_string_lossy().into_owned();
+            file.starts_with('.')
+                && file.ends_with(".part")
+                && fs::read(entry.path()).is_ok_and(|bytes| bytes == marker.as_bytes())
+        });
+        assert!(!leftover);
+    }
+
+    #[actix_web::test]
+    async fn uploads_honor_preconditions() {
+        let data = state(config());
+        let name = unique("pre.txt");
+        let res = json(call(&data, upload(&name, "one")).await).await;
+        let id = res["files"][0]["id"].as_str().unwrap().to_string();
+        let etag = format!(
+            "\"{}\"",
+            record(&data, &id).unwrap().checksum.unwrap().digest
+        );
+
+        let create = upload(&name, "two").insert_header((header::IF_NONE_MATCH, "*"));
+        assert_eq!(call(&data, create).await.status(), 412);
+
+        let stale = upload(&name, "two").insert_header((header::IF_MATCH, "\"stale\""));
+        assert_eq!(call(&data, stale).await.status(), 412);
+
+        let replace = upload(&name, "two").insert_header((header::IF_MATCH, etag.as_str()));
+        let res = json(call(&data, replace).await).await;
+        assert_eq!(res["files"][0]["name"], name.as_str());
+        assert_eq!(fs::read(path(&name)).unwrap(), b"two");
+
+        let missing = unique("missing.txt");
+        let req = upload(&missing, "x").insert_header((header::IF_MATCH, "*"));
+        assert_eq!(call(&data, req).await.status(), 412);
+        assert!(!path(&missing).exists());
+
+        let both = upload(&name, "x")
+            .insert_header((header::IF_MATCH, "*"))
+            .insert_header((header::IF_NONE_MATCH, "*"));
+        assert_eq!(call(&data, both).await.status(), 400);
+        let tagged = upload(&name, "x").insert_header((header::IF_NONE_MATCH, etag.as_str()));
+        assert_eq!(call(&data, tagged).await.status(), 400);
+        remove(&name);
+    }
+
+    #[test]
+    fn recognizes_unsafe_names() {
+        for name in ["", "..", "___", ".hidden", "CON", "con.txt", "Lpt1.tar.gz"] {
+            assert!(is_unsafe_name(name), "{:?}", name);
+        }
+        for name in ["report.pdf", "CONTRACT.txt", "a.", "nul_and_void"] {
+            assert!(!is_unsafe_name(name), "{:?}", name);
+        }
+        let id = "0123456789abcdef";
+        assert_eq!(safe_name("con.txt", id), "file_01234567.txt");
+        assert_eq!(safe_name("..", id), "file_01234567");
+        assert_eq!(safe_name(".tar.g-z", id), "file_01234567");
+    }
+
+    #[actix_web::test]
+    async fn unsafe_names_follow_the_policy() {
+        let data = state(config());
+        let res = json(call(&data, upload("CON.txt", "x")).await).await;
+        let stored = res["files"][0]["name"].as_str().unwrap().to_string();
+        assert!(stored.starts_with("file_") && stored.ends_with(".txt"));
+        remove(&stored);
+
+        let data = state(Config {
+            unsafe_names: UnsafeNamePolicy::Reject,
+            ..config()
+        });
+        let res = call(&data, upload("CON.txt", "x")).await;
+        assert_eq!(res.status(), 400);
+    }
+
+    #[test]
+    fn listing_order_is_stable_for_equal_times() {
+        let at = Utc::now();
+        let file = |id: &str, name: &str, secs: i64| FileInfo {
+            id: id.to_string(),
+            name: name.to_string(),
+            uploaded_at: at + chrono::Duration::seconds(secs),
+            ..Default::default()
+        };
+        let mut files = [
+            file("2", "b.txt", 0),
+            file("9", "z.txt", 1),
+            file("1", "b.txt", 0),
+            file("3", "a.txt", 0),
+        ];
+        files.sort_by(listing_order);
+        let order: Vec<&str> = files.iter().map(|f| f.id.as_str()).collect();
+        assert_eq!(order, ["9", "3", "1", "2"]);
+    }
+
+    #[test]
+    fn inline_types_match_exactly_or_by_family() {
+        let config = Config {
+            inline_types: vec!["image/png".to_string(), "audio/*".to_string()],
+            ..config()
+        };
+        assert!(inline_allowed(&config, "image/png"));
+        assert!(inline_allowed(&config, "IMAGE/PNG; charset=binary"));
+        assert!(inline_allowed(&config, "audio/ogg"));
+        assert!(!inline_allowed(&config, "image/svg+xml"));
+        assert!(!inline_allowed(&config, "audiox/ogg"));
+        assert!(!inline_allowed(&config, "text/html"));
+    }
+
+    #[actix_web::test]
+    async fn only_allowed_types_are_shown_inline() {
+        let data = state(config());
+        let (text, page) = (unique("notes.txt"), unique("page.html"));
+        add_file(&data, &text, b"hi", None);
+        add_file(&data, &page, b"<script></script>", None);
+        let get = |name: &str| {
+            let uri = format!("/api/download/{}?disposition=inline", name);
+            call(&data, TestRequest::get().uri(&uri))
+        };
+        assert!(disposition(&get(&text).await).starts_with("inline"));
+        assert!(disposition(&get(&page).await).starts_with("attachment"));
+        let res = call(&data, download(&text)).await;
+        assert!(disposition(&res).starts_with("attachment"));
+        remove(&text);
+        remove(&page);
+    }
+
+    #[actix_web::test]
+    async fn names_differing_by_case_collide_when_folded() {
+        let data = state(Config {
+            case_insensitive_names: CaseNames::Always,
+            ..config()
+        });
+        let name = unique("Report.txt");
+        let lower = name.to_lowercase();
+        add_file(&data, &name, b"one", None);
+        assert_eq!(
+            case_collision(&data, &data.files.lock().unwrap(), &lower),
+            Some(name.clone())
+        );
+        assert_eq!(
+            case_collision(&data, &data.files.lock().unwrap(), &name),
+            None
+        );
+
+        let stored = stored_as(&data, upload(&lower, "two")).await;
+        assert_ne!(stored, lower);
+        assert!(!path(&lower).exists());
+        remove(&stored);
+
+        let replace = upload(&lower, "three").insert_header((header::IF_MATCH, "*"));
+        assert_eq!(stored_as(&data, replace).await, name);
+        assert_eq!(fs::read(path(&name)).unwrap(), b"three");
+        remove(&name);
+
+        let data = state(Config {
+            case_insensitive_names: CaseNames::Never,
+            ..config()
+        });
+        add_file(&data, &name, b"one", None);
+        assert_eq!(
+            case_collision(&data, &data.files.lock().unwrap(), &lower),
+            None
+        );
+        remove(&name);
+    }
+
+    #[test]
+    fn sanitizes_names_in_nfc() {
+        let decomposed = "Cafe\u{301} menu/v2?.pdf";
+        let normalizing = Config {
+            normalize_names: true,
+            ..config()
+        };
+        assert_eq!(
+            sanitize_filename(&normalizing, decomposed),
+            "Caf\u{e9} menu_v2_.pdf"
+        );
+        let verbatim = Config {
+            normalize_names: false,
+            ..config()
+        };
+        assert_eq!(
+            sanitize_filename(&verbatim, decomposed),
+            "Cafe_ menu_v2_.pdf"
+        );
+        assert_eq!(sanitize_filename(&verbatim, "a-b_c.d e"), "a-b_c.d e");
+    }
+
+    #[actix_web::test]
+    async fn decomposed_upload_names_match_composed_ones() {
+        let data = state(Config {
+            normalize_names: true,
+            ..config()
+        });
+        let prefix = unique("");
+        // U+0301, the combining acute accent, percent-encoded as sent.
+        let stored = stored_as(
+            &data,
+            upload(&format!("{}re%CC%81sume%CC%81.txt", prefix), "cv"),
+        )
+        .await;
+        assert_eq!(stored, format!("{}r\u{e9}sum\u{e9}.txt", prefix));
+        remove(&stored);
+    }
+
+    #[actix_web::test]
+    async fn uploads_are_checked_against_the_expected_size() {
+        let data = state(config());
+        let sized = |name: &str, body: &str, size: &str| {
+            upload(name, body).insert_header(("X-Expected-Size", size))
+        };
+        let name = unique("sized.txt");
+        let res = json(call(&data, sized(&name, "12345", "5")).await).await;
+        assert_eq!(res["files"][0]["size"], 5);
+        remove(&name);
+
+        for size in ["4", "6"] {
+            let res = call(&data, sized(&name, "12345", size)).await;
+            assert_eq!(res.status(), 400);
+            assert!(!path(&name).exists());
+        }
+        let res = call(&data, sized(&name, "12345", "five")).await;
+        assert_eq!(res.status(), 400);
+
+        let data = state(Config {
+            size_mismatch: SizeMismatchPolicy::Record,
+            ..config()
+        });
+        let res = json(call(&data, sized(&name, "12345", "9")).await).await;
+        assert_eq!(res["files"][0]["size"], 5);
+        remove(&name);
+    }
+
+    #[actix_web::test]
+    async fn concurrent_uploads_of_one_name_never_share_a_file() {
+        use futures_util::{stream, StreamExt};
+
+        // Two uploads of `name`. The first stalls until the second has been
+        // stored, so both pick the free name before either finishes.
+        async fn race(
+            data: &web::Data<AppState>,
+            name: &str,
+        ) -> (Result<FileInfo, HttpResponse>, FileInfo) {
+            let declared = Declared {
+                name: Some(name),
+                mime_type: None,
+                size: None,
+            };
+            let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+            let mut slow = stream::once(async {
+                let _ = rx.await;
+                Ok::<_, std::io::Error>(web::Bytes::from_static(b"first"))
+            })
+            .boxed_local();
+            let mut fast =
+                stream::iter([Ok::<_, std::io::Error>(web::Bytes::from_static(b"second"))]);
+            let first = store_upload(
+                data,
+                &declared,
+                &mut slow,
+                None,
+                &Precondition::None,
+                "1.2.3.4",
+            );
+            let second = async {
+                let stored = store_upload(
+                    data,
+                    &declared,
+                    &mut fast,
+                    None,
+                    &Precondition::None,
+                    "1.2.3.4",
+                )
+                .await;
+                let _ = tx.send(());
+                stored
+            };
+            let (first, second) = futures_util::join!(first, second);
+            (first, second.ok().unwrap())
+        }
+
+        let data = state(config());
+        let name = unique("race.txt");
+        let (first, second) = race(&data, &name).await;
+        let first = first.ok().unwrap();
+        assert_eq!(second.name, name);
+        assert_ne!(first.name, second.name);
+        assert_eq!(fs::read(path(&second.name)).unwrap(), b"second");
+        assert_eq!(fs::read(path(&first.name)).unwrap(), b"first");
+        remove(&first.name);
+        remove(&second.name);
+
+        let data = state(Config {
+            unique_names: true,
+            ..config()
+        });
+        let (first, second) = race(&data, &name).await;
+        assert_eq!(first.unwrap_err().status(), 409);
+        assert_eq!(fs::read(path(&second.name)).unwrap(), b"second");
+        assert_eq!(data.files.lock().unwrap().len(), 1);
+        remove(&name);
+    }
+}
@@ -0,0 +1,175 @@
+//! EXIF metadata of photos, for `EXIF_METADATA` deployments.
+//!
+//! Read on request from the stored file (only the header is parsed) rather
+//! than stored on the record, so listings stay small. If EXIF is ever
+//! stripped from uploads, this reports whatever the stored file still has.
+
+use crate::{find_file, stored_path, AppState};
+use actix_web::{web, HttpResponse};
+use exif::{In, Tag, Value};
+use serde_json::{Map, Value as Json};
+use std::io::BufReader;
+use std::path::Path;
+
+/// Binary fields above this size (maker notes, thumbnails) are left out.
+const MAX_BINARY_FIELD: usize = 64;
+
+/// Fields from the primary image, keyed by tag name, plus decoded GPS
+/// coordinates when present. `Ok(None)` when the image has no EXIF. Blocking.
+fn read(path: &Path) -> Result<Option<Json>, String> {
+    let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+    let exif = match exif::Reader::new().read_from_container(&mut BufReader::new(file)) {
+        Ok(exif) => exif,
+        Err(exif::Error::NotFound(_)) | Err(exif::Error::InvalidFormat(_)) => return Ok(None),
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let mut fields = Map::new();
+    for field in exif.fields().filter(|f| f.ifd_num == In::PRIMARY) {
+        if matches!(&field.value, Value::Undefined(bytes, _) if bytes.len() > MAX_BINARY_FIELD) {
+            continue;
+        }
+        let value = field.display_value().with_unit(&exif).to_string();
+        fields.insert(field.tag.to_string(), value.trim_matches('"').into());
+    }
+
+    let coordinate = |tag: Tag, reference: Tag, negative: &str| {
+        let Value::Rational(dms) = &exif.get_field(tag, In::PRIMARY)?.value else {
+            return None;
+        };
+        let [d, m, s] = dms.as_slice() else {
+            return None;
+        };
+        let value = d.to_f64() + m.to_f64() / 60.0 + s.to_f64() / 3600.0;
+        let sign = match &exif.get_field(reference, In::PRIMARY)?.value {
+            Value::Ascii(v)
+                if v.first()
+                    .is_some_and(|r| r.as_slice() == negative.as_bytes()) =>
+            {
+                -1.0
+            }
+            _ => 1.0,
+        };
+        Some(sign * value)
+    };
+    let gps = match (
+        coordinate(Tag::GPSLatitude, Tag::GPSLatitudeRef, "S"),
+        coordinate(Tag::GPSLongitude, Tag::GPSLongitudeRef, "W"),
+    ) {
+        (Some(latitude), Some(longitude)) => {
+            serde_json::json!({"latitude": latitude, "longitude": longitude})
+        }
+        _ => Json::Null,
+    };
+
+    Ok(Some(serde_json::json!({ "fields": fields, "gps": gps })))
+}
+
+/// `GET /api/files/{id}/metadata`: `{"exif": {fields, gps}}`, with `exif`
+/// `null` for images that carry none.
+pub async fn get_metadata(path: web::Path<String>, data: web::Data<AppState>) -> HttpResponse {
+    if !data.config.exif_metadata {
+        return HttpResponse::NotFound().json(serde_json::json!({"error": "Not found"}));
+    }
+    let Some(info) = find_file(&data, &path.into_inner()) else {
+        return HttpResponse::NotFound().json(serde_json::json!({"error": "File not found"}));
+    };
+    if !info.mime_type.starts_with("image/") {
+        return HttpResponse::UnprocessableEntity()
+            .json(serde_json::json!({"error": "Not an image"}));
+    }
+    let Some(filepath) = stored_path(&data.config, &info.name) else {
+        return HttpResponse::NotFound().json(serde_json::json!({"error": "File not found"}));
+    };
+
+    match web::block(move || read(&filepath)).await {
+        Ok(Ok(exif)) => HttpResponse::Ok().json(serde_json::json!({ "id": info.id, "exif": exif })),
+        Ok(Err(e)) => HttpResponse::InternalServerError()
+            .json(serde_json::json!({"error": format!("Failed to read metadata: {}", e)})),
+        Err(_) => HttpResponse::InternalServerError()
+            .json(serde_json::json!({"error": "Metadata task failed"})),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{add_file, call, config, json, remove, state, unique};
+    use crate::Config;
+    use actix_web::test::TestRequest;
+
+    /// A JPEG holding only an EXIF segment: `Make` and a position in the
+    /// southern hemisphere, 33°30' S 151°15' E.
+    fn jpeg_with_exif() -> Vec<u8> {
+        let mut tiff = b"II\x2a\0\x08\0\0\0".to_vec();
+        let entry = |tiff: &mut Vec<u8>, tag: u16, kind: u16, count: u32, value: [u8; 4]| {
+            tiff.extend_from_slice(&tag.to_le_bytes());
+            tiff.extend_from_slice(&kind.to_le_bytes());
+            tiff.extend_from_slice(&count.to_le_bytes());
+            tiff.extend_from_slice(&value);
+        };
+        // IFD0 at 8, "Test" at 38, the GPS IFD at 44 and its rationals at 98.
+        tiff.extend_from_slice(&2u16.to_le_bytes());
+        entry(&mut tiff, 0x010f, 2, 5, 38u32.to_le_bytes());
+        entry(&mut tiff, 0x8825, 4, 1, 44u32.to_le_bytes());
+        tiff.extend_from_slice(&[0; 4]);
+        tiff.extend_from_slice(b"Test\0\0");
+        tiff.extend_from_slice(&4u16.to_le_bytes());
+        entry(&mut tiff, 1, 2, 2, *b"S\0\0\0");
+        entry(&mut tiff, 2, 5, 3, 98u32.to_le_bytes());
+        entry(&mut tiff, 3, 2, 2, *b"E\0\0\0");
+        entry(&mut tiff, 4, 5, 3, 122u32.to_le_bytes());
+        tiff.extend_from_slice(&[0; 4]);
+        for n in [33u32, 30, 0, 151, 15, 0] {
+            tiff.extend_from_slice(&n.to_le_bytes());
+            tiff.extend_from_slice(&1u32.to_le_bytes());
+        }
+
+        let mut jpeg = b"\xff\xd8\xff\xe1".to_vec();
+        jpeg.extend_from_slice(&(8 + tiff.len() as u16).to_be_bytes());
+        jpeg.extend_from_slice(b"Exif\0\0");
+        jpeg.extend_from_slice(&tiff);
+        jpeg.extend_from_slice(b"\xff\xd9");
+        jpeg
+    }
+
+    fn metadata(id: &str) -> TestRequest {
+        TestRequest::get().uri(&format!("/api/files/{}/metadata", id))
+    }
+
+    #[actix_web::test]
+    async fn reads_fields_and_coordinates() {
+        let data = state(Config {
+            exif_metadata: true,
+            ..config()
+        });
+        let name = unique("photo.jpg");
+        let photo = add_file(&data, &name, &jpeg_with_exif(), None);
+        let res = json(call(&data, metadata(&photo.id)).await).await;
+        assert_eq!(res["exif"]["fields"]["Make"], "Test");
+        assert_eq!(res["exif"]["gps"]["latitude"], -33.5);
+        assert_eq!(res["exif"]["gps"]["longitude"], 151.25);
+        remove(&name);
+    }
+
+    #[actix_web::test]
+    async fn only_serves_images_when_enabled() {
+        let data = state(Config {
+            exif_metadata: true,
+            ..config()
+        });
+        let (plain, text) = (unique("plain.jpg"), unique("notes.txt"));
+        let plain_info = add_file(&data, &plain, b"\xff\xd8\xff\xd9", None);
+        let text_info = add_file(&data, &text, b"hello", None);
+        let res = json(call(&data, metadata(&plain_info.id)).await).await;
+        assert!(res["exif"].is_null());
+        let res = call(&data, metadata(&text_info.id)).await;
+        assert_eq!(res.status(), 422);
+
+        let off = state(config());
+        off.files.lock().unwrap().push(plain_info.clone());
+        let res = call(&off, metadata(&plain_info.id)).await;
+        assert_eq!(res.status(), 404);
+        remove(&plain);
+        remove(&text);
+    }
+}
@@ -0,0 +1,107 @@
+//! MIME types by extension: `mime_guess`, corrected by a table of
+//! overrides so uploads are classified and served consistently whatever
+//! the library version thinks.
+
+use crate::config::Config;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Built-in corrections for formats `mime_guess` gets wrong, leaves as
+/// `application/octet-stream` or has changed its mind about between
+/// releases. `MIME_OVERRIDES` entries take precedence.
+const BUILTIN: &[(&str, &str)] = &[
+    ("md", "text/markdown"),
+    ("markdown", "text/markdown"),
+    ("log", "text/plain"),
+    ("js", "text/javascript"),
+    ("mjs", "text/javascript"),
+    ("toml", "application/toml"),
+    ("yaml", "application/x-yaml"),
+    ("yml", "application/x-yaml"),
+    ("wasm", "application/wasm"),
+    ("gz", "application/gzip"),
+    ("zst", "application/zstd"),
+    ("7z", "application/x-7z-compressed"),
+    ("svg", "image/svg+xml"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("heic", "image/heic"),
+    ("heif", "image/heif"),
+    ("jxl", "image/jxl"),
+    ("webm", "video/webm"),
+    ("mkv", "video/x-matroska"),
+    ("opus", "audio/opus"),
+    ("flac", "audio/flac"),
+    ("m4a", "audio/mp4"),
+];
+
+/// Builds the override table from the built-ins plus `MIME_OVERRIDES`, a
+/// comma-separated list of `ext=type/subtype` pairs.
+pub fn overrides(spec: Option<&str>) -> Result<HashMap<String, String>, String> {
+    let mut table: HashMap<String, String> = BUILTIN
+        .iter()
+        .map(|(ext, mime)| (ext.to_string(), mime.to_string()))
+        .collect();
+    for entry in spec.unwrap_or_default().split(',').map(str::trim) {
+        if entry.is_empty() {
+            continue;
+        }
+        let parsed = entry.split_once('=').and_then(|(ext, mime)| {
+            let ext = ext.trim().trim_start_matches('.').to_ascii_lowercase();
+            let mime = mime.trim().to_ascii_lowercase();
+            let valid = !ext.is_empty() && mime.parse::<mime_guess::Mime>().is_ok();
+            valid.then_some((ext, mime))
+        });
+        let Some((ext, mime)) = parsed else {
+            return Err(format!(
+                "invalid MIME_OVERRIDES entry '{}' (expected ext=type/subtype)",
+                entry
+            ));
+        };
+        table.insert(ext, mime);
+    }
+    Ok(table)
+}
+
+/// The MIME type for a file name, with overrides applied.
+pub fn guess(config: &Config, path: impl AsRef<Path>) -> String {
+    let path = path.as_ref();
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(|ext| config.mime_overrides.get(&ext.to_ascii_lowercase()))
+        .cloned()
+        .unwrap_or_else(|| {
+            mime_guess::from_path(path)
+                .first_or_octet_stream()
+                .to_string()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::config;
+
+    #[test]
+    fn parses_overrides_over_the_builtins() {
+        let table = overrides(Some(" .BIN=Application/X-Custom, md=text/x-markdown,")).unwrap();
+        assert_eq!(table["bin"], "application/x-custom");
+        assert_eq!(table["md"], "text/x-markdown");
+        assert_eq!(table["webp"], "image/webp");
+        assert!(overrides(Some("bin")).is_err());
+        assert!(overrides(Some("=text/plain")).is_err());
+        assert!(overrides(Some("bin=not a type")).is_err());
+    }
+
+    #[test]
+    fn guesses_with_overrides_applied() {
+        let config = Config {
+            mime_overrides: overrides(Some("log=text/x-log")).unwrap(),
+            ..config()
+        };
+        assert_eq!(guess(&config, "notes.MD"), "text/markdown");
+        assert_eq!(guess(&config, "server.log"), "text/x-log");
+        assert_eq!(guess(&config, "photo.png"), "image/png");
+        assert_eq!(guess(&config, "README"), "application/octet-stream");
+    }
+}
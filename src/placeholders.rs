@@ -0,0 +1,292 @@
+//! `POST /api/files/placeholder`: a `pending` record for an upload that has
+//! not started yet, so a UI can list the file straight away.
+//!
+//! The placeholder is filled by an upload sent with `X-Placeholder-Id`,
+//! which stores the file under the placeholder's id and name and flips it
+//! to `ready`. Until then it is listed but cannot be downloaded or read, and
+//! it is dropped after `PLACEHOLDER_TTL_SECS` if no upload completes.
+
+use crate::{
+    checked_name, client_ip, free_name, listing_order, mimetypes, name_conflict, name_taken,
+    sanitize_filename, AppState, FileInfo, FileStatus, MAX_FILE_SIZE,
+};
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::time::Duration;
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+struct PlaceholderRequest {
+    name: String,
+    /// Expected size, shown until the upload replaces it with the real one.
+    #[serde(default)]
+    size: u64,
+    #[serde(default, alias = "mimeType")]
+    mime_type: Option<String>,
+}
+
+pub fn gone() -> HttpResponse {
+    HttpResponse::Gone().json(serde_json::json!({"error": "Placeholder expired or was deleted"}))
+}
+
+pub async fn create_placeholder(
+    req: HttpRequest,
+    body: web::Bytes,
+    data: web::Data<AppState>,
+) -> HttpResponse {
+    let body: PlaceholderRequest = match serde_json::from_slice(&body) {
+        Ok(b) => b,
+        Err(e) => {
+            return HttpResponse::BadRequest()
+                .json(serde_json::json!({"error": format!("Invalid request: {}", e)}));
+        }
+    };
+    if let Err(res) = data.storage.require_writable() {
+        return res;
+    }
+    if body.size > MAX_FILE_SIZE as u64 {
+        return HttpResponse::PayloadTooLarge()
+            .json(serde_json::json!({"error": "File too large (max 10 GB)"}));
+    }
+    let id = Uuid::new_v4().to_string();
+    let declared_mime = match body.mime_type {
+        Some(mime) if mime.parse::<mime_guess::Mime>().is_ok() => Some(mime.to_ascii_lowercase()),
+        Some(mime) => {
+            return HttpResponse::BadRequest()
+                .json(serde_json::json!({"error": format!("Invalid MIME type '{}'", mime)}));
+        }
+        None => None,
+    };
+    // Checked now, like an upload's name, so a placeholder is never
+    // created only to be refused when it is filled.
+    let name = sanitize_filename(&data.config, &body.name);
+    let mut name = match checked_name(&data.config, name, &id, declared_mime.as_deref()) {
+        Ok(name) => name,
+        Err(res) => return res,
+    };
+    let mime_type = declared_mime.unwrap_or_else(|| mimetypes::guess(&data.config, &name));
+
+    let mut files = data.files.lock().unwrap();
+    // A placeholder must not share a name with a stored file, or filling it
+    // would not be the only thing writing to that name.
+    if name_taken(&data, &files, &name, &id) {
+        if data.config.unique_names {
+            return name_conflict(&name);
+        }
+        match free_name(&data, &files, &name, &id) {
+            Some(free) => name = free,
+            None => return name_conflict(&name),
+        }
+    }
+    let info = FileInfo {
+        id,
+        name,
+        size: body.size,
+        mime_type,
+        uploaded_at: Utc::now(),
+        owner: Some(client_ip(&req)),
+        status: FileStatus::Pending,
+        ..Default::default()
+    };
+    let pos = files.partition_point(|f| listing_order(f, &info).is_lt());
+    files.insert(pos, info.clone());
+    HttpResponse::Ok().json(serde_json::json!({
+        "id": info.id,
+        "file": info,
+        "expires_at": info.uploaded_at
+            + chrono::Duration::from_std(data.config.placeholder_ttl).unwrap_or_default(),
+    }))
+}
+
+/// Drops the placeholders older than `PLACEHOLDER_TTL_SECS` at `now`.
+fn expire(data: &AppState, now: DateTime<Utc>) {
+    let ttl =
+        chrono::Duration::from_std(data.config.placeholder_ttl).unwrap_or(chrono::TimeDelta::MAX);
+    data.files
+        .lock()
+        .unwrap()
+        .retain(|f| f.status != FileStatus::Pending || now - f.uploaded_at < ttl);
+}
+
+/// Drops expired placeholders every minute, or every half TTL when shorter.
+pub fn start_expiry(data: web::Data<AppState>) {
+    let ttl = data.config.placeholder_ttl;
+    let interval = (ttl / 2).clamp(Duration::from_secs(1), Duration::from_secs(60));
+    actix_web::rt::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            expire(&data, Utc::now());
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{
+        add_file, call, config, json, path, record, remove, state, unique, upload,
+    };
+    use crate::{Config, FileInfo, FileStatus};
+    use actix_web::test::TestRequest;
+
+    fn placeholder(body: serde_json::Value) -> TestRequest {
+        TestRequest::post()
+            .uri("/api/files/placeholder")
+            .peer_addr("1.2.3.4:4000".parse().unwrap())
+            .set_json(body)
+    }
+
+    #[actix_web::test]
+    async fn uploads_fill_placeholders() {
+        let data = state(config());
+        let name = unique("video.mp4");
+        let res = json(
+            call(
+                &data,
+                placeholder(serde_json::json!({"name": name, "size": 5})),
+            )
+            .await,
+        )
+        .await;
+        let id = res["id"].as_str().unwrap().to_string();
+        assert_eq!(res["file"]["status"], "pending");
+        assert_eq!(res["file"]["mime_type"], "video/mp4");
+        let download = TestRequest::get().uri(&format!("/api/download/{}", name));
+        assert_eq!(call(&data, download).await.status(), 404);
+
+        let fill = upload("ignored.bin", "bytes").insert_header(("X-Placeholder-Id", id.as_str()));
+        let res = json(call(&data, fill).await).await;
+        assert_eq!(res["files"][0]["id"], id.as_str());
+        assert_eq!(res["files"][0]["name"], name.as_str());
+        assert_eq!(std::fs::read(path(&name)).unwrap(), b"bytes");
+        assert!(record(&data, &id).unwrap().is_ready());
+
+        let again = upload("ignored.bin", "x").insert_header(("X-Placeholder-Id", id.as_str()));
+        assert_eq!(call(&data, again).await.status(), 410);
+        remove(&name);
+    }
+
+    #[actix_web::test]
+    async fn rejects_invalid_placeholders() {
+        let data = state(config());
+        let bad_type = serde_json::json!({"name": "a.txt", "mime_type": "not a type"});
+        assert_eq!(call(&data, placeholder(bad_type)).await.status(), 400);
+        let too_big = serde_json::json!({"name": "a.txt", "size": u64::MAX});
+        assert_eq!(call(&data, placeholder(too_big)).await.status(), 413);
+        let combined = upload("a.txt", "x")
+            .insert_header(("X-Placeholder-Id", "x"))
+            .insert_header(("If-Match", "*"));
+        assert_eq!(call(&data, combined).await.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn placeholder_names_do_not_collide_with_files() {
+        let data = state(config());
+        let name = unique("taken.txt");
+        add_file(&data, &name, b"stored", None);
+        let res = json(call(&data, placeholder(serde_json::json!({"name": name}))).await).await;
+        let id = res["id"].as_str().unwrap();
+        let expected = name.replace(".txt", &format!("_{}.txt", &id[..8]));
+        assert_eq!(res["file"]["name"], expected.as_str());
+
+        let data = state(Config {
+            unique_names: true,
+            ..config()
+        });
+        add_file(&data, &name, b"stored", None);
+        let res = call(&data, placeholder(serde_json::json!({"name": name}))).await;
+        assert_eq!(res.status(), 409);
+        remove(&name);
+    }
+
+    #[actix_web::test]
+    async fn deleting_a_placeholder_leaves_a_same_named_file_alone() {
+        let data = state(config());
+        let name = unique("shared.txt");
+        let stored = add_file(&data, &name, b"stored", None);
+        crate::ids::save(&name, &stored.id);
+        let pending = FileInfo {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: name.clone(),
+            status: FileStatus::Pending,
+            ..Default::default()
+        };
+        data.files.lock().unwrap().push(pending.clone());
+
+        let res = json(call(&data, TestRequest::get().uri("/api/changes")).await).await;
+        let cursor = res["cursor"].as_str().unwrap().to_string();
+        let delete = TestRequest::delete().uri(&format!("/api/files/{}", pending.id));
+        assert_eq!(call(&data, delete).await.status(), 200);
+        assert!(record(&data, &pending.id).is_none());
+        assert!(record(&data, &stored.id).is_some());
+        assert_eq!(std::fs::read(path(&name)).unwrap(), b"stored");
+        assert_eq!(crate::ids::load(&name), stored.id);
+        let changes = format!("/api/changes?since={}", cursor);
+        let res = json(call(&data, TestRequest::get().uri(&changes)).await).await;
+        assert_eq!(res["deleted"], serde_json::json!([]));
+        remove(&name);
+    }
+
+    #[test]
+    fn expiry_drops_only_stale_placeholders() {
+        let data = state(Config {
+            placeholder_ttl: Duration::from_secs(60),
+            ..config()
+        });
+        let now = Utc::now();
+        let record = |id: &str, status, age: i64| FileInfo {
+            id: id.to_string(),
+            name: format!("{}.txt", id),
+            status,
+            uploaded_at: now - chrono::Duration::seconds(age),
+            ..Default::default()
+        };
+        data.files.lock().unwrap().extend([
+            record("stale", FileStatus::Pending, 61),
+            record("fresh", FileStatus::Pending, 59),
+            record("old-file", FileStatus::Ready, 3600),
+        ]);
+        expire(&data, now);
+        let left: Vec<String> = data
+            .files
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|f| f.id.clone())
+            .collect();
+        assert_eq!(left, ["fresh", "old-file"]);
+    }
+
+    #[actix_web::test]
+    async fn placeholder_names_follow_the_upload_name_policies() {
+        use crate::config::{ExtensionlessPolicy, UnsafeNamePolicy};
+
+        let data = state(Config {
+            extensionless_uploads: ExtensionlessPolicy::Reject,
+            unsafe_names: UnsafeNamePolicy::Reject,
+            ..config()
+        });
+        let res = call(
+            &data,
+            placeholder(serde_json::json!({"name": unique("README")})),
+        )
+        .await;
+        assert_eq!(res.status(), 422);
+        let res = call(&data, placeholder(serde_json::json!({"name": "CON.txt"}))).await;
+        assert_eq!(res.status(), 400);
+        assert!(data.files.lock().unwrap().is_empty());
+
+        let data = state(Config {
+            extensionless_uploads: ExtensionlessPolicy::Derive,
+            ..config()
+        });
+        let name = unique("scan");
+        let body = serde_json::json!({"name": name, "mime_type": "application/pdf"});
+        let res = json(call(&data, placeholder(body)).await).await;
+        assert_eq!(res["file"]["name"], format!("{}.pdf", name));
+        assert_eq!(res["file"]["mime_type"], "application/pdf");
+    }
+}
@@ -0,0 +1,137 @@
+//! Optional external upload policy check (`POLICY_URL`).
+//!
+//! Once an upload has been received and hashed, but before it is moved into
+//! place, its metadata is POSTed as JSON to the policy endpoint:
+//!
+//! ```json
+//! {"name": "report.pdf", "size": 1234, "mime_type": "application/pdf",
+//!  "checksum": {"algorithm": "sha256", "digest": "…"}, "client": "10.0.0.5"}
+//! ```
+//!
+//! A `2xx` reply of `{"allow": true}` accepts the file; `{"allow": false,
+//! "reason": "…"}` rejects it with `403`. Any other outcome (timeout,
+//! connection error, non-`2xx` status, unparsable body) is a failure of the
+//! service and is decided by `POLICY_FAIL_OPEN`.
+
+use crate::config::Config;
+use crate::hashing::Checksum;
+use crate::logging;
+use actix_web::HttpResponse;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize)]
+pub struct PolicyRequest<'a> {
+    pub name: &'a str,
+    pub size: u64,
+    pub mime_type: &'a str,
+    pub checksum: &'a Checksum,
+    pub client: &'a str,
+}
+
+#[derive(Deserialize)]
+struct PolicyReply {
+    allow: bool,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// The service accepted the upload.
+    Allowed,
+    /// The service could not be reached and `POLICY_FAIL_OPEN` let it through.
+    FailedOpen,
+}
+
+/// The decision recorded on an accepted upload.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PolicyDecision {
+    pub outcome: Outcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(alias = "checkedAt")]
+    pub checked_at: DateTime<Utc>,
+}
+
+async fn ask(
+    url: &str,
+    config: &Config,
+    request: &PolicyRequest<'_>,
+) -> Result<PolicyReply, String> {
+    let client = awc::Client::builder()
+        .timeout(config.policy_timeout)
+        .finish();
+    let call = async {
+        let mut res = client
+            .post(url)
+            .send_json(request)
+            .await
+            .map_err(|e| e.to_string())?;
+        if !res.status().is_success() {
+            return Err(format!("policy service returned {}", res.status()));
+        }
+        res.json::<PolicyReply>().await.map_err(|e| e.to_string())
+    };
+    tokio::time::timeout(config.policy_timeout, call)
+        .await
+        .map_err(|_| "policy service timed out".to_string())?
+}
+
+/// Asks the policy service about an upload. Returns `Ok(None)` when no
+/// service is configured, and the response to send when the upload must be
+/// refused.
+pub async fn check(
+    config: &Config,
+    request: &PolicyRequest<'_>,
+) -> Result<Option<PolicyDecision>, HttpResponse> {
+    let Some(url) = config.policy_url.as_deref() else {
+        return Ok(None);
+    };
+    match ask(url, config, request).await {
+        Ok(PolicyReply {
+            allow: true,
+            reason,
+        }) => Ok(Some(PolicyDecision {
+            outcome: Outcome::Allowed,
+            reason,
+            checked_at: Utc::now(),
+        })),
+        Ok(PolicyReply {
+            allow: false,
+            reason,
+        }) => {
+            logging::event(
+                "info",
+                "upload rejected by policy",
+                serde_json::json!({"file": request.name, "client": request.client, "reason": reason}),
+            );
+            Err(HttpResponse::Forbidden().json(serde_json::json!({
+                "error": "Upload rejected by policy",
+                "reason": reason,
+            })))
+        }
+        Err(e) => {
+            logging::event(
+                "warn",
+                "upload policy check failed",
+                serde_json::json!({
+                    "file": request.name,
+                    "error": e,
+                    "fail_open": config.policy_fail_open,
+                }),
+            );
+            if config.policy_fail_open {
+                Ok(Some(PolicyDecision {
+                    outcome: Outcome::FailedOpen,
+                    reason: Some(e),
+                    checked_at: Utc::now(),
+                }))
+            } else {
+                Err(HttpResponse::ServiceUnavailable()
+                    .json(serde_json::json!({"error": "Upload policy service unavailable"})))
+            }
+        }
+    }
+}
@@ -0,0 +1,237 @@
+//! Optional gzip and brotli variants of compressible uploads, created once at
+//! upload time and served to clients that accept them.
+//!
+//! Variants live in `uploads/.variants/<name>.gz` and `<name>.br`. The
+//! startup scan skips dot entries, so they never show up as files. A variant
+//! is only served while it is at least as new as the original, so a stale one
+//! left behind by a replaced file is ignored rather than served.
+
+use crate::textstats::is_text;
+use crate::UPLOAD_DIR;
+use actix_web::http::header;
+use actix_web::HttpRequest;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+const VARIANT_DIR: &str = ".variants";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    /// In order of preference.
+    const ALL: [Encoding; 2] = [Encoding::Brotli, Encoding::Gzip];
+
+    /// The `Content-Encoding` token.
+    pub fn token(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gz",
+        }
+    }
+}
+
+/// The entity tag of a variant of the file tagged `etag`.
+pub fn variant_etag(etag: &str, encoding: Encoding) -> String {
+    format!("{}-{}\"", etag.trim_end_matches('"'), encoding.token())
+}
+
+/// Every tag a variant of the file tagged `etag` may be sent with.
+pub fn variant_etags(etag: &str) -> Vec<String> {
+    Encoding::ALL
+        .iter()
+        .map(|&e| variant_etag(etag, e))
+        .collect()
+}
+
+pub fn is_compressible(mime: &str) -> bool {
+    is_text(mime) || matches!(mime, "image/svg+xml" | "application/wasm")
+}
+
+fn variant_path(name: &str, encoding: Encoding) -> PathBuf {
+    Path::new(UPLOAD_DIR)
+        .join(VARIANT_DIR)
+        .join(format!("{}.{}", name, encoding.extension()))
+}
+
+/// Compresses the stored file `name` into every encoding and returns the
+/// disk space the variants take. The file is streamed through the encoders,
+/// so memory use does not grow with its size. Variants that would not be
+/// smaller than the original are not kept, and none are kept if the file is
+/// replaced meanwhile. Blocking.
+pub fn create(name: &str) -> io::Result<u64> {
+    let source = Path::new(UPLOAD_DIR).join(name);
+    let before = fs::metadata(&source)?;
+    fs::create_dir_all(Path::new(UPLOAD_DIR).join(VARIANT_DIR))?;
+    let mut total = 0;
+    for encoding in Encoding::ALL {
+        let path = variant_path(name, encoding);
+        let tmp = path.with_extension("tmp");
+        let mut input = BufReader::new(File::open(&source)?);
+        let output = BufWriter::new(File::create(&tmp)?);
+        let written = match encoding {
+            Encoding::Gzip => {
+                let mut enc = flate2::write::GzEncoder::new(output, flate2::Compression::best());
+                io::copy(&mut input, &mut enc)?;
+                enc.finish()?.into_inner().map_err(|e| e.into_error())?
+            }
+            Encoding::Brotli => {
+                let mut enc = brotli::CompressorWriter::new(output, 64 * 1024, 11, 22);
+                io::copy(&mut input, &mut enc)?;
+                enc.flush()?;
+                enc.into_inner().into_inner().map_err(|e| e.into_error())?
+            }
+        };
+        written.sync_all()?;
+        let size = written.metadata()?.len();
+        if size >= before.len() {
+            let _ = fs::remove_file(&tmp);
+            let _ = fs::remove_file(&path);
+            continue;
+        }
+        fs::rename(&tmp, &path)?;
+        total += size;
+    }
+    // A replacement written while we compressed would otherwise be shadowed
+    // by variants of the old contents, which are newer than it.
+    if !same_file(&before, &fs::metadata(&source)?) {
+        remove(name);
+        return Ok(0);
+    }
+    Ok(total)
+}
+
+#[cfg(unix)]
+fn same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.ino() == b.ino() && a.len() == b.len() && a.modified().ok() == b.modified().ok()
+}
+
+#[cfg(not(unix))]
+fn same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    a.len() == b.len() && a.modified().ok() == b.modified().ok()
+}
+
+pub fn remove(name: &str) {
+    for encoding in Encoding::ALL {
+        let _ = fs::remove_file(variant_path(name, encoding));
+    }
+}
+
+/// Moves the variants of a renamed file along with it.
+pub fn rename(from: &str, to: &str) {
+    for encoding in Encoding::ALL {
+        let _ = fs::rename(variant_path(from, encoding), variant_path(to, encoding));
+    }
+}
+
+/// Disk space taken by the variants of `name`, if it has any.
+pub fn disk_usage(name: &str) -> Option<u64> {
+    let sizes: Vec<u64> = Encoding::ALL
+        .iter()
+        .filter_map(|&e| fs::metadata(variant_path(name, e)).ok())
+        .map(|m| m.len())
+        .collect();
+    (!sizes.is_empty()).then(|| sizes.iter().sum())
+}
+
+/// Whether the stored file has at least one usable variant, i.e. whether
+/// its responses depend on `Accept-Encoding`.
+pub fn has_variants(name: &str, original: &fs::Metadata) -> bool {
+    Encoding::ALL
+        .iter()
+        .any(|&e| fresh_variant(name, e, original).is_some())
+}
+
+/// Picks the preferred variant the client accepts. Returns its encoding,
+/// path and metadata.
+pub fn negotiate(
+    req: &HttpRequest,
+    name: &str,
+    original: &fs::Metadata,
+) -> Option<(Encoding, PathBuf, fs::Metadata)> {
+    let accept = req.headers().get(header::ACCEPT_ENCODING)?.to_str().ok()?;
+    let accepted = |encoding: Encoding| {
+        accept.split(',').any(|item| {
+            let mut parts = item.split(';').map(str::trim);
+            let token = parts.next().unwrap_or_default();
+            let q = parts
+                .find_map(|p| p.strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            q > 0.0 && (token.eq_ignore_ascii_case(encoding.token()) || token == "*")
+        })
+    };
+    Encoding::ALL
+        .into_iter()
+        .filter(|&e| accepted(e))
+        .find_map(|e| fresh_variant(name, e, original).map(|(path, meta)| (e, path, meta)))
+}
+
+fn fresh_variant(
+    name: &str,
+    encoding: Encoding,
+    original: &fs::Metadata,
+) -> Option<(PathBuf, fs::Metadata)> {
+    let path = variant_path(name, encoding);
+    let meta = fs::metadata(&path).ok()?;
+    (meta.modified().ok()? >= original.modified().ok()?).then_some((path, meta))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{path, unique};
+    use std::io::Read;
+
+    #[test]
+    fn variants_decompress_to_the_original() {
+        std::fs::create_dir_all(UPLOAD_DIR).unwrap();
+        let name = unique("notes.txt");
+        let contents = "all work and no play\n".repeat(20_000);
+        fs::write(path(&name), &contents).unwrap();
+
+        let total = create(&name).unwrap();
+        assert!(total > 0 && total < contents.len() as u64);
+        assert_eq!(disk_usage(&name), Some(total));
+
+        let mut gzip = String::new();
+        flate2::read::GzDecoder::new(File::open(variant_path(&name, Encoding::Gzip)).unwrap())
+            .read_to_string(&mut gzip)
+            .unwrap();
+        assert_eq!(gzip, contents);
+        let mut brotli = String::new();
+        brotli::Decompressor::new(
+            File::open(variant_path(&name, Encoding::Brotli)).unwrap(),
+            4096,
+        )
+        .read_to_string(&mut brotli)
+        .unwrap();
+        assert_eq!(brotli, contents);
+
+        remove(&name);
+        assert_eq!(disk_usage(&name), None);
+        fs::remove_file(path(&name)).unwrap();
+    }
+
+    #[test]
+    fn variants_that_do_not_shrink_are_dropped() {
+        std::fs::create_dir_all(UPLOAD_DIR).unwrap();
+        let name = unique("tiny.txt");
+        fs::write(path(&name), "x").unwrap();
+        assert_eq!(create(&name).unwrap(), 0);
+        assert_eq!(disk_usage(&name), None);
+        fs::remove_file(path(&name)).unwrap();
+    }
+}
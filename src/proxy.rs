@@ -0,0 +1,190 @@
+//! Which forwarding headers to believe (`TRUSTED_PROXIES`).
+//!
+//! `X-Forwarded-For` is only read when the connection comes from a trusted
+//! proxy, and then from the right: each proxy appends the address it saw,
+//! so the first hop that is not itself a trusted proxy is the client. Hops a
+//! client wrote itself sit further left and are never reached. Without
+//! trusted proxies the client is simply the connection's peer address.
+//!
+//! Likewise `X-Forwarded-Host` and `X-Forwarded-Proto` only count from a
+//! trusted proxy; otherwise the host is the `Host` header and the scheme is
+//! plain `http`.
+
+use actix_web::dev::RequestHead;
+use actix_web::http::header;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// A single address or a CIDR range such as `10.0.0.0/8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNet {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpNet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid address or CIDR range '{}'", s);
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p.parse().ok().filter(|&p| p <= max).ok_or_else(invalid)?,
+            None => max,
+        };
+        Ok(IpNet { addr, prefix })
+    }
+}
+
+fn is_trusted(trusted: &[IpNet], ip: IpAddr) -> bool {
+    trusted.iter().any(|net| net.contains(ip))
+}
+
+/// The connection's peer when it is a trusted proxy.
+fn trusted_peer(head: &RequestHead, trusted: &[IpNet]) -> Option<IpAddr> {
+    head.peer_addr
+        .map(|a| a.ip())
+        .filter(|&ip| is_trusted(trusted, ip))
+}
+
+/// Last value of a header set by a trusted proxy, which overrides whatever
+/// the client sent in front of it.
+fn forwarded(head: &RequestHead, trusted: &[IpNet], name: &str) -> Option<String> {
+    trusted_peer(head, trusted)?;
+    let value = head.headers().get_all(name).last()?.to_str().ok()?;
+    let last = value.rsplit(',').next()?.trim();
+    (!last.is_empty()).then(|| last.to_string())
+}
+
+/// The host the request was sent to: `Host` (or the authority of an
+/// absolute request target), or `X-Forwarded-Host` from a trusted proxy.
+pub fn host(head: &RequestHead, trusted: &[IpNet]) -> Option<String> {
+    if let Some(host) = forwarded(head, trusted, "x-forwarded-host") {
+        return Some(host);
+    }
+    head.headers()
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+        .or_else(|| head.uri.authority().map(|a| a.to_string()))
+}
+
+/// `https` when a trusted proxy says the request arrived over TLS.
+pub fn scheme(head: &RequestHead, trusted: &[IpNet]) -> &'static str {
+    match forwarded(head, trusted, "x-forwarded-proto") {
+        Some(proto) if proto.eq_ignore_ascii_case("https") => "https",
+        _ => "http",
+    }
+}
+
+/// The client's address: the peer, or for requests through a trusted proxy
+/// the right-most `X-Forwarded-For` hop that is not a trusted proxy.
+pub fn client_addr(head: &RequestHead, trusted: &[IpNet]) -> Option<IpAddr> {
+    let peer = head.peer_addr.map(|a| a.ip())?;
+    if trusted_peer(head, trusted).is_none() {
+        return Some(peer);
+    }
+    let hops: Vec<&str> = head
+        .headers()
+        .get_all("x-forwarded-for")
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .collect();
+    let mut client = peer;
+    for hop in hops.iter().rev() {
+        // Anything unparseable was not written by a proxy we trust.
+        let Ok(ip) = hop.parse::<IpAddr>() else {
+            break;
+        };
+        client = ip;
+        if !is_trusted(trusted, ip) {
+            break;
+        }
+    }
+    Some(client)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn nets(list: &[&str]) -> Vec<IpNet> {
+        list.iter().map(|s| s.parse().unwrap()).collect()
+    }
+
+    fn client(peer: &str, forwarded: Option<&str>, trusted: &[&str]) -> String {
+        let mut req = TestRequest::default().peer_addr(format!("{}:1234", peer).parse().unwrap());
+        if let Some(xff) = forwarded {
+            req = req.insert_header(("X-Forwarded-For", xff));
+        }
+        let req = req.to_http_request();
+        client_addr(req.head(), &nets(trusted)).unwrap().to_string()
+    }
+
+    #[test]
+    fn parses_addresses_and_ranges() {
+        let net: IpNet = "10.0.0.0/8".parse().unwrap();
+        assert!(net.contains("10.1.2.3".parse().unwrap()));
+        assert!(!net.contains("11.0.0.1".parse().unwrap()));
+        let single: IpNet = "::1".parse().unwrap();
+        assert!(single.contains("::1".parse().unwrap()));
+        assert!(!single.contains("::2".parse().unwrap()));
+        let all: IpNet = "0.0.0.0/0".parse().unwrap();
+        assert!(all.contains("8.8.8.8".parse().unwrap()));
+        assert!(all.contains("::ffff:8.8.8.8".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<IpNet>().is_err());
+        assert!("example.com".parse::<IpNet>().is_err());
+    }
+
+    #[test]
+    fn ignores_forwarded_for_from_untrusted_peers() {
+        assert_eq!(client("1.2.3.4", Some("6.6.6.6"), &[]), "1.2.3.4");
+        assert_eq!(
+            client("1.2.3.4", Some("6.6.6.6"), &["127.0.0.1"]),
+            "1.2.3.4"
+        );
+    }
+
+    #[test]
+    fn takes_the_right_most_untrusted_hop() {
+        let trusted = ["127.0.0.1", "10.0.0.0/8"];
+        assert_eq!(client("127.0.0.1", Some("9.9.9.9"), &trusted), "9.9.9.9");
+        // A forged entry in front of the real one is never reached.
+        assert_eq!(
+            client("127.0.0.1", Some("6.6.6.6, 9.9.9.9"), &trusted),
+            "9.9.9.9"
+        );
+        assert_eq!(
+            client("127.0.0.1", Some("6.6.6.6, 9.9.9.9, 10.0.0.2"), &trusted),
+            "9.9.9.9"
+        );
+        assert_eq!(client("127.0.0.1", None, &trusted), "127.0.0.1");
+        assert_eq!(
+            client("127.0.0.1", Some("garbage, 10.0.0.2"), &trusted),
+            "10.0.0.2"
+        );
+    }
+}
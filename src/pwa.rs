@@ -0,0 +1,64 @@
+//! Installable web app support (`PWA`): a web app manifest and a service
+//! worker that keeps the UI and the last file listing available offline.
+//!
+//! Every URL involved is relative, so the manifest scope and the service
+//! worker scope follow wherever the UI is mounted, including under a path
+//! prefix added by a reverse proxy. Downloads are never cached.
+
+use crate::AppState;
+use actix_web::http::header;
+use actix_web::{web, HttpResponse};
+
+const SERVICE_WORKER: &str = include_str!("../static/sw.js");
+const ICON: &str = include_str!("../static/icon.svg");
+
+/// Tags added to the page head when `PWA` is on.
+pub const HEAD_TAGS: &str = concat!(
+    "    <link rel=\"manifest\" href=\"./manifest.json\">\n",
+    "    <link rel=\"icon\" href=\"./icon.svg\" type=\"image/svg+xml\">\n",
+    "    <meta name=\"theme-color\" content=\"#050505\">\n",
+    "    <script>if ('serviceWorker' in navigator) navigator.serviceWorker.register('./sw.js');</script>\n",
+);
+
+fn disabled() -> HttpResponse {
+    HttpResponse::NotFound().json(serde_json::json!({"error": "Not found"}))
+}
+
+/// `GET /manifest.json`.
+pub async fn manifest(data: web::Data<AppState>) -> HttpResponse {
+    if !data.config.pwa {
+        return disabled();
+    }
+    HttpResponse::Ok()
+        .content_type("application/manifest+json")
+        .json(serde_json::json!({
+            "name": "FileDrop",
+            "short_name": "FileDrop",
+            "start_url": "./",
+            "scope": "./",
+            "display": "standalone",
+            "background_color": "#050505",
+            "theme_color": "#050505",
+            "icons": [{"src": "./icon.svg", "sizes": "any", "type": "image/svg+xml"}],
+        }))
+}
+
+/// `GET /sw.js`. Never cached by the browser itself, so a new release's
+/// worker (and cache name) is picked up on the next visit.
+pub async fn service_worker(data: web::Data<AppState>) -> HttpResponse {
+    if !data.config.pwa {
+        return disabled();
+    }
+    HttpResponse::Ok()
+        .content_type("text/javascript; charset=utf-8")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .body(SERVICE_WORKER.replace("__VERSION__", env!("CARGO_PKG_VERSION")))
+}
+
+/// `GET /icon.svg`.
+pub async fn icon(data: web::Data<AppState>) -> HttpResponse {
+    if !data.config.pwa {
+        return disabled();
+    }
+    HttpResponse::Ok().content_type("image/svg+xml").body(ICON)
+}
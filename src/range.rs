@@ -0,0 +1,262 @@
+//! Parsing of `Range: bytes=...` request headers for downloads.
+//!
+//! The parser is deliberately forgiving about whitespace and overlong
+//! positions (they are clamped to the file size) but strict about syntax:
+//! anything that is not a well-formed `bytes` range set is reported as
+//! unsatisfiable so the caller answers `416`.
+
+/// Upper bound on the number of ranges accepted in a single header. Requests
+/// asking for more are rejected outright rather than served piecemeal.
+pub const MAX_RANGES: usize = 16;
+
+/// An inclusive byte range within a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// No Range header, or one using a unit other than `bytes`: serve the
+    /// whole file with `200`.
+    Full,
+    /// Satisfiable ranges, sorted and with overlapping or adjacent ranges
+    /// merged, so their total span never exceeds the file size.
+    Partial(Vec<ByteRange>),
+    /// Malformed, too many ranges, or none overlapping the file: `416`.
+    Unsatisfiable,
+}
+
+/// Parses a Range header value against a file of `size` bytes.
+pub fn parse(header: Option<&str>, size: u64) -> RangeRequest {
+    let Some(header) = header else {
+        return RangeRequest::Full;
+    };
+    let Some((unit, set)) = header.trim().split_once('=') else {
+        return RangeRequest::Unsatisfiable;
+    };
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return RangeRequest::Full;
+    }
+
+    let mut ranges = Vec::new();
+    let mut count = 0;
+    for spec in set.split(',').map(str::trim) {
+        // Empty list elements (`bytes=0-1,,2-3`) are permitted by the grammar.
+        if spec.is_empty() {
+            continue;
+        }
+        count += 1;
+        if count > MAX_RANGES {
+            return RangeRequest::Unsatisfiable;
+        }
+        let Some((first, last)) = spec.split_once('-') else {
+            return RangeRequest::Unsatisfiable;
+        };
+        let (first, last) = (first.trim(), last.trim());
+
+        if first.is_empty() {
+            // Suffix range: the final `n` bytes.
+            let Some(n) = parse_pos(last) else {
+                return RangeRequest::Unsatisfiable;
+            };
+            if n > 0 && size > 0 {
+                ranges.push(ByteRange {
+                    start: size - n.min(size),
+                    end: size - 1,
+                });
+            }
+            continue;
+        }
+
+        let Some(start) = parse_pos(first) else {
+            return RangeRequest::Unsatisfiable;
+        };
+        let end = if last.is_empty() {
+            u64::MAX
+        } else {
+            match parse_pos(last) {
+                Some(end) => end,
+                None => return RangeRequest::Unsatisfiable,
+            }
+        };
+        if end < start {
+            return RangeRequest::Unsatisfiable;
+        }
+        if start < size {
+            ranges.push(ByteRange {
+                start,
+                end: end.min(size - 1),
+            });
+        }
+    }
+
+    if ranges.is_empty() {
+        return RangeRequest::Unsatisfiable;
+    }
+    RangeRequest::Partial(merge(ranges))
+}
+
+/// Parses a non-negative decimal position. Values too large for a `u64`
+/// saturate; they are clamped to the file size by the caller anyway.
+fn parse_pos(s: &str) -> Option<u64> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some(s.bytes().fold(0u64, |acc, b| {
+        acc.saturating_mul(10).saturating_add(u64::from(b - b'0'))
+    }))
+}
+
+fn merge(mut ranges: Vec<ByteRange>) -> Vec<ByteRange> {
+    ranges.sort_by_key(|r| r.start);
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for r in ranges {
+        match merged.last_mut() {
+            Some(prev) if r.start <= prev.end.saturating_add(1) => {
+                prev.end = prev.end.max(r.end);
+            }
+            _ => merged.push(r),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(header: &str, size: u64) -> Vec<(u64, u64)> {
+        match parse(Some(header), size) {
+            RangeRequest::Partial(r) => r.iter().map(|r| (r.start, r.end)).collect(),
+            other => panic!("{:?} for {:?}", other, header),
+        }
+    }
+
+    #[test]
+    fn parses_range_sets() {
+        assert_eq!(parse(None, 10), RangeRequest::Full);
+        assert_eq!(parse(Some("items=0-1"), 10), RangeRequest::Full);
+        assert_eq!(ranges("bytes=0-4", 10), [(0, 4)]);
+        assert_eq!(ranges("bytes=5-", 10), [(5, 9)]);
+        assert_eq!(ranges("bytes=-3", 10), [(7, 9)]);
+        assert_eq!(ranges("bytes=-30", 10), [(0, 9)]);
+        assert_eq!(ranges("bytes=8-99999999999999999999999", 10), [(8, 9)]);
+        assert_eq!(ranges(" bytes = 0-1 ,, 6-7 ", 10), [(0, 1), (6, 7)]);
+        assert_eq!(ranges("bytes=4-6,0-2,3-3", 10), [(0, 6)]);
+        for bad in [
+            "bytes",
+            "bytes=",
+            "bytes=5-2",
+            "bytes=a-b",
+            "bytes=10-",
+            "bytes=-0",
+        ] {
+            assert_eq!(parse(Some(bad), 10), RangeRequest::Unsatisfiable, "{}", bad);
+        }
+        let many = format!("bytes={}", vec!["0-0"; MAX_RANGES + 1].join(","));
+        assert_eq!(parse(Some(&many), 10), RangeRequest::Unsatisfiable);
+        assert_eq!(parse(Some("bytes=-5"), 0), RangeRequest::Unsatisfiable);
+    }
+
+    /// A small deterministic generator, so failures reproduce.
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % n
+        }
+    }
+
+    #[test]
+    fn random_headers_keep_the_invariants() {
+        const PIECES: &[&str] = &[
+            "bytes",
+            "=",
+            "-",
+            ",",
+            " ",
+            "0",
+            "1",
+            "7",
+            "42",
+            "999",
+            "x",
+            "18446744073709551616",
+        ];
+        let mut rng = XorShift(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..20_000 {
+            let size = [0, 1, 2, 10, 1000, u64::MAX][rng.below(6) as usize];
+            let mut header = String::from(if rng.below(4) == 0 { "" } else { "bytes=" });
+            for _ in 0..rng.below(12) {
+                header.push_str(PIECES[rng.below(PIECES.len() as u64) as usize]);
+            }
+            let RangeRequest::Partial(ranges) = parse(Some(&header), size) else {
+                continue;
+            };
+            assert!(
+                !ranges.is_empty() && ranges.len() <= MAX_RANGES,
+                "{:?}",
+                header
+            );
+            for r in &ranges {
+                assert!(r.start <= r.end && r.end < size, "{:?}: {:?}", header, r);
+            }
+            for pair in ranges.windows(2) {
+                assert!(pair[0].end + 1 < pair[1].start, "{:?}: not merged", header);
+            }
+            assert!(ranges.iter().map(|r| r.len()).sum::<u64>() <= size);
+        }
+    }
+
+    #[test]
+    fn random_range_sets_cover_exactly_the_requested_bytes() {
+        let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+        for _ in 0..5_000 {
+            let size = rng.below(64);
+            let mut wanted = vec![false; size as usize];
+            let mut specs = Vec::new();
+            for _ in 0..1 + rng.below(MAX_RANGES as u64) {
+                let first = rng.below(80);
+                let spec = match rng.below(3) {
+                    0 => format!("{}-", first),
+                    1 => format!("-{}", first),
+                    _ => format!("{}-{}", first, first + rng.below(20)),
+                };
+                let (start, end) = match spec.split_once('-').unwrap() {
+                    ("", n) => (size.saturating_sub(n.parse().unwrap()), size),
+                    (s, "") => (s.parse().unwrap(), size),
+                    (s, e) => (s.parse().unwrap(), e.parse::<u64>().unwrap() + 1),
+                };
+                for b in start..end.min(size) {
+                    wanted[b as usize] = true;
+                }
+                specs.push(spec);
+            }
+            let header = format!("bytes={}", specs.join(","));
+            let mut got = vec![false; size as usize];
+            match parse(Some(&header), size) {
+                RangeRequest::Partial(ranges) => {
+                    for r in ranges {
+                        for b in r.start..=r.end {
+                            got[b as usize] = true;
+                        }
+                    }
+                }
+                RangeRequest::Unsatisfiable => {}
+                RangeRequest::Full => panic!("{}", header),
+            }
+            assert_eq!(got, wanted, "{} of {} bytes", header, size);
+        }
+    }
+}
@@ -0,0 +1,300 @@
+//! Background integrity scrubbing (`SCRUB_FILES_PER_HOUR`).
+//!
+//! Stored files are re-hashed one at a time, least recently verified first,
+//! at a fixed rate so the scrub never competes with downloads for disk. A
+//! file whose digest no longer matches its checksum is reported at
+//! `GET /api/admin/scrub` and flagged with `checksum_mismatch` in listings;
+//! with `SCRUB_QUARANTINE` it is instead moved out of the listing into
+//! `uploads/.quarantine/`. Files without a checksum (found on disk at
+//! startup) get one on their first pass, which later passes check against.
+//! Downloads checked under `VERIFY_DOWNLOADS` report failures the same way.
+
+use crate::admin::require_admin;
+use crate::hashing::{hash_file, Checksum};
+use crate::{ids, logging, precompress, stored_path, versions, AppState, FileInfo, UPLOAD_DIR};
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+const QUARANTINE_DIR: &str = ".quarantine";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Mismatch {
+    id: String,
+    name: String,
+    expected: Checksum,
+    actual: Checksum,
+    detected_at: DateTime<Utc>,
+    /// Where the file was moved, when `SCRUB_QUARANTINE` is on.
+    quarantined_to: Option<PathBuf>,
+}
+
+/// Scrub progress and findings since startup.
+pub struct ScrubReport {
+    inner: Mutex<Report>,
+}
+
+#[derive(Default)]
+struct Report {
+    /// When each file was last picked, by id, so unreadable files do not
+    /// hold up the rest.
+    attempted: HashMap<String, DateTime<Utc>>,
+    verified: u64,
+    mismatches: Vec<Mismatch>,
+}
+
+impl ScrubReport {
+    pub fn new() -> Self {
+        ScrubReport {
+            inner: Mutex::new(Report::default()),
+        }
+    }
+
+    /// Files still listed that are known not to match their checksum.
+    /// Quarantined files stay in the report but are no longer served.
+    pub fn mismatch_count(&self) -> usize {
+        let report = self.inner.lock().unwrap();
+        let flagged = report.mismatches.iter();
+        flagged.filter(|m| m.quarantined_to.is_none()).count()
+    }
+}
+
+/// Moves a corrupted file aside so it is no longer served, along with its
+/// version history.
+fn move_aside(id: &str, name: &str) -> std::io::Result<PathBuf> {
+    let dir = PathBuf::from(UPLOAD_DIR).join(QUARANTINE_DIR);
+    std::fs::create_dir_all(&dir)?;
+    let target = dir.join(format!("{}-{}", id, name));
+    std::fs::rename(PathBuf::from(UPLOAD_DIR).join(name), &target)?;
+    if let Err(e) = versions::move_to(name, &dir.join(format!("{}-{}.versions", id, name))) {
+        logging::event(
+            "warn",
+            "failed to quarantine version history, removing it",
+            serde_json::json!({"file": name, "error": e.to_string()}),
+        );
+        versions::remove_all(name);
+    }
+    Ok(target)
+}
+
+/// Verifies the file that has gone longest without a scrub.
+async fn scrub_one(data: &AppState) {
+    let next = {
+        let files = data.files.lock().unwrap();
+        let mut report = data.scrub.inner.lock().unwrap();
+        let ids: HashSet<&str> = files.iter().map(|f| f.id.as_str()).collect();
+        report.attempted.retain(|id, _| ids.contains(id.as_str()));
+        let next = files
+            .iter()
+            .filter(|f| f.is_ready())
+            .min_by_key(|f| report.attempted.get(&f.id))
+            .map(|f| (f.id.clone(), f.name.clone(), f.checksum.clone()));
+        if let Some((id, _, _)) = &next {
+            report.attempted.insert(id.clone(), Utc::now());
+        }
+        next
+    };
+    let Some((id, name, expected)) = next else {
+        return;
+    };
+    let Some(path) = stored_path(&data.config, &name) else {
+        return;
+    };
+    let algorithm = expected
+        .as_ref()
+        .map_or(data.config.hash_algorithm, |c| c.algorithm);
+    let actual = match web::block(move || hash_file(&path, algorithm)).await {
+        Ok(Ok(actual)) => actual,
+        Ok(Err(e)) => {
+            logging::event(
+                "warn",
+                "scrub could not read file",
+                serde_json::json!({"file": name, "error": e.to_string()}),
+            );
+            return;
+        }
+        Err(_) => return,
+    };
+
+    let mut files = data.files.lock().unwrap();
+    // Skip the result if the file was replaced or deleted meanwhile.
+    let Some(pos) = files
+        .iter()
+        .position(|f| f.id == id && f.checksum == expected)
+    else {
+        return;
+    };
+    let Some(expected) = expected.filter(|e| *e != actual) else {
+        files[pos].checksum = Some(actual);
+        files[pos].last_verified = Some(Utc::now());
+        files[pos].checksum_mismatch = None;
+        let mut report = data.scrub.inner.lock().unwrap();
+        report.verified += 1;
+        report.mismatches.retain(|m| m.id != id);
+        return;
+    };
+    record_mismatch(
+        data,
+        files,
+        pos,
+        expected,
+        actual,
+        data.config.scrub_quarantine,
+    );
+}
+
+/// Flags the file at `pos` as corrupted, or moves it to the quarantine
+/// directory when `quarantine` is set, and reports it at
+/// `GET /api/admin/scrub`. Takes the files lock and releases it.
+pub fn record_mismatch(
+    data: &AppState,
+    mut files: MutexGuard<'_, Vec<FileInfo>>,
+    pos: usize,
+    expected: Checksum,
+    actual: Checksum,
+    quarantine: bool,
+) {
+    let now = Utc::now();
+    let (id, name) = (files[pos].id.clone(), files[pos].name.clone());
+    let quarantined_to = if quarantine {
+        match move_aside(&id, &name) {
+            Ok(target) => {
+                files.remove(pos);
+                data.tombstones
+                    .record(data.config.tombstone_retention, id.clone(), name.clone());
+                precompress::remove(&name);
+                ids::remove(&name);
+                Some(target)
+            }
+            Err(e) => {
+                logging::event(
+                    "error",
+                    "failed to quarantine corrupted file",
+                    serde_json::json!({"file": name, "error": e.to_string()}),
+                );
+                None
+            }
+        }
+    } else {
+        files[pos].checksum_mismatch = Some(now);
+        None
+    };
+    drop(files);
+    logging::event(
+        "error",
+        "checksum mismatch",
+        serde_json::json!({
+            "file": name,
+            "expected": expected.digest,
+            "actual": actual.digest,
+            "quarantined": quarantined_to.is_some(),
+        }),
+    );
+    let mut report = data.scrub.inner.lock().unwrap();
+    report.mismatches.retain(|m| m.id != id);
+    report.mismatches.push(Mismatch {
+        id,
+        name,
+        expected,
+        actual,
+        detected_at: now,
+        quarantined_to,
+    });
+}
+
+/// Starts the scrub loop when `SCRUB_FILES_PER_HOUR` is non-zero.
+pub fn start(data: web::Data<AppState>) {
+    let rate = data.config.scrub_files_per_hour;
+    if rate == 0 {
+        return;
+    }
+    let interval = Duration::from_secs_f64(3600.0 / rate as f64);
+    actix_web::rt::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            scrub_one(&data).await;
+        }
+    });
+}
+
+/// `GET /api/admin/scrub`: scrub settings, progress and mismatches found.
+pub async fn scrub_report(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
+    if let Err(res) = require_admin(&req, &data) {
+        return res;
+    }
+    let (never_verified, oldest) = {
+        let files = data.files.lock().unwrap();
+        (
+            files.iter().filter(|f| f.last_verified.is_none()).count(),
+            files.iter().filter_map(|f| f.last_verified).min(),
+        )
+    };
+    let report = data.scrub.inner.lock().unwrap();
+    HttpResponse::Ok().json(serde_json::json!({
+        "enabled": data.config.scrub_files_per_hour > 0,
+        "files_per_hour": data.config.scrub_files_per_hour,
+        "quarantine": data.config.scrub_quarantine,
+        "verified": report.verified,
+        "never_verified": never_verified,
+        "oldest_verification": oldest,
+        "mismatches": report.mismatches,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{add_file, config, path, record, remove, state, unique};
+    use crate::Config;
+
+    fn history(name: &str) -> PathBuf {
+        PathBuf::from(UPLOAD_DIR).join(".versions").join(name)
+    }
+
+    #[actix_web::test]
+    async fn flags_corrupted_files() {
+        let data = state(config());
+        let name = unique("flagged.txt");
+        let info = add_file(&data, &name, b"original", None);
+        std::fs::write(path(&name), b"corrupted").unwrap();
+        scrub_one(&data).await;
+        assert!(record(&data, &info.id).unwrap().checksum_mismatch.is_some());
+        assert_eq!(data.scrub.mismatch_count(), 1);
+        remove(&name);
+    }
+
+    #[actix_web::test]
+    async fn quarantines_corrupted_files_with_their_history() {
+        let data = state(Config {
+            scrub_quarantine: true,
+            ..config()
+        });
+        let name = unique("quarantined.txt");
+        let info = add_file(&data, &name, b"original", None);
+        std::fs::create_dir_all(history(&name)).unwrap();
+        std::fs::write(history(&name).join("1"), b"older").unwrap();
+        std::fs::write(path(&name), b"corrupted").unwrap();
+        scrub_one(&data).await;
+
+        assert!(record(&data, &info.id).is_none());
+        assert!(!path(&name).exists());
+        assert!(!history(&name).exists());
+        let quarantine = PathBuf::from(UPLOAD_DIR).join(QUARANTINE_DIR);
+        let moved = quarantine.join(format!("{}-{}", info.id, name));
+        let moved_history = quarantine.join(format!("{}-{}.versions", info.id, name));
+        assert_eq!(std::fs::read(&moved).unwrap(), b"corrupted");
+        assert_eq!(std::fs::read(moved_history.join("1")).unwrap(), b"older");
+        // Reported, but no longer counted against status.
+        assert_eq!(data.scrub.inner.lock().unwrap().mismatches.len(), 1);
+        assert_eq!(data.scrub.mismatch_count(), 0);
+        std::fs::remove_file(moved).unwrap();
+        std::fs::remove_dir_all(moved_history).unwrap();
+    }
+}
@@ -0,0 +1,62 @@
+//! Sets the `Server` response header (`SERVER_HEADER`).
+//!
+//! actix-web does not send a `Server` header of its own, so by default
+//! responses carry none and there is nothing to strip. Operators who want a
+//! fixed value, for a policy or to tell instances apart, can set one.
+
+use crate::AppState;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::web;
+
+/// Outermost middleware, so it also sees error, static and download
+/// responses produced by the other layers.
+pub async fn apply(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let value = req
+        .app_data::<web::Data<AppState>>()
+        .and_then(|data| data.config.server_header.as_deref())
+        .and_then(|v| HeaderValue::from_str(v).ok());
+    let mut res = next.call(req).await?;
+    if let Some(value) = value {
+        res.headers_mut().insert(header::SERVER, value);
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{config, state};
+    use crate::Config;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{middleware, App, HttpResponse};
+
+    async fn server(value: Option<&str>) -> Option<String> {
+        let data = state(Config {
+            server_header: value.map(str::to_string),
+            ..config()
+        });
+        let app = init_service(
+            App::new()
+                .wrap(middleware::from_fn(apply))
+                .app_data(data)
+                .route("/", web::get().to(HttpResponse::NotFound)),
+        )
+        .await;
+        let res = call_service(&app, TestRequest::get().uri("/").to_request()).await;
+        res.headers()
+            .get(header::SERVER)
+            .map(|v| v.to_str().unwrap().to_string())
+    }
+
+    #[actix_web::test]
+    async fn sends_the_configured_value_only() {
+        assert_eq!(server(Some("files")).await.as_deref(), Some("files"));
+        assert_eq!(server(None).await, None);
+    }
+}
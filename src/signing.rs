@@ -0,0 +1,102 @@
+//! Time-limited signed download URLs.
+//!
+//! `GET /api/files/{id}/signed-url` hands out
+//! `/api/signed/{id}/{expires}/{signature}`, where `signature` is an
+//! HMAC-SHA256 over the file id and the expiry timestamp. The signed route
+//! serves the file without any further checks (including download
+//! confirmation) until the expiry passes, so the URL can be given to a CDN or
+//! embedded directly by a frontend.
+
+use crate::admin::constant_time_eq;
+use crate::{find_file, public_base_url, serve_file, AppState, Disposition};
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use uuid::Uuid;
+
+pub struct Signer {
+    key: Vec<u8>,
+}
+
+impl Signer {
+    /// Uses `key` when configured, otherwise a random per-process key.
+    pub fn new(key: Option<&str>) -> Self {
+        let key = match key {
+            Some(k) => k.as_bytes().to_vec(),
+            None => [Uuid::new_v4().into_bytes(), Uuid::new_v4().into_bytes()].concat(),
+        };
+        Signer { key }
+    }
+
+    fn sign(&self, id: &str, expires: i64) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        mac.update(id.as_bytes());
+        mac.update(b"\n");
+        mac.update(expires.to_string().as_bytes());
+        format!("{:x}", mac.finalize().into_bytes())
+    }
+
+    fn verify(&self, id: &str, expires: i64, signature: &str) -> bool {
+        constant_time_eq(self.sign(id, expires).as_bytes(), signature.as_bytes())
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SignQuery {
+    /// Lifetime in seconds; defaults to `SIGNED_URL_TTL_SECS`.
+    ttl: Option<u64>,
+}
+
+/// `GET /api/files/{id}/signed-url?ttl=`.
+pub async fn signed_url(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<SignQuery>,
+    data: web::Data<AppState>,
+) -> HttpResponse {
+    let Some(info) = find_file(&data, &path.into_inner()) else {
+        return HttpResponse::NotFound().json(serde_json::json!({"error": "File not found"}));
+    };
+    let max = data.config.signed_url_max_ttl;
+    let ttl = query.ttl.unwrap_or(data.config.signed_url_ttl);
+    if ttl == 0 || ttl > max {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("ttl must be between 1 and {} seconds", max)
+        }));
+    }
+
+    let expires = Utc::now().timestamp() + ttl as i64;
+    let signature = data.signer.sign(&info.id, expires);
+    HttpResponse::Ok().json(serde_json::json!({
+        "url": format!(
+            "{}/api/signed/{}/{}/{}",
+            public_base_url(&req, &data.config),
+            info.id,
+            expires,
+            signature
+        ),
+        "expires_at": DateTime::<Utc>::from_timestamp(expires, 0),
+    }))
+}
+
+/// `GET /api/signed/{id}/{expires}/{signature}`.
+pub async fn signed_download(
+    req: HttpRequest,
+    path: web::Path<(String, i64, String)>,
+    data: web::Data<AppState>,
+) -> HttpResponse {
+    let (id, expires, signature) = path.into_inner();
+    if !data.signer.verify(&id, expires, &signature) {
+        return HttpResponse::Forbidden().json(serde_json::json!({"error": "Invalid signature"}));
+    }
+    if expires <= Utc::now().timestamp() {
+        return HttpResponse::Gone().json(serde_json::json!({"error": "Signed URL has expired"}));
+    }
+    let Some(info) = find_file(&data, &id) else {
+        return HttpResponse::NotFound().json(serde_json::json!({"error": "File not found"}));
+    };
+    serve_file(&req, &data, &info.name, &info.name, Disposition::Attachment).await
+}
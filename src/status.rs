@@ -0,0 +1,112 @@
+//! `GET /api/status`: one cheap, unauthenticated summary for status pages
+//! and dashboards. Orchestrators should keep using `/healthz` and `/readyz`,
+//! whose answers are meant for restart and routing decisions.
+//!
+//! `status` is `up` when everything works, `degraded` when files can still
+//! be downloaded but something needs attention (uploads failing because the
+//! directory is unwritable, less than 5% disk space left, corrupted files
+//! found by the scrubber), and `down` when the upload directory cannot be
+//! read at all. `down` is sent with `503`, the others with `200`.
+
+use crate::{AppState, UPLOAD_DIR};
+use actix_web::{web, HttpResponse};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Uploads currently streaming in.
+pub struct ActiveUploads {
+    count: Arc<AtomicUsize>,
+}
+
+/// Counts as one active upload until dropped.
+pub struct UploadGuard {
+    count: Arc<AtomicUsize>,
+}
+
+impl ActiveUploads {
+    pub fn new() -> Self {
+        ActiveUploads {
+            count: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn start(&self) -> UploadGuard {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        UploadGuard {
+            count: self.count.clone(),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for UploadGuard {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Total and available bytes of the filesystem holding the upload
+/// directory.
+#[cfg(unix)]
+fn disk_space() -> Option<(u64, u64)> {
+    use std::os::unix::ffi::OsStrExt;
+    let path =
+        std::ffi::CString::new(std::path::Path::new(UPLOAD_DIR).as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is NUL-terminated and `stat` is a valid out pointer.
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    let block = stat.f_frsize as u64;
+    Some((stat.f_blocks as u64 * block, stat.f_bavail as u64 * block))
+}
+
+#[cfg(not(unix))]
+fn disk_space() -> Option<(u64, u64)> {
+    None
+}
+
+pub async fn status(data: web::Data<AppState>) -> HttpResponse {
+    let (files, used) = {
+        let files = data.files.lock().unwrap();
+        let ready = files.iter().filter(|f| f.is_ready());
+        ready.fold((0, 0), |(n, bytes), f| (n + 1, bytes + f.size))
+    };
+    let readable = std::fs::read_dir(UPLOAD_DIR).is_ok();
+    let writable = data.storage.problem().is_none();
+    let space = web::block(disk_space).await.ok().flatten();
+    let low_space = space.is_some_and(|(total, free)| free < total / 20);
+    let corrupted = data.scrub.mismatch_count();
+
+    let status = if !readable {
+        "down"
+    } else if !writable || low_space || corrupted > 0 {
+        "degraded"
+    } else {
+        "up"
+    };
+    let mut res = if readable {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::ServiceUnavailable()
+    };
+    res.json(serde_json::json!({
+        "status": status,
+        "version": env!("CARGO_PKG_VERSION"),
+        "storage": {
+            "writable": writable,
+            "used_bytes": used,
+            "total_bytes": space.map(|(total, _)| total),
+            "free_bytes": space.map(|(_, free)| free),
+            "low_space": low_space,
+        },
+        "client_quota_bytes": data.config.client_quota,
+        "files": files,
+        "corrupted_files": corrupted,
+        "active_downloads": data.downloads.active_count(),
+        "active_uploads": data.uploads.count(),
+    }))
+}
@@ -0,0 +1,194 @@
+//! Writability of the upload directory, probed at startup and periodically.
+//!
+//! A read-only or unwritable upload directory (a full disk remounted `ro`, a
+//! permission change) makes `/readyz` fail and uploads fail fast with `503`,
+//! while downloads keep working.
+
+use crate::{logging, AppState, UPLOAD_DIR};
+use actix_web::{web, HttpResponse};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+use uuid::Uuid;
+
+pub struct StorageHealth {
+    /// Why the upload directory is unwritable, or `None` while it is fine.
+    problem: Mutex<Option<String>>,
+}
+
+impl StorageHealth {
+    pub fn new() -> Self {
+        StorageHealth {
+            problem: Mutex::new(None),
+        }
+    }
+
+    pub fn problem(&self) -> Option<String> {
+        self.problem.lock().unwrap().clone()
+    }
+
+    /// Records the outcome of a probe or a failed write, logging only
+    /// transitions so a persistent condition does not flood the log.
+    pub fn set(&self, problem: Option<String>) {
+        let mut current = self.problem.lock().unwrap();
+        match (&*current, &problem) {
+            (None, Some(reason)) => logging::event(
+                "error",
+                "upload directory is not writable",
+                serde_json::json!({"dir": UPLOAD_DIR, "reason": reason}),
+            ),
+            (Some(_), None) => logging::event(
+                "info",
+                "upload directory is writable again",
+                serde_json::json!({"dir": UPLOAD_DIR}),
+            ),
+            _ => {}
+        }
+        *current = problem;
+    }
+
+    /// Fails uploads fast while storage is known to be unwritable.
+    pub fn require_writable(&self) -> Result<(), HttpResponse> {
+        match self.problem() {
+            None => Ok(()),
+            Some(reason) => Err(unavailable(&reason)),
+        }
+    }
+}
+
+pub fn unavailable(reason: &str) -> HttpResponse {
+    HttpResponse::ServiceUnavailable().json(serde_json::json!({
+        "error": format!("Uploads are unavailable: storage is not writable ({})", reason)
+    }))
+}
+
+/// Whether an I/O error means the upload directory cannot be written at all,
+/// as opposed to this one write failing.
+pub fn is_unwritable(e: &std::io::Error) -> bool {
+    use std::io::ErrorKind::*;
+    matches!(e.kind(), ReadOnlyFilesystem | PermissionDenied)
+}
+
+fn probe() -> Result<(), String> {
+    probe_dir(Path::new(UPLOAD_DIR))
+}
+
+/// Creates, writes and removes a hidden probe file in `dir`. Blocking.
+fn probe_dir(dir: &Path) -> Result<(), String> {
+    let path = dir.join(format!(".probe-{}", Uuid::new_v4().simple()));
+    let result = std::fs::File::create(&path).and_then(|mut f| f.write_all(b"ok"));
+    let _ = std::fs::remove_file(&path);
+    result.map_err(|e| e.to_string())
+}
+
+/// Whether the upload directory's filesystem treats names differing only by
+/// case as the same file (the macOS and Windows defaults). Blocking.
+pub fn is_case_insensitive() -> bool {
+    let name = format!(".case-probe-{}", Uuid::new_v4().simple());
+    let path = Path::new(UPLOAD_DIR).join(&name);
+    if std::fs::File::create(&path).is_err() {
+        return false;
+    }
+    let folded = Path::new(UPLOAD_DIR).join(name.to_uppercase()).exists();
+    let _ = std::fs::remove_file(&path);
+    folded
+}
+
+/// Probes once now, then every `interval` in the background (if non-zero).
+pub fn start_probing(data: web::Data<AppState>, interval: Duration) {
+    data.storage.set(probe().err());
+    if interval.is_zero() {
+        return;
+    }
+    actix_web::rt::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let result = web::block(probe)
+                .await
+                .unwrap_or_else(|_| Err("probe task failed".to_string()));
+            data.storage.set(result.err());
+        }
+    });
+}
+
+/// `GET /healthz`: the process is up.
+pub async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({"status": "ok"}))
+}
+
+/// `GET /readyz`: ready to accept uploads.
+pub async fn readyz(data: web::Data<AppState>) -> HttpResponse {
+    match data.storage.problem() {
+        None => HttpResponse::Ok().json(serde_json::json!({"status": "ready"})),
+        Some(reason) => HttpResponse::ServiceUnavailable().json(serde_json::json!({
+            "status": "not ready",
+            "reason": format!("upload directory is not writable: {}", reason),
+        })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{call, config, json, state, upload};
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn probes_the_upload_directory() {
+        std::fs::create_dir_all(UPLOAD_DIR).unwrap();
+        assert_eq!(probe(), Ok(()));
+        use std::io::{Error, ErrorKind};
+        assert!(is_unwritable(&Error::from(ErrorKind::ReadOnlyFilesystem)));
+        assert!(is_unwritable(&Error::from(ErrorKind::PermissionDenied)));
+        assert!(!is_unwritable(&Error::from(ErrorKind::StorageFull)));
+    }
+
+    #[cfg(unix)]
+    #[actix_web::test]
+    async fn a_read_only_directory_stops_uploads() {
+        use std::os::unix::fs::PermissionsExt;
+        // Root writes through any permission bits.
+        if unsafe { libc::geteuid() } == 0 {
+            return;
+        }
+        let dir = std::env::temp_dir().join(format!("probe-{}", Uuid::new_v4().simple()));
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o555)).unwrap();
+        let result = probe_dir(&dir);
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::fs::remove_dir(&dir).unwrap();
+        assert!(result.is_err());
+
+        let data = state(config());
+        data.storage.set(result.err());
+        let res = call(&data, TestRequest::get().uri("/readyz")).await;
+        assert_eq!(res.status(), 503);
+        let res = call(&data, upload("never-stored.txt", "x")).await;
+        assert_eq!(res.status(), 503);
+    }
+
+    #[actix_web::test]
+    async fn readiness_follows_storage() {
+        let data = state(config());
+        let get = |uri: &str| call(&data, TestRequest::get().uri(uri));
+        assert_eq!(get("/healthz").await.status(), 200);
+        assert_eq!(json(get("/readyz").await).await["status"], "ready");
+
+        data.storage.set(Some("read-only file system".to_string()));
+        let res = get("/readyz").await;
+        assert_eq!(res.status(), 503);
+        assert!(json(res).await["reason"]
+            .as_str()
+            .unwrap()
+            .contains("read-only"));
+        assert_eq!(get("/healthz").await.status(), 200);
+        let res = call(&data, upload("never-stored.txt", "x")).await;
+        assert_eq!(res.status(), 503);
+
+        data.storage.set(None);
+        assert_eq!(get("/readyz").await.status(), 200);
+    }
+}
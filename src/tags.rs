@@ -0,0 +1,195 @@
+//! Free-form file tags and `POST /api/files/tag` for changing them in bulk.
+
+use crate::{AppState, FileInfo};
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+const MAX_TAG_LEN: usize = 64;
+
+/// Which files a bulk operation applies to. Every given criterion must
+/// match; an empty filter matches every file.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct FileFilter {
+    /// Case-insensitive substring of the file name.
+    q: Option<String>,
+    /// Prefix of the MIME type, e.g. `image/`.
+    #[serde(alias = "mimePrefix")]
+    mime_prefix: Option<String>,
+    /// Uploaded at or after this instant.
+    #[serde(alias = "uploadedAfter")]
+    uploaded_after: Option<DateTime<Utc>>,
+    /// Uploaded before this instant.
+    #[serde(alias = "uploadedBefore")]
+    uploaded_before: Option<DateTime<Utc>>,
+}
+
+impl FileFilter {
+    fn matches(&self, f: &FileInfo) -> bool {
+        self.q
+            .as_deref()
+            .is_none_or(|q| f.name.to_lowercase().contains(&q.to_lowercase()))
+            && self
+                .mime_prefix
+                .as_deref()
+                .is_none_or(|p| f.mime_type.starts_with(p))
+            && self.uploaded_after.is_none_or(|t| f.uploaded_at >= t)
+            && self.uploaded_before.is_none_or(|t| f.uploaded_at < t)
+    }
+}
+
+#[derive(Deserialize)]
+struct BulkTagRequest {
+    #[serde(default)]
+    filter: FileFilter,
+    #[serde(default)]
+    add: Vec<String>,
+    #[serde(default)]
+    remove: Vec<String>,
+    /// Report the changes without applying them.
+    #[serde(default, alias = "dryRun")]
+    dry_run: bool,
+}
+
+/// Trims a tag and checks it is non-empty and at most [`MAX_TAG_LEN`] bytes.
+pub fn normalize(tag: &str) -> Result<String, HttpResponse> {
+    let tag = tag.trim();
+    if tag.is_empty() || tag.len() > MAX_TAG_LEN {
+        return Err(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Tags must be 1 to {} bytes long", MAX_TAG_LEN)
+        })));
+    }
+    Ok(tag.to_string())
+}
+
+/// The tags `current` ends up with after removing `remove` and adding `add`,
+/// kept sorted.
+pub fn apply(current: &[String], add: &[String], remove: &[String]) -> Vec<String> {
+    let mut tags: Vec<String> = current
+        .iter()
+        .filter(|t| !remove.contains(t))
+        .cloned()
+        .collect();
+    for tag in add {
+        if !tags.contains(tag) {
+            tags.push(tag.clone());
+        }
+    }
+    tags.sort();
+    tags
+}
+
+/// `POST /api/files/tag`: adds and removes tags on every file matching a
+/// filter. Removals are applied before additions, so a tag in both lists
+/// ends up present. Only files whose tags actually change are counted.
+pub async fn bulk_tag(body: web::Bytes, data: web::Data<AppState>) -> HttpResponse {
+    let body: BulkTagRequest = match serde_json::from_slice(&body) {
+        Ok(b) => b,
+        Err(e) => {
+            return HttpResponse::BadRequest()
+                .json(serde_json::json!({"error": format!("Invalid request: {}", e)}));
+        }
+    };
+    let normalized = |tags: &[String]| tags.iter().map(|t| normalize(t)).collect();
+    let (add, remove): (Vec<String>, Vec<String>) =
+        match (normalized(&body.add), normalized(&body.remove)) {
+            (Ok(add), Ok(remove)) => (add, remove),
+            (Err(res), _) | (_, Err(res)) => return res,
+        };
+    if add.is_empty() && remove.is_empty() {
+        return HttpResponse::BadRequest()
+            .json(serde_json::json!({"error": "Nothing to do: give tags to add or remove"}));
+    }
+
+    let now = Utc::now();
+    let mut files = data.files.lock().unwrap();
+    let mut matched = 0;
+    let mut changed = Vec::new();
+    for f in files.iter_mut().filter(|f| body.filter.matches(f)) {
+        matched += 1;
+        let tags = apply(&f.tags, &add, &remove);
+        if tags == f.tags {
+            continue;
+        }
+        changed.push(serde_json::json!({
+            "id": f.id,
+            "name": f.name,
+            "tags": tags,
+        }));
+        if !body.dry_run {
+            f.tags = tags;
+            f.modified_at = Some(now);
+        }
+    }
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "dry_run": body.dry_run,
+        "matched": matched,
+        "changed": changed.len(),
+        "files": changed,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{add_file, call, config, json, record, remove, state, unique};
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn normalizes_tags() {
+        assert_eq!(normalize("  work ").unwrap(), "work");
+        assert!(normalize("   ").is_err());
+        assert!(normalize(&"x".repeat(MAX_TAG_LEN + 1)).is_err());
+        assert!(normalize(&"x".repeat(MAX_TAG_LEN)).is_ok());
+    }
+
+    #[test]
+    fn applies_removals_before_additions() {
+        let tags = |list: &[&str]| list.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            apply(&tags(&["b", "old"]), &tags(&["a", "b"]), &tags(&["old"])),
+            tags(&["a", "b"])
+        );
+        assert_eq!(
+            apply(&tags(&["x"]), &tags(&["x"]), &tags(&["x"])),
+            tags(&["x"])
+        );
+    }
+
+    #[actix_web::test]
+    async fn tags_matching_files_in_bulk() {
+        let data = state(config());
+        let (photo, notes) = (unique("photo.png"), unique("notes.txt"));
+        let photo_info = add_file(&data, &photo, b"png", None);
+        let notes_info = add_file(&data, &notes, b"text", None);
+        let tag = |body: serde_json::Value| {
+            call(
+                &data,
+                TestRequest::post().uri("/api/files/tag").set_json(body),
+            )
+        };
+
+        let filter = serde_json::json!({"mimePrefix": "image/"});
+        let res =
+            json(tag(serde_json::json!({"filter": filter, "add": ["pics"], "dryRun": true})).await)
+                .await;
+        assert_eq!(res["matched"], 1);
+        assert_eq!(res["changed"], 1);
+        assert!(record(&data, &photo_info.id).unwrap().tags.is_empty());
+
+        let res = json(tag(serde_json::json!({"filter": filter, "add": [" pics "]})).await).await;
+        assert_eq!(res["files"][0]["tags"], serde_json::json!(["pics"]));
+        assert_eq!(record(&data, &photo_info.id).unwrap().tags, ["pics"]);
+        assert!(record(&data, &notes_info.id).unwrap().tags.is_empty());
+
+        let res = json(tag(serde_json::json!({"filter": filter, "add": ["pics"]})).await).await;
+        assert_eq!(res["changed"], 0);
+
+        assert_eq!(tag(serde_json::json!({"add": []})).await.status(), 400);
+        assert_eq!(tag(serde_json::json!({"add": [""]})).await.status(), 400);
+        remove(&photo);
+        remove(&notes);
+    }
+}
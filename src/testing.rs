@@ -0,0 +1,85 @@
+//! Helpers shared by the handler tests.
+//!
+//! Every test gets its own state but they all share `UPLOAD_DIR`, so files
+//! are created under unique names and tests never look at the whole
+//! directory.
+
+use crate::hashing::hash_file;
+use crate::{AppState, Config, FileInfo, UPLOAD_DIR};
+use actix_web::dev::ServiceResponse;
+use actix_web::test::{call_service, init_service, read_body, TestRequest};
+use actix_web::{web, App};
+use chrono::Utc;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+/// The configuration with every setting at its default.
+pub fn config() -> Config {
+    Config::from_env().expect("default configuration")
+}
+
+pub fn state(config: Config) -> web::Data<AppState> {
+    std::fs::create_dir_all(UPLOAD_DIR).unwrap();
+    web::Data::new(AppState::with_files(config, Vec::new()))
+}
+
+/// `name` with a random prefix, so parallel tests never collide.
+pub fn unique(name: &str) -> String {
+    format!("{}-{}", &Uuid::new_v4().simple().to_string()[..12], name)
+}
+
+pub fn path(name: &str) -> PathBuf {
+    PathBuf::from(UPLOAD_DIR).join(name)
+}
+
+/// Stores `contents` as `name` and lists it, as an upload by `owner` would.
+pub fn add_file(data: &AppState, name: &str, contents: &[u8], owner: Option<&str>) -> FileInfo {
+    std::fs::write(path(name), contents).unwrap();
+    let info = FileInfo {
+        id: Uuid::new_v4().to_string(),
+        name: name.to_string(),
+        size: contents.len() as u64,
+        mime_type: crate::mimetypes::guess(&data.config, name),
+        uploaded_at: Utc::now(),
+        checksum: Some(hash_file(&path(name), data.config.hash_algorithm).unwrap()),
+        owner: owner.map(str::to_string),
+        ..Default::default()
+    };
+    data.files.lock().unwrap().insert(0, info.clone());
+    info
+}
+
+/// Deletes what storing `name` left behind.
+pub fn remove(name: &str) {
+    let _ = std::fs::remove_file(path(name));
+    crate::ids::remove(name);
+}
+
+/// The listed record with `id`.
+pub fn record(data: &AppState, id: &str) -> Option<FileInfo> {
+    data.files
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|f| f.id == id)
+        .cloned()
+}
+
+/// Sends `req` to the app's routes, with `data` as their state.
+pub async fn call(data: &web::Data<AppState>, req: TestRequest) -> ServiceResponse {
+    let app = init_service(App::new().app_data(data.clone()).configure(crate::routes)).await;
+    call_service(&app, req.to_request()).await
+}
+
+/// A raw upload of `body` as `name`, from a fixed client address.
+pub fn upload(name: &str, body: &str) -> TestRequest {
+    TestRequest::put()
+        .uri("/api/upload")
+        .peer_addr("1.2.3.4:4000".parse().unwrap())
+        .insert_header(("X-Filename", name))
+        .set_payload(body.to_string())
+}
+
+pub async fn json(res: ServiceResponse) -> serde_json::Value {
+    serde_json::from_slice(&read_body(res).await).unwrap()
+}
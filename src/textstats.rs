@@ -0,0 +1,113 @@
+//! `wc`-style line/word/byte counts for text files.
+
+use crate::{find_file, stored_path, AppState};
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TextStats {
+    /// Newline characters, as `wc -l` counts them.
+    pub lines: u64,
+    /// Runs of non-whitespace separated by ASCII whitespace.
+    pub words: u64,
+    pub bytes: u64,
+    /// Set when counting stopped at `WC_MAX_BYTES` before the end of the file.
+    pub truncated: bool,
+}
+
+/// Whether a MIME type is worth counting. Binary files are skipped.
+pub fn is_text(mime: &str) -> bool {
+    mime.starts_with("text/")
+        || matches!(
+            mime,
+            "application/json"
+                | "application/xml"
+                | "application/javascript"
+                | "application/x-yaml"
+                | "application/toml"
+                | "application/x-sh"
+                | "application/sql"
+        )
+}
+
+/// Counts up to `max_bytes` of the file. Returns `Ok(None)` when a NUL byte
+/// shows the content is binary after all. Blocking.
+fn count(path: &Path, max_bytes: u64) -> std::io::Result<Option<TextStats>> {
+    let mut file = std::fs::File::open(path)?.take(max_bytes);
+    let mut buf = vec![0u8; 64 * 1024];
+    let (mut lines, mut words, mut bytes) = (0u64, 0u64, 0u64);
+    let mut in_word = false;
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        for &b in &buf[..n] {
+            match b {
+                0 => return Ok(None),
+                b'\n' => {
+                    lines += 1;
+                    in_word = false;
+                }
+                b if b.is_ascii_whitespace() => in_word = false,
+                _ => {
+                    if !in_word {
+                        words += 1;
+                        in_word = true;
+                    }
+                }
+            }
+        }
+        bytes += n as u64;
+    }
+    let total = std::fs::metadata(path)?.len();
+    Ok(Some(TextStats {
+        lines,
+        words,
+        bytes,
+        truncated: bytes < total,
+    }))
+}
+
+/// `GET /api/files/{id}/wc`: counts on first request (on a blocking thread)
+/// and caches the result on the file record.
+pub async fn get_text_stats(path: web::Path<String>, data: web::Data<AppState>) -> HttpResponse {
+    let id = path.into_inner();
+    let Some(info) = find_file(&data, &id) else {
+        return HttpResponse::NotFound().json(serde_json::json!({"error": "File not found"}));
+    };
+    if let Some(stats) = info.text_stats {
+        return HttpResponse::Ok().json(stats);
+    }
+    if !is_text(&info.mime_type) {
+        return HttpResponse::UnprocessableEntity()
+            .json(serde_json::json!({"error": "Not a text file"}));
+    }
+    let Some(filepath) = stored_path(&data.config, &info.name) else {
+        return HttpResponse::NotFound().json(serde_json::json!({"error": "File not found"}));
+    };
+
+    let max_bytes = data.config.wc_max_bytes;
+    let stats = match web::block(move || count(&filepath, max_bytes)).await {
+        Ok(Ok(Some(stats))) => stats,
+        Ok(Ok(None)) => {
+            return HttpResponse::UnprocessableEntity()
+                .json(serde_json::json!({"error": "Not a text file"}));
+        }
+        Ok(Err(e)) => {
+            return HttpResponse::InternalServerError()
+                .json(serde_json::json!({"error": format!("Failed to read file: {}", e)}));
+        }
+        Err(_) => {
+            return HttpResponse::InternalServerError()
+                .json(serde_json::json!({"error": "Counting task failed"}));
+        }
+    };
+
+    if let Some(f) = data.files.lock().unwrap().iter_mut().find(|f| f.id == id) {
+        f.text_stats = Some(stats);
+    }
+    HttpResponse::Ok().json(stats)
+}
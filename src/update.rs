@@ -0,0 +1,414 @@
+//! Editing a file's record: `PATCH /api/files/{id}` changes only the fields
+//! it is given, `PUT /api/files/{id}` replaces all of them.
+//!
+//! The editable fields are `name`, `tags` and `description`. Unknown fields
+//! are rejected rather than ignored, so a client never believes a change
+//! went through when it did not. A new name is checked like an upload's, so
+//! `UNSAFE_NAMES` and `EXTENSIONLESS_UPLOADS` apply. A rename moves the
+//! stored file, its precompressed variants and its versions; the id stays
+//! the same.
+
+use crate::{
+    checked_name, ids, listing_order, mimetypes, precompress, sanitize_filename, tags, versions,
+    AppState, MAX_FORM_FIELD, UPLOAD_DIR,
+};
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use serde::{Deserialize, Deserializer};
+use std::fs;
+use std::path::PathBuf;
+
+/// Tells an explicit `null` (`Some(None)`) apart from an absent field
+/// (`None`).
+fn present<'de, D, T>(d: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(d).map(Some)
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PatchRequest {
+    #[serde(default, deserialize_with = "present")]
+    name: Option<Option<String>>,
+    /// `null` removes every tag.
+    #[serde(default, deserialize_with = "present")]
+    tags: Option<Option<TagChange>>,
+    /// `null` clears the description.
+    #[serde(default, deserialize_with = "present")]
+    description: Option<Option<String>>,
+}
+
+/// Either `set` to replace the tags, or `add` and `remove` to change them.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TagChange {
+    set: Option<Vec<String>>,
+    #[serde(default)]
+    add: Vec<String>,
+    #[serde(default)]
+    remove: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PutRequest {
+    name: String,
+    /// Omitted means no tags.
+    #[serde(default)]
+    tags: Vec<String>,
+    /// Omitted means no description.
+    #[serde(default)]
+    description: Option<String>,
+}
+
+enum Tags {
+    Keep,
+    Set(Vec<String>),
+    Change {
+        add: Vec<String>,
+        remove: Vec<String>,
+    },
+}
+
+fn bad_request(error: &str) -> HttpResponse {
+    HttpResponse::BadRequest().json(serde_json::json!({ "error": error }))
+}
+
+fn normalized(tags: &[String]) -> Result<Vec<String>, HttpResponse> {
+    tags.iter().map(|t| tags::normalize(t)).collect()
+}
+
+/// Trimmed like the upload form field, with a blank description meaning
+/// none.
+fn normalized_description(description: Option<String>) -> Result<Option<String>, HttpResponse> {
+    let description = description.map(|d| d.trim().to_string());
+    if description
+        .as_ref()
+        .is_some_and(|d| d.len() > MAX_FORM_FIELD)
+    {
+        return Err(bad_request(&format!(
+            "description must be at most {} bytes long",
+            MAX_FORM_FIELD
+        )));
+    }
+    Ok(description.filter(|d| !d.is_empty()))
+}
+
+/// `PATCH /api/files/{id}`: fields left out keep their value.
+pub async fn patch_file(
+    path: web::Path<String>,
+    body: web::Bytes,
+    data: web::Data<AppState>,
+) -> HttpResponse {
+    let body: PatchRequest = match serde_json::from_slice(&body) {
+        Ok(b) => b,
+        Err(e) => return bad_request(&format!("Invalid request: {}", e)),
+    };
+    let name = match body.name {
+        Some(None) => return bad_request("name cannot be cleared"),
+        Some(Some(name)) => Some(name),
+        None => None,
+    };
+    let tags = match body.tags {
+        None => Tags::Keep,
+        Some(None) => Tags::Set(Vec::new()),
+        Some(Some(change)) => match change.set {
+            Some(_) if !change.add.is_empty() || !change.remove.is_empty() => {
+                return bad_request("Give either tags.set or tags.add/tags.remove, not both");
+            }
+            Some(set) => match normalized(&set) {
+                Ok(set) => Tags::Set(set),
+                Err(res) => return res,
+            },
+            None => match (normalized(&change.add), normalized(&change.remove)) {
+                (Ok(add), Ok(remove)) => Tags::Change { add, remove },
+                (Err(res), _) | (_, Err(res)) => return res,
+            },
+        },
+    };
+    let description = match body.description.map(normalized_description) {
+        None => None,
+        Some(Ok(description)) => Some(description),
+        Some(Err(res)) => return res,
+    };
+    update(
+        &data,
+        &path.into_inner(),
+        name.as_deref(),
+        tags,
+        description,
+    )
+}
+
+/// `PUT /api/files/{id}`: every editable field is set; omitted tags and
+/// description are removed.
+pub async fn put_file(
+    path: web::Path<String>,
+    body: web::Bytes,
+    data: web::Data<AppState>,
+) -> HttpResponse {
+    let body: PutRequest = match serde_json::from_slice(&body) {
+        Ok(b) => b,
+        Err(e) => return bad_request(&format!("Invalid request: {}", e)),
+    };
+    let tags = match normalized(&body.tags) {
+        Ok(tags) => tags,
+        Err(res) => return res,
+    };
+    let description = match normalized_description(body.description) {
+        Ok(description) => description,
+        Err(res) => return res,
+    };
+    update(
+        &data,
+        &path.into_inner(),
+        Some(&body.name),
+        Tags::Set(tags),
+        Some(description),
+    )
+}
+
+/// Applies the changes to the record `id`. `description` is `None` to keep
+/// the current one.
+fn update(
+    data: &AppState,
+    id: &str,
+    requested: Option<&str>,
+    tags: Tags,
+    description: Option<Option<String>>,
+) -> HttpResponse {
+    let mut files = data.files.lock().unwrap();
+    let Some(pos) = files.iter().position(|f| f.id == id && f.is_ready()) else {
+        return HttpResponse::NotFound().json(serde_json::json!({"error": "File not found"}));
+    };
+    let old_name = files[pos].name.clone();
+
+    // Checked like an upload's name, unless it is the current one.
+    let rename = match requested
+        .map(|name| sanitize_filename(&data.config, name))
+        .filter(|name| *name != old_name)
+        .map(|name| checked_name(&data.config, name, id, Some(&files[pos].mime_type)))
+    {
+        Some(Ok(name)) => Some(name).filter(|name| *name != old_name),
+        Some(Err(res)) => return res,
+        None => None,
+    };
+    if let Some(name) = &rename {
+        if let Err(res) = data.storage.require_writable() {
+            return res;
+        }
+        let folded = name.to_lowercase();
+        let same_file = data.fold_case && old_name.to_lowercase() == folded;
+        let taken = files.iter().any(|f| {
+            f.id != id
+                && f.is_ready()
+                && (f.name == *name || data.fold_case && f.name.to_lowercase() == folded)
+        }) || (!same_file
+            && fs::symlink_metadata(PathBuf::from(UPLOAD_DIR).join(name)).is_ok());
+        if taken {
+            return HttpResponse::Conflict().json(serde_json::json!({
+                "error": format!("A file named '{}' already exists", name)
+            }));
+        }
+        if let Err(e) = fs::rename(
+            PathBuf::from(UPLOAD_DIR).join(&old_name),
+            PathBuf::from(UPLOAD_DIR).join(name),
+        ) {
+            return HttpResponse::InternalServerError()
+                .json(serde_json::json!({"error": format!("Failed to rename file: {}", e)}));
+        }
+        precompress::rename(&old_name, name);
+        versions::rename(&old_name, name);
+        ids::rename(&old_name, name);
+    }
+
+    let info = &mut files[pos];
+    let tags = match tags {
+        Tags::Keep => info.tags.clone(),
+        Tags::Set(set) => tags::apply(&[], &set, &[]),
+        Tags::Change { add, remove } => tags::apply(&info.tags, &add, &remove),
+    };
+    let description = description.unwrap_or_else(|| info.description.clone());
+    if rename.is_none() && tags == info.tags && description == info.description {
+        return HttpResponse::Ok().json(serde_json::json!({"success": true, "file": info}));
+    }
+    info.tags = tags;
+    info.description = description;
+    info.modified_at = Some(Utc::now());
+    let Some(name) = rename else {
+        return HttpResponse::Ok().json(serde_json::json!({"success": true, "file": info}));
+    };
+    info.mime_type = mimetypes::guess(&data.config, &name);
+    info.name = name;
+
+    // Names break ties in the listing order.
+    let info = files.remove(pos);
+    let pos = files.partition_point(|f| listing_order(f, &info).is_lt());
+    files.insert(pos, info.clone());
+    HttpResponse::Ok().json(serde_json::json!({"success": true, "file": info}))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, ExtensionlessPolicy, UnsafeNamePolicy};
+    use crate::testing::{add_file, config, path, record, state, unique};
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::App;
+
+    async fn send(
+        data: &web::Data<AppState>,
+        req: TestRequest,
+        body: serde_json::Value,
+    ) -> (u16, serde_json::Value) {
+        let app = init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/api/files/{id}", web::patch().to(patch_file))
+                .route("/api/files/{id}", web::put().to(put_file)),
+        )
+        .await;
+        let res = call_service(&app, req.set_payload(body.to_string()).to_request()).await;
+        let status = res.status().as_u16();
+        (
+            status,
+            serde_json::from_slice(&read_body(res).await).unwrap(),
+        )
+    }
+
+    #[actix_web::test]
+    async fn patch_renames_and_retags() {
+        let data = state(config());
+        let (name, renamed) = (unique("a.txt"), unique("b.txt"));
+        let info = add_file(&data, &name, b"abc", None);
+        let uri = format!("/api/files/{}", info.id);
+
+        let body = serde_json::json!({"name": renamed, "tags": {"add": ["x", "a"]}});
+        let (status, res) = send(&data, TestRequest::patch().uri(&uri), body).await;
+        assert_eq!(status, 200);
+        assert_eq!(res["file"]["name"], renamed);
+        assert_eq!(res["file"]["tags"], serde_json::json!(["a", "x"]));
+        assert!(!path(&name).exists() && path(&renamed).exists());
+
+        let body = serde_json::json!({"tags": {"remove": ["x"]}});
+        let (_, res) = send(&data, TestRequest::patch().uri(&uri), body).await;
+        assert_eq!(res["file"]["tags"], serde_json::json!(["a"]));
+        assert_eq!(record(&data, &info.id).unwrap().name, renamed);
+
+        let body = serde_json::json!({"description": " Notes "});
+        let (_, res) = send(&data, TestRequest::patch().uri(&uri), body).await;
+        assert_eq!(res["file"]["description"], "Notes");
+        let body = serde_json::json!({"tags": {"add": ["y"]}});
+        let (_, res) = send(&data, TestRequest::patch().uri(&uri), body).await;
+        assert_eq!(res["file"]["description"], "Notes");
+        let body = serde_json::json!({"description": null});
+        let (_, res) = send(&data, TestRequest::patch().uri(&uri), body).await;
+        assert!(res["file"]["description"].is_null());
+        assert_eq!(record(&data, &info.id).unwrap().description, None);
+
+        fs::remove_file(path(&renamed)).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn put_replaces_every_field() {
+        let data = state(config());
+        let name = unique("a.txt");
+        let info = add_file(&data, &name, b"abc", None);
+        let uri = format!("/api/files/{}", info.id);
+
+        let body = serde_json::json!({"name": name, "tags": ["t"], "description": "d"});
+        let (_, res) = send(&data, TestRequest::put().uri(&uri), body).await;
+        assert_eq!(res["file"]["tags"], serde_json::json!(["t"]));
+        assert_eq!(res["file"]["description"], "d");
+        let body = serde_json::json!({"name": name});
+        let (status, res) = send(&data, TestRequest::put().uri(&uri), body).await;
+        assert_eq!(status, 200);
+        assert!(res["file"].get("tags").is_none());
+        assert!(res["file"]["description"].is_null());
+        assert_eq!(record(&data, &info.id).unwrap().description, None);
+
+        fs::remove_file(path(&name)).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn bad_requests_change_nothing() {
+        let data = state(config());
+        let (name, other) = (unique("a.txt"), unique("b.txt"));
+        let info = add_file(&data, &name, b"abc", None);
+        add_file(&data, &other, b"def", None);
+        let uri = format!("/api/files/{}", info.id);
+
+        for (body, expected) in [
+            (serde_json::json!({"size": 1}), 400),
+            (serde_json::json!({"name": null}), 400),
+            (
+                serde_json::json!({"tags": {"set": ["a"], "add": ["b"]}}),
+                400,
+            ),
+            (serde_json::json!({"tags": {"set": [""]}}), 400),
+            (
+                serde_json::json!({"description": "x".repeat(MAX_FORM_FIELD + 1)}),
+                400,
+            ),
+            (serde_json::json!({"name": other}), 409),
+        ] {
+            let (status, _) = send(&data, TestRequest::patch().uri(&uri), body).await;
+            assert_eq!(status, expected);
+        }
+        let (status, _) = send(
+            &data,
+            TestRequest::patch().uri("/api/files/missing"),
+            serde_json::json!({}),
+        )
+        .await;
+        assert_eq!(status, 404);
+        assert_eq!(record(&data, &info.id).unwrap().name, name);
+
+        fs::remove_file(path(&name)).unwrap();
+        fs::remove_file(path(&other)).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn renames_follow_the_upload_name_policies() {
+        let data = state(Config {
+            unsafe_names: UnsafeNamePolicy::Reject,
+            extensionless_uploads: ExtensionlessPolicy::Reject,
+            ..config()
+        });
+        let name = unique("a.txt");
+        let info = add_file(&data, &name, b"abc", None);
+        let uri = format!("/api/files/{}", info.id);
+
+        let body = serde_json::json!({"name": "CON.txt"});
+        let (status, _) = send(&data, TestRequest::patch().uri(&uri), body).await;
+        assert_eq!(status, 400);
+        let body = serde_json::json!({"name": unique("noext")});
+        let (status, _) = send(&data, TestRequest::patch().uri(&uri), body).await;
+        assert_eq!(status, 422);
+        assert!(path(&name).exists());
+
+        fs::remove_file(path(&name)).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn extensionless_renames_can_derive_an_extension() {
+        let data = state(Config {
+            extensionless_uploads: ExtensionlessPolicy::Derive,
+            ..config()
+        });
+        let name = unique("a.txt");
+        let info = add_file(&data, &name, b"abc", None);
+        let renamed = unique("notes");
+
+        let body = serde_json::json!({"name": renamed});
+        let uri = format!("/api/files/{}", info.id);
+        let (status, res) = send(&data, TestRequest::patch().uri(&uri), body).await;
+        assert_eq!(status, 200);
+        assert_eq!(res["file"]["name"], format!("{}.txt", renamed));
+
+        fs::remove_file(path(&format!("{}.txt", renamed))).unwrap();
+    }
+}
@@ -0,0 +1,113 @@
+//! Checksum verification of downloads (`VERIFY_DOWNLOADS`).
+//!
+//! Whole-file downloads of files with a checksum are hashed as they are
+//! served. Files up to [`CHECK_FIRST_MAX`] are hashed before the response
+//! starts, so a mismatch is a clean `500`; range requests for them are
+//! checked the same way. Ranges of larger files are not checked, since that
+//! would mean reading the whole file; they are refused only once the file
+//! has been flagged. Larger ones are hashed while they
+//! stream, and the last chunk is only sent once the digest matches; on a
+//! mismatch the connection is closed short of `Content-Length`, which
+//! clients treat as a failed transfer. Either way the file is flagged like a
+//! scrub failure (see `scrub.rs`) and refused from then on, including range
+//! requests and precompressed variants, which cannot be checked themselves.
+//! A variant is only served once the original has been checked, so larger
+//! files are sent uncompressed while verification is on.
+//!
+//! The cost is one extra read of small files before their first byte, and
+//! hashing on the download path of large ones.
+
+use crate::config::VerifyDownloads;
+use crate::hashing::{hash_file, Checksum, Hasher};
+use crate::{scrub, AppState};
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use futures_util::{Stream, StreamExt};
+use std::io;
+use std::path::PathBuf;
+
+/// Largest file hashed before its download starts.
+pub const CHECK_FIRST_MAX: u64 = 4 * 1024 * 1024;
+
+pub fn integrity_failed() -> HttpResponse {
+    HttpResponse::InternalServerError().json(serde_json::json!({
+        "error": "Integrity check failed: the stored file does not match its checksum"
+    }))
+}
+
+/// Records the outcome for the file `id`, unless it was replaced meanwhile.
+fn record(data: &AppState, id: &str, expected: &Checksum, actual: Checksum) {
+    let mut files = data.files.lock().unwrap();
+    let Some(pos) = files
+        .iter()
+        .position(|f| f.id == id && f.checksum.as_ref() == Some(expected))
+    else {
+        return;
+    };
+    if actual == *expected {
+        files[pos].last_verified = Some(Utc::now());
+        return;
+    }
+    let quarantine = data.config.verify_downloads == VerifyDownloads::Quarantine;
+    scrub::record_mismatch(data, files, pos, expected.clone(), actual, quarantine);
+}
+
+/// Hashes a small file before it is served.
+pub async fn check(
+    data: &AppState,
+    id: &str,
+    path: PathBuf,
+    expected: &Checksum,
+) -> Result<(), HttpResponse> {
+    let algorithm = expected.algorithm;
+    let Ok(Ok(actual)) = web::block(move || hash_file(&path, algorithm)).await else {
+        // Unreadable files fail while streaming instead.
+        return Ok(());
+    };
+    let matches = actual == *expected;
+    record(data, id, expected, actual);
+    if matches {
+        Ok(())
+    } else {
+        Err(integrity_failed())
+    }
+}
+
+/// Hashes `body`, the whole `size` bytes of the file `id`, as it passes
+/// through, holding back its last chunk until the digest is known.
+pub fn stream<S>(
+    body: S,
+    data: web::Data<AppState>,
+    id: String,
+    expected: Checksum,
+    size: u64,
+) -> impl Stream<Item = io::Result<web::Bytes>>
+where
+    S: Stream<Item = io::Result<web::Bytes>>,
+{
+    let mut hasher = Some(Hasher::new(expected.algorithm));
+    let mut seen = 0;
+    body.map(move |chunk| {
+        let chunk = chunk?;
+        let Some(mut h) = hasher.take() else {
+            return Ok(chunk);
+        };
+        h.update(&chunk);
+        seen += chunk.len() as u64;
+        if seen < size {
+            hasher = Some(h);
+            return Ok(chunk);
+        }
+        let actual = h.finalize();
+        let matches = actual == expected;
+        record(&data, &id, &expected, actual);
+        if matches {
+            Ok(chunk)
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "integrity check failed",
+            ))
+        }
+    })
+}
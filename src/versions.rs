@@ -0,0 +1,318 @@
+//! Version history of replaced files (`MAX_VERSIONS`).
+//!
+//! When an upload replaces a file (`If-Match`), the old contents are moved
+//! to `uploads/.versions/<name>/<n>` instead of being overwritten, and the
+//! record lists them under `versions`, oldest first. Beyond `MAX_VERSIONS`
+//! the oldest is pruned. Restoring a version copies it back over the file,
+//! which archives the current contents in turn, so a restore can be undone.
+//! Appends modify a file in place and do not create versions.
+//!
+//! Records are not persisted, so after a restart versions are recovered
+//! from the directory with their size and time but without checksums.
+
+use crate::hashing::{hash_file, Checksum};
+use crate::{
+    image_dimensions, part_path, precompress, stored_path, AppState, Config, FileInfo, UPLOAD_DIR,
+};
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+const VERSION_DIR: &str = ".versions";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Version {
+    /// Numbered from 1, never reused for a file.
+    pub version: u32,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<Checksum>,
+    /// When these contents were written: uploaded, appended to or restored.
+    #[serde(alias = "createdAt")]
+    pub created_at: DateTime<Utc>,
+}
+
+fn dir(name: &str) -> PathBuf {
+    PathBuf::from(UPLOAD_DIR).join(VERSION_DIR).join(name)
+}
+
+fn version_path(name: &str, version: u32) -> PathBuf {
+    dir(name).join(version.to_string())
+}
+
+/// Moves the current contents of `record`'s file into its history, just
+/// before the file is overwritten. Returns the history including the new
+/// entry, unpruned. Call with the files lock held.
+pub fn archive(config: &Config, record: &FileInfo) -> io::Result<Vec<Version>> {
+    let mut versions = record.versions.clone();
+    if config.max_versions == 0 {
+        return Ok(versions);
+    }
+    let next = versions.last().map_or(1, |v| v.version + 1);
+    fs::create_dir_all(dir(&record.name))?;
+    fs::rename(
+        PathBuf::from(UPLOAD_DIR).join(&record.name),
+        version_path(&record.name, next),
+    )?;
+    versions.push(Version {
+        version: next,
+        size: record.size,
+        checksum: record.checksum.clone(),
+        created_at: record
+            .modified_at
+            .map_or(record.uploaded_at, |m| m.max(record.uploaded_at)),
+    });
+    Ok(versions)
+}
+
+/// Puts back the contents `archive` just moved away, when writing the new
+/// contents failed.
+pub fn unarchive(config: &Config, name: &str, versions: &[Version]) {
+    if config.max_versions == 0 {
+        return;
+    }
+    if let Some(last) = versions.last() {
+        let _ = fs::rename(
+            version_path(name, last.version),
+            PathBuf::from(UPLOAD_DIR).join(name),
+        );
+    }
+}
+
+/// Drops the oldest versions beyond `MAX_VERSIONS`.
+pub fn prune(config: &Config, name: &str, versions: &mut Vec<Version>) {
+    let excess = versions.len().saturating_sub(config.max_versions);
+    for old in versions.drain(..excess) {
+        let _ = fs::remove_file(version_path(name, old.version));
+    }
+}
+
+/// Removes the whole history of a deleted file.
+pub fn remove_all(name: &str) {
+    let _ = fs::remove_dir_all(dir(name));
+}
+
+/// Moves the history of a renamed file along with it.
+pub fn rename(from: &str, to: &str) {
+    let _ = fs::rename(dir(from), dir(to));
+}
+
+/// Moves the history of a quarantined file to `target`, so it goes with
+/// the file and not to the next upload of the same name.
+pub fn move_to(name: &str, target: &Path) -> io::Result<()> {
+    match fs::rename(dir(name), target) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Recovers the history of a file found at startup. Blocking.
+pub fn scan(name: &str) -> Vec<Version> {
+    let mut versions: Vec<Version> = fs::read_dir(dir(name))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let version = entry.file_name().to_str()?.parse().ok()?;
+            let meta = entry.metadata().ok().filter(|m| m.is_file())?;
+            Some(Version {
+                version,
+                size: meta.len(),
+                checksum: None,
+                created_at: meta.modified().ok()?.into(),
+            })
+        })
+        .collect();
+    versions.sort_by_key(|v| v.version);
+    versions
+}
+
+fn not_found(what: &str) -> HttpResponse {
+    HttpResponse::NotFound().json(serde_json::json!({"error": format!("{} not found", what)}))
+}
+
+/// `GET /api/files/{id}/versions`: the file's previous versions, oldest
+/// first.
+pub async fn list_versions(path: web::Path<String>, data: web::Data<AppState>) -> HttpResponse {
+    let id = path.into_inner();
+    let files = data.files.lock().unwrap();
+    let Some(info) = files.iter().find(|f| f.id == id) else {
+        return not_found("File");
+    };
+    HttpResponse::Ok().json(serde_json::json!({
+        "id": info.id,
+        "max_versions": data.config.max_versions,
+        "versions": info.versions,
+    }))
+}
+
+/// `POST /api/files/{id}/versions/{version}/restore`: makes a previous
+/// version the current contents again.
+pub async fn restore_version(
+    path: web::Path<(String, u32)>,
+    data: web::Data<AppState>,
+) -> HttpResponse {
+    let (id, number) = path.into_inner();
+    if let Err(res) = data.storage.require_writable() {
+        return res;
+    }
+    let Some((name, version)) = data
+        .files
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|f| f.id == id)
+        .map(|f| {
+            let version = f.versions.iter().find(|v| v.version == number).cloned();
+            (f.name.clone(), version)
+        })
+    else {
+        return not_found("File");
+    };
+    let Some(version) = version else {
+        return not_found("Version");
+    };
+    if stored_path(&data.config, &name).is_none() {
+        return not_found("File");
+    }
+
+    // Copy outside the lock; versions are never modified in place.
+    let part = part_path(&Uuid::new_v4().to_string());
+    let (source, target) = (version_path(&name, version.version), part.clone());
+    let algorithm = version
+        .checksum
+        .as_ref()
+        .map_or(data.config.hash_algorithm, |c| c.algorithm);
+    let copied = web::block(move || {
+        fs::copy(&source, &target)?;
+        hash_file(&target, algorithm)
+    })
+    .await;
+    let checksum = match copied {
+        Ok(Ok(checksum)) => checksum,
+        Ok(Err(e)) => {
+            let _ = fs::remove_file(&part);
+            return HttpResponse::InternalServerError()
+                .json(serde_json::json!({"error": format!("Failed to restore version: {}", e)}));
+        }
+        Err(_) => {
+            let _ = fs::remove_file(&part);
+            return HttpResponse::InternalServerError()
+                .json(serde_json::json!({"error": "Restore task failed"}));
+        }
+    };
+    if version.checksum.as_ref().is_some_and(|c| *c != checksum) {
+        let _ = fs::remove_file(&part);
+        return HttpResponse::InternalServerError()
+            .json(serde_json::json!({"error": "Stored version does not match its checksum"}));
+    }
+
+    let mut files = data.files.lock().unwrap();
+    // Replaced, deleted or pruned meanwhile.
+    let Some(record) = files
+        .iter_mut()
+        .find(|f| f.id == id && f.name == name && f.versions.contains(&version))
+    else {
+        let _ = fs::remove_file(&part);
+        return HttpResponse::Conflict()
+            .json(serde_json::json!({"error": "The file changed during the restore"}));
+    };
+    let mut versions = match archive(&data.config, record) {
+        Ok(versions) => versions,
+        Err(e) => {
+            let _ = fs::remove_file(&part);
+            return HttpResponse::InternalServerError().json(
+                serde_json::json!({"error": format!("Failed to keep current version: {}", e)}),
+            );
+        }
+    };
+    let filepath = PathBuf::from(UPLOAD_DIR).join(&name);
+    if let Err(e) = fs::rename(&part, &filepath) {
+        unarchive(&data.config, &name, &versions);
+        let _ = fs::remove_file(&part);
+        return HttpResponse::InternalServerError()
+            .json(serde_json::json!({"error": format!("Failed to restore version: {}", e)}));
+    }
+    prune(&data.config, &name, &mut versions);
+    precompress::remove(&name);
+
+    let (width, height) = image_dimensions(&data.config, &filepath, &record.mime_type);
+    record.versions = versions;
+    record.size = version.size;
+    record.checksum = Some(checksum);
+    record.modified_at = Some(Utc::now());
+    record.width = width;
+    record.height = height;
+    record.variant_bytes = None;
+    record.text_stats = None;
+    record.last_verified = None;
+    record.checksum_mismatch = None;
+    HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "restored": version.version,
+        "file": record,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{call, config, json, path, remove, state, unique, upload};
+    use actix_web::http::header;
+    use actix_web::test::TestRequest;
+
+    #[actix_web::test]
+    async fn keeps_restores_and_prunes_versions() {
+        let data = state(Config {
+            max_versions: 2,
+            ..config()
+        });
+        let name = unique("doc.txt");
+        let res = json(call(&data, upload(&name, "one")).await).await;
+        let id = res["files"][0]["id"].as_str().unwrap().to_string();
+        for contents in ["two", "three", "four"] {
+            let req = upload(&name, contents).insert_header((header::IF_MATCH, "*"));
+            assert_eq!(call(&data, req).await.status(), 200);
+        }
+
+        let list = TestRequest::get().uri(&format!("/api/files/{}/versions", id));
+        let res = json(call(&data, list).await).await;
+        let numbers: Vec<u64> = res["versions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| v["version"].as_u64().unwrap())
+            .collect();
+        assert_eq!(numbers, [2, 3]);
+        assert!(!version_path(&name, 1).exists());
+
+        let restore = |n: u32| {
+            let uri = format!("/api/files/{}/versions/{}/restore", id, n);
+            call(&data, TestRequest::post().uri(&uri))
+        };
+        let res = json(restore(2).await).await;
+        assert_eq!(res["restored"], 2);
+        assert_eq!(fs::read(path(&name)).unwrap(), b"two");
+        assert_eq!(fs::read(version_path(&name, 4)).unwrap(), b"four");
+        let recovered: Vec<u32> = scan(&name).iter().map(|v| v.version).collect();
+        assert_eq!(recovered, [3, 4]);
+
+        assert_eq!(restore(1).await.status(), 404);
+        remove_all(&name);
+        remove(&name);
+    }
+
+    #[test]
+    fn nothing_is_archived_without_max_versions() {
+        let record = FileInfo {
+            name: unique("never-archived.txt"),
+            ..Default::default()
+        };
+        assert!(archive(&config(), &record).unwrap().is_empty());
+        assert!(!dir(&record.name).exists());
+    }
+}
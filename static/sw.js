@@ -0,0 +1,53 @@
+// Service worker for the FileDrop UI (PWA=1). Every URL is relative to this
+// script, so the UI keeps working when mounted under a path prefix.
+var CACHE = 'filedrop-__VERSION__';
+var SHELL = ['./', './manifest.json', './icon.svg'];
+
+self.addEventListener('install', function (event) {
+    event.waitUntil(caches.open(CACHE).then(function (cache) {
+        return cache.addAll(SHELL);
+    }).then(function () {
+        return self.skipWaiting();
+    }));
+});
+
+self.addEventListener('activate', function (event) {
+    event.waitUntil(caches.keys().then(function (keys) {
+        return Promise.all(keys.filter(function (k) {
+            return k.indexOf('filedrop-') === 0 && k !== CACHE;
+        }).map(function (k) {
+            return caches.delete(k);
+        }));
+    }).then(function () {
+        return self.clients.claim();
+    }));
+});
+
+// Network first, so the cache only answers while offline.
+function networkFirst(request) {
+    return fetch(request).then(function (res) {
+        if (res.ok) {
+            var copy = res.clone();
+            caches.open(CACHE).then(function (cache) { cache.put(request, copy); });
+        }
+        return res;
+    }).catch(function () {
+        return caches.match(request).then(function (cached) {
+            return cached || Response.error();
+        });
+    });
+}
+
+self.addEventListener('fetch', function (event) {
+    var request = event.request;
+    if (request.method !== 'GET') return;
+    var url = new URL(request.url);
+    if (url.origin !== self.location.origin) return;
+    var shell = SHELL.some(function (p) {
+        return new URL(p, self.registration.scope).pathname === url.pathname;
+    });
+    // File contents are never cached; only the UI shell and the listing.
+    if (request.mode === 'navigate' || shell || /\/api\/files$/.test(url.pathname)) {
+        event.respondWith(networkFirst(request));
+    }
+});